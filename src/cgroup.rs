@@ -0,0 +1,115 @@
+//! Finding which container each overlay filesystem belongs to, for
+//! `--cgroup-aware`.
+//!
+//! A running container's processes have an overlay mount as their root, and
+//! the layer directory holding its `upperdir` (under
+//! `/var/lib/docker/overlay2/`, say) is where the container's own writes go.
+//! The container is named after the last component of those processes'
+//! cgroup, less the `docker-`, `libpod-` or `crio-` prefix and `.scope` suffix
+//! that systemd adds.
+
+#[cfg(any(test, all(target_os = "linux", feature = "cgroup")))]
+use std::path::Path;
+use std::path::PathBuf;
+
+/// `(layer directory, container)` for every container with a process
+/// running, each directory listed once
+#[cfg(all(target_os = "linux", feature = "cgroup"))]
+pub fn container_layers() -> std::io::Result<Vec<(PathBuf, String)>> {
+    let mut layers: Vec<(PathBuf, String)> = Vec::new();
+    for entry in std::fs::read_dir("/proc")? {
+        let proc_dir = entry?.path();
+        let is_pid = proc_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()));
+        if !is_pid {
+            continue;
+        }
+        // processes come and go, and others' can't always be read
+        let (Ok(cgroup), Ok(mountinfo)) = (
+            std::fs::read_to_string(proc_dir.join("cgroup")),
+            std::fs::read_to_string(proc_dir.join("mountinfo")),
+        ) else {
+            continue;
+        };
+        let (Some(name), Some(layer)) = (container_name(&cgroup), overlay_layer(&mountinfo)) else {
+            continue;
+        };
+        if !layers.iter().any(|(known, _)| *known == layer) {
+            layers.push((layer, name));
+        }
+    }
+    Ok(layers)
+}
+
+/// The container named by a `/proc/<pid>/cgroup`, preferring the cgroup v2
+/// hierarchy; processes in the root cgroup aren't in a container
+#[cfg(any(test, all(target_os = "linux", feature = "cgroup")))]
+fn container_name(cgroup: &str) -> Option<String> {
+    let path = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .or_else(|| cgroup.lines().find_map(|line| line.splitn(3, ':').nth(2)))?;
+    let name = Path::new(path).file_name()?.to_str()?;
+    let name = name.strip_suffix(".scope").unwrap_or(name);
+    let name = ["docker-", "libpod-", "crio-"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name);
+    Some(name.to_owned())
+}
+
+/// The layer directory of the overlay mounted at `/` in a
+/// `/proc/<pid>/mountinfo`: the parent of its `upperdir`
+#[cfg(any(test, all(target_os = "linux", feature = "cgroup")))]
+fn overlay_layer(mountinfo: &str) -> Option<PathBuf> {
+    mountinfo.lines().find_map(|line| {
+        let mut fields = line.split(' ');
+        if fields.nth(4)? != "/" {
+            return None;
+        }
+        let mut fields = fields.skip_while(|&field| field != "-").skip(1);
+        if fields.next()? != "overlay" {
+            return None;
+        }
+        let upper = fields
+            .nth(1)?
+            .split(',')
+            .find_map(|option| option.strip_prefix("upperdir="))?;
+        Path::new(upper).parent().map(Path::to_owned)
+    })
+}
+
+#[cfg(not(all(target_os = "linux", feature = "cgroup")))]
+pub fn container_layers() -> std::io::Result<Vec<(PathBuf, String)>> {
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_name() {
+        let cgroup = "0::/system.slice/docker-4f1e2a.scope\n";
+        assert_eq!(container_name(cgroup).as_deref(), Some("4f1e2a"));
+        let v1 = "12:pids:/docker/4f1e2a\n11:memory:/docker/4f1e2a\n";
+        assert_eq!(container_name(v1).as_deref(), Some("4f1e2a"));
+        assert_eq!(container_name("0::/\n"), None);
+    }
+
+    #[test]
+    fn test_overlay_layer() {
+        let mountinfo = "\
+812 700 0:52 / / rw,relatime - overlay overlay rw,lowerdir=/var/lib/docker/overlay2/l/AB:/var/lib/docker/overlay2/l/CD,upperdir=/var/lib/docker/overlay2/9c1d/diff,workdir=/var/lib/docker/overlay2/9c1d/work
+813 812 0:55 / /proc rw,nosuid - proc proc rw
+";
+        assert_eq!(
+            overlay_layer(mountinfo),
+            Some(PathBuf::from("/var/lib/docker/overlay2/9c1d"))
+        );
+        let host = "22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n";
+        assert_eq!(overlay_layer(host), None);
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::io::{BufReader, Write};
+
+use du_by_user::id::UserId;
+use du_by_user::size::{SizeFormatter, SizeMode};
+use itertools::Itertools;
+
+use crate::output::{Column, OutputFormat, PathPrefix, Table, Value, Writer, SCHEMA_VERSION};
+use crate::snapshot;
+
+/// How `--compare` prints its results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    Human,
+    Json,
+    Csv,
+}
+
+impl DiffFormat {
+    pub const NAMES: [&'static str; 3] = ["human", "json", "csv"];
+
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        match matches.value_of("diff-format") {
+            Some("json") => Self::Json,
+            Some("csv") => Self::Csv,
+            _ => Self::Human,
+        }
+    }
+
+    fn output_format(self) -> OutputFormat {
+        match self {
+            Self::Human => OutputFormat::Table,
+            Self::Json => OutputFormat::Json,
+            Self::Csv => OutputFormat::Csv,
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct SavedRow {
+    user: String,
+    uid: Option<u32>,
+    bytes: u64,
+}
+
+/// Total bytes (and the UID, if recorded) per user name in a saved report.
+///
+/// Reports are matched up by name rather than UID, since the same person can
+/// have different UIDs on different machines. Both `json` and `jsonlines`
+/// output are accepted (as are the bare arrays that `json` output used to
+/// be), and multi-path reports are added together.
+fn load(path: &std::path::Path) -> std::io::Result<HashMap<String, (Option<u32>, u64)>> {
+    let reader = BufReader::new(std::fs::File::open(path)?);
+    let mut usage: HashMap<String, (Option<u32>, u64)> = HashMap::new();
+    for value in serde_json::Deserializer::from_reader(reader).into_iter::<serde_json::Value>() {
+        let rows = match value? {
+            serde_json::Value::Object(mut report) if report.contains_key("schema_version") => {
+                let version = report["schema_version"].as_u64();
+                if version != Some(SCHEMA_VERSION) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "{} has schema_version {}, but only version {} can be read",
+                            path.display(),
+                            report["schema_version"],
+                            SCHEMA_VERSION
+                        ),
+                    ));
+                }
+                match report.remove("users") {
+                    Some(serde_json::Value::Array(rows)) => rows,
+                    _ => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("{} is not a per-user report", path.display()),
+                        ))
+                    }
+                }
+            }
+            serde_json::Value::Array(rows) => rows,
+            row => vec![row],
+        };
+        for row in rows {
+            let row: SavedRow = serde_json::from_value(row)?;
+            let entry = usage.entry(row.user).or_default();
+            entry.0 = entry.0.or(row.uid);
+            entry.1 += row.bytes;
+        }
+    }
+    Ok(usage)
+}
+
+/// Print the per-user change between two saved reports; users whose usage is
+/// the same in both are left out unless `unchanged` is set
+pub fn compare<W: Write>(
+    out: &mut W,
+    before: &std::path::Path,
+    after: &std::path::Path,
+    format: DiffFormat,
+    formatter: &SizeFormatter,
+    unchanged: bool,
+) -> std::io::Result<()> {
+    let before = load(before)?;
+    let mut after = load(after)?;
+    let mut users = before
+        .into_iter()
+        .map(|(user, (uid, bytes))| {
+            let (new_uid, new_bytes) = after.remove(&user).unwrap_or((None, 0));
+            (user, new_uid.or(uid), bytes, new_bytes)
+        })
+        .collect::<Vec<_>>();
+    users.extend(
+        after
+            .into_iter()
+            .map(|(user, (uid, bytes))| (user, uid, 0, bytes)),
+    );
+    let rows = users
+        .into_iter()
+        .filter(|&(_, _, before, after)| unchanged || before != after)
+        .sorted_by_key(|&(_, _, before, after)| after as i64 - before as i64)
+        .map(|(user, uid, before, after)| {
+            let delta = after as i64 - before as i64;
+            vec![
+                Value::Text(user),
+                uid.map_or(Value::Missing, Value::Id),
+                Value::Size(before),
+                Value::Size(after),
+                Value::SizeDelta(delta),
+                match before {
+                    0 => Value::Missing,
+                    before => Value::Percent(delta as f64 * 100.0 / before as f64),
+                },
+            ]
+        })
+        .collect();
+    let table = Table {
+        columns: vec![
+            Column::new("user", "user"),
+            Column::new("uid", "uid"),
+            Column::new("before", "before_bytes"),
+            Column::new("after", "after_bytes"),
+            Column::new("delta", "delta_bytes"),
+            Column::new("change", "delta_percent"),
+        ],
+        rows,
+        alerts: Vec::new(),
+    };
+    writer(format, formatter).write(out, &table)
+}
+
+/// A writer for `format`, with none of the scan-specific settings
+fn writer(format: DiffFormat, formatter: &SizeFormatter) -> Writer<'_> {
+    Writer {
+        format: format.output_format(),
+        formatter,
+        scan_root: std::path::Path::new(""),
+        width: None,
+        measurement: "du_by_user",
+        timestamp: std::time::SystemTime::now(),
+        color: false,
+        sql_table: "",
+        sql_create_table: false,
+        comment_header: false,
+        ascii: false,
+        path_prefix: PathPrefix::default(),
+    }
+}
+
+/// Print every inode whose owner differs between two `--snapshot` files.
+/// Inodes in only one of them were created or deleted rather than given
+/// away, so they're left out; an inode number reused by a new file in
+/// between looks like a change of owner.
+pub fn owner_changes<W: Write>(
+    out: &mut W,
+    before: &std::path::Path,
+    after: &std::path::Path,
+    format: DiffFormat,
+    name: impl Fn(UserId) -> String,
+) -> std::io::Result<()> {
+    let before = snapshot::load_inodes(before)?;
+    let after = snapshot::load_inodes(after)?;
+    let rows = before
+        .into_iter()
+        .filter_map(|(inode, old)| match after.get(&inode) {
+            Some(&new) if new != old => Some((old, new, inode)),
+            _ => None,
+        })
+        .sorted()
+        .map(|(old, new, (dev, ino))| {
+            vec![
+                Value::Count(dev),
+                Value::Count(ino),
+                Value::Text(name(old)),
+                Value::Text(name(new)),
+                Value::Id(old.0),
+                Value::Id(new.0),
+            ]
+        })
+        .collect();
+    let table = Table {
+        columns: vec![
+            Column::new("device", "device"),
+            Column::new("inode", "inode"),
+            Column::new("before", "before_user"),
+            Column::new("after", "after_user"),
+            Column::new("before_uid", "before_uid"),
+            Column::new("after_uid", "after_uid"),
+        ],
+        rows,
+        alerts: Vec::new(),
+    };
+    // nothing here is a size; this is only there for the writer
+    let formatter = SizeFormatter::new(SizeMode::Bytes, false);
+    writer(format, &formatter).write(out, &table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, DiffFormat};
+    use du_by_user::size::{SizeFormatter, SizeMode};
+
+    #[test]
+    fn test_compare_json() {
+        let dir = std::env::temp_dir().join(format!("du-by-user-compare-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (before, after) = (dir.join("before.json"), dir.join("after.json"));
+        std::fs::write(
+            &before,
+            concat!(
+                r#"{"schema_version":1,"users":[{"user":"alice","uid":1000,"bytes":100}]}"#,
+                r#"[{"user":"alice","uid":1000,"bytes":100}]"#,
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            &after,
+            "{\"user\":\"alice\",\"uid\":1001,\"bytes\":300}\n{\"user\":\"bob\",\"uid\":1002,\"bytes\":5}\n",
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        let formatter = SizeFormatter::new(SizeMode::Bytes, false);
+        compare(
+            &mut out,
+            &before,
+            &after,
+            DiffFormat::Json,
+            &formatter,
+            false,
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                r#"[{"user":"bob","uid":1002,"before_bytes":0,"after_bytes":5,"delta_bytes":5,"delta_percent":null},"#,
+                r#"{"user":"alice","uid":1001,"before_bytes":200,"after_bytes":300,"delta_bytes":100,"delta_percent":50.0}]"#,
+                "\n"
+            )
+        );
+    }
+}
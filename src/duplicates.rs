@@ -0,0 +1,152 @@
+//! Finding files with the same content owned by different users, for
+//! `--report-duplicate-content`.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use du_by_user::id::UserId;
+use itertools::Itertools;
+
+/// How file contents are compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentHash {
+    Sha256,
+    /// Faster, but files can be made to collide on purpose
+    Md5,
+}
+
+impl ContentHash {
+    pub const NAMES: [&'static str; 2] = ["sha256", "md5"];
+
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        match matches.value_of("duplicate-hash") {
+            Some("md5") => Self::Md5,
+            _ => Self::Sha256,
+        }
+    }
+
+    fn digest(self, path: &Path) -> std::io::Result<Vec<u8>> {
+        use sha2::Digest;
+
+        fn feed<D: Digest>(mut hasher: D, path: &Path) -> std::io::Result<Vec<u8>> {
+            let mut file = std::fs::File::open(path)?;
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                match file.read(&mut buffer)? {
+                    0 => return Ok(hasher.finalize().to_vec()),
+                    read => hasher.update(&buffer[..read]),
+                }
+            }
+        }
+
+        match self {
+            Self::Sha256 => feed(sha2::Sha256::new(), path),
+            Self::Md5 => feed(md5::Md5::new(), path),
+        }
+    }
+}
+
+/// Files with identical content, owned by more than one user
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DuplicateGroup {
+    pub size: u64,
+    pub files: Vec<(UserId, PathBuf)>,
+}
+
+impl DuplicateGroup {
+    fn owners(&self) -> usize {
+        self.files
+            .iter()
+            .map(|(user_id, _)| user_id)
+            .unique()
+            .count()
+    }
+
+    /// What keeping just one copy would save: `size × (owners - 1)`
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.owners() as u64 - 1)
+    }
+}
+
+/// Every non-empty file seen during the walk, by size; only sizes shared by
+/// more than one owner are ever read
+#[derive(Debug, Default)]
+pub struct Candidates {
+    by_size: HashMap<u64, Vec<(UserId, PathBuf)>>,
+}
+
+impl Candidates {
+    pub fn push(&mut self, size: u64, owner: UserId, path: &Path) {
+        if size > 0 {
+            self.by_size
+                .entry(size)
+                .or_default()
+                .push((owner, path.to_owned()));
+        }
+    }
+
+    /// Hash the files that could be duplicates, and group them by content.
+    /// Files that can't be read are left out.
+    pub fn find(self, hash: ContentHash) -> Vec<DuplicateGroup> {
+        let mut groups = Vec::new();
+        for (size, files) in self.by_size {
+            let owners = files
+                .iter()
+                .map(|(user_id, _)| user_id)
+                .collect::<HashSet<_>>();
+            if owners.len() < 2 {
+                continue;
+            }
+            let mut by_content: HashMap<Vec<u8>, Vec<(UserId, PathBuf)>> = HashMap::new();
+            for (user_id, path) in files {
+                match hash.digest(&path) {
+                    Ok(digest) => by_content.entry(digest).or_default().push((user_id, path)),
+                    Err(err) => log::debug!("could not hash {}: {}", path.display(), err),
+                }
+            }
+            groups.extend(
+                by_content
+                    .into_values()
+                    .map(|files| DuplicateGroup { size, files })
+                    .filter(|group| group.owners() > 1),
+            );
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Candidates, ContentHash};
+    use du_by_user::id::UserId;
+
+    #[test]
+    fn test_find_across_owners() {
+        let dir =
+            std::env::temp_dir().join(format!("du-by-user-duplicates-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = [
+            ("a", "same", 1),
+            ("b", "same", 2),
+            ("c", "same", 2),
+            ("d", "diff", 3),
+            ("e", "solo", 1),
+        ];
+        for (name, content, _) in files {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        for hash in [ContentHash::Sha256, ContentHash::Md5] {
+            let mut candidates = Candidates::default();
+            for (name, _, owner) in files {
+                candidates.push(4, UserId(owner), &dir.join(name));
+            }
+            let groups = candidates.find(hash);
+            assert_eq!(groups.len(), 1);
+            assert_eq!(groups[0].files.len(), 3);
+            // two owners, so one copy too many
+            assert_eq!(groups[0].wasted(), 4);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,20 @@
+//! Free space on the filesystem being scanned, for `--show-total-and-free`.
+
+use std::os::unix::ffi::OsStrExt;
+
+/// Bytes available to unprivileged users on the filesystem holding `path`,
+/// which is what `df` shows as available
+pub fn available(path: &std::path::Path) -> std::io::Result<u64> {
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated, and `stats` has room for the
+    // `struct statvfs` that a successful call fills in
+    let rc = unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) };
+    if rc < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: statvfs succeeded, so it filled in `stats`
+    let stats = unsafe { stats.assume_init() };
+    #[allow(clippy::unnecessary_cast)] // the field types differ between platforms
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
@@ -0,0 +1,34 @@
+//! Inode flags, the attributes set with `chattr(1)`, for `--ignore-immutable`.
+
+/// Whether `file` has the immutable attribute (`chattr +i`), which keeps even
+/// its owner from changing or deleting it
+#[cfg(target_os = "linux")]
+pub fn is_immutable(file: &std::path::Path) -> std::io::Result<bool> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    // _IOR('f', 1, long); the kernel only ever reads and writes an int
+    const FS_IOC_GETFLAGS: libc::c_ulong = (2 << 30)
+        | ((std::mem::size_of::<libc::c_long>() as libc::c_ulong) << 16)
+        | (0x66 << 8)
+        | 1;
+    const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+
+    let handle = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(file)?;
+    let mut flags: libc::c_int = 0;
+    // SAFETY: FS_IOC_GETFLAGS writes a single int to `flags`, which outlives
+    // the call
+    let rc = unsafe { libc::ioctl(handle.as_raw_fd(), FS_IOC_GETFLAGS, &mut flags) };
+    if rc < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(flags & FS_IMMUTABLE_FL != 0)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn is_immutable(_file: &std::path::Path) -> std::io::Result<bool> {
+    Ok(false)
+}
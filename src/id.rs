@@ -0,0 +1,149 @@
+//! Typed numeric IDs, so that a UID can't be passed where a GID was meant.
+
+/// A numeric user ID, as found in `st_uid`
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(transparent)]
+pub struct UserId(pub u32);
+
+impl From<u32> for UserId {
+    fn from(id: u32) -> Self {
+        Self(id)
+    }
+}
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        self.0.fmt(f)
+    }
+}
+
+/// A range of UIDs written like a Rust range: `MIN..MAX` leaves `MAX` out and
+/// `MIN..=MAX` takes it in, and a missing bound (`..1000`, `60000..`) means
+/// everything on that side. Stored with both ends included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UidRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl UidRange {
+    pub fn contains(&self, user_id: UserId) -> bool {
+        (self.min..=self.max).contains(&user_id.0)
+    }
+}
+
+/// A UID range that couldn't be parsed, with the reason why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseUidRangeError(String);
+
+impl std::fmt::Display for ParseUidRangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for ParseUidRangeError {}
+
+impl std::str::FromStr for UidRange {
+    type Err = ParseUidRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min, max) = s
+            .split_once("..")
+            .ok_or_else(|| ParseUidRangeError(format!("{:?} is not a range like 0..1000", s)))?;
+        let (inclusive, max) = match max.strip_prefix('=') {
+            Some(max) => (true, max),
+            None => (false, max),
+        };
+        let bound = |bound: &str| match bound.trim() {
+            "" => Ok(None),
+            bound => bound
+                .parse::<u32>()
+                .map(Some)
+                .map_err(|_| ParseUidRangeError(format!("invalid UID {:?}", bound))),
+        };
+        let min = bound(min)?.unwrap_or(0);
+        let max = match (bound(max)?, inclusive) {
+            (Some(max), true) => max,
+            (Some(max), false) => max
+                .checked_sub(1)
+                .ok_or_else(|| ParseUidRangeError(format!("{:?} is empty", s)))?,
+            (None, true) => {
+                return Err(ParseUidRangeError(format!(
+                    "{:?} needs an end after ..=",
+                    s
+                )))
+            }
+            (None, false) => u32::MAX,
+        };
+        if min > max {
+            return Err(ParseUidRangeError(format!("{:?} is empty", s)));
+        }
+        Ok(Self { min, max })
+    }
+}
+
+/// A numeric group ID, as found in `st_gid`
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(transparent)]
+pub struct GroupId(pub u32);
+
+impl From<u32> for GroupId {
+    fn from(id: u32) -> Self {
+        Self(id)
+    }
+}
+
+impl std::fmt::Display for GroupId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UidRange, UserId};
+
+    #[test]
+    fn test_parse_uid_ranges() {
+        let range = "0..1000".parse::<UidRange>().unwrap();
+        assert_eq!(range, UidRange { min: 0, max: 999 });
+        assert!(range.contains(UserId(999)) && !range.contains(UserId(1000)));
+        assert_eq!("0..=1000".parse(), Ok(UidRange { min: 0, max: 1000 }));
+        assert_eq!(
+            "60000..".parse(),
+            Ok(UidRange {
+                min: 60000,
+                max: u32::MAX
+            })
+        );
+        assert_eq!("..=99".parse(), Ok(UidRange { min: 0, max: 99 }));
+        for invalid in ["1000", "5..5", "..0", "10..=", "a..b", "9..=1"] {
+            assert!(invalid.parse::<UidRange>().is_err(), "{}", invalid);
+        }
+    }
+}
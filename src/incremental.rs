@@ -0,0 +1,312 @@
+//! Keeping per-user totals up to date by rescanning only the directories that
+//! changed, for programs that hear about changes from `inotify(7)` or
+//! `kqueue(2)`.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::id::UserId;
+
+/// What a scan found, kept in enough detail to redo parts of it with
+/// [`scan_incremental`].
+///
+/// Usage is counted like `du-by-user` does by default: the apparent size of
+/// every regular file, counting a hard-linked file only once, without
+/// following symbolic links. Anything that can't be read is left out.
+///
+/// It can be saved with `serde` between runs, and the states of separate
+/// subtrees (scanned by parallel workers, say) put together with
+/// [`ScanState::merge`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(into = "SavedState", from = "SavedState")]
+pub struct ScanState {
+    scan_root: PathBuf,
+    scanned_at: SystemTime,
+    /// `(bytes, files)` directly inside each directory, by owner
+    dirs: HashMap<PathBuf, HashMap<UserId, (u64, u64)>>,
+    /// `(device, inode)` of every multiply-linked file counted so far, and
+    /// the directory, owner and size it was counted with
+    links: HashMap<(u64, u64), (PathBuf, UserId, u64)>,
+}
+
+impl ScanState {
+    /// Scan everything under `root`
+    pub fn new(root: &Path) -> Self {
+        let mut state = Self {
+            scan_root: root.to_owned(),
+            scanned_at: SystemTime::now(),
+            dirs: HashMap::new(),
+            links: HashMap::new(),
+        };
+        state.walk(root);
+        state
+    }
+
+    pub fn scan_root(&self) -> &Path {
+        &self.scan_root
+    }
+
+    /// When the walk started, or for a state brought up to date with
+    /// [`scan_incremental`], when that started
+    pub fn scanned_at(&self) -> SystemTime {
+        self.scanned_at
+    }
+
+    /// Total usage by owner
+    pub fn usage(&self) -> HashMap<UserId, u64> {
+        self.totals(|(bytes, _)| bytes)
+    }
+
+    /// How many files each owner has, each hard-linked file once
+    pub fn files(&self) -> HashMap<UserId, u64> {
+        self.totals(|(_, files)| files)
+    }
+
+    fn totals(&self, pick: impl Fn((u64, u64)) -> u64) -> HashMap<UserId, u64> {
+        let mut totals = HashMap::new();
+        for owners in self.dirs.values() {
+            for (&user_id, &counts) in owners {
+                *totals.entry(user_id).or_default() += pick(counts);
+            }
+        }
+        totals
+    }
+
+    /// Both scans together, under the deepest directory containing both
+    /// roots and dated by the older of the two, since changes after that may
+    /// be missing from it.
+    ///
+    /// The scans should be of separate subtrees; where both counted the same
+    /// directory, `other`'s count of it is kept. A hard-linked file found by
+    /// both is only counted once.
+    pub fn merge(mut self, other: ScanState) -> ScanState {
+        let ScanState {
+            scan_root,
+            scanned_at,
+            mut dirs,
+            links,
+        } = other;
+        for (inode, (dir, user_id, size)) in links {
+            match self.links.entry(inode) {
+                Entry::Occupied(_) => {
+                    let counted = dirs
+                        .get_mut(&dir)
+                        .and_then(|owners| owners.get_mut(&user_id));
+                    if let Some((bytes, files)) = counted {
+                        *bytes -= size;
+                        *files -= 1;
+                    }
+                }
+                Entry::Vacant(vacant) => {
+                    vacant.insert((dir, user_id, size));
+                }
+            }
+        }
+        self.dirs.extend(dirs);
+        self.scan_root = common_ancestor(&self.scan_root, &scan_root);
+        self.scanned_at = self.scanned_at.min(scanned_at);
+        self
+    }
+
+    /// Drop everything counted at or under `path`
+    fn forget(&mut self, path: &Path) {
+        self.dirs.retain(|dir, _| !dir.starts_with(path));
+        self.links.retain(|_, (dir, ..)| !dir.starts_with(path));
+    }
+
+    fn walk(&mut self, root: &Path) {
+        for entry in walkdir::WalkDir::new(root)
+            .into_iter()
+            .filter_map(Result::ok)
+        {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let dir = entry.path().parent().unwrap_or(root);
+            let user_id = UserId(metadata.uid());
+            if metadata.nlink() > 1 {
+                let inode = (metadata.dev(), metadata.ino());
+                if self.links.contains_key(&inode) {
+                    continue;
+                }
+                self.links
+                    .insert(inode, (dir.to_owned(), user_id, metadata.size()));
+            }
+            let owners = self.dirs.entry(dir.to_owned()).or_default();
+            let (bytes, files) = owners.entry(user_id).or_default();
+            *bytes += metadata.size();
+            *files += 1;
+        }
+    }
+}
+
+/// The deepest directory both `a` and `b` are in (or are)
+fn common_ancestor(a: &Path, b: &Path) -> PathBuf {
+    a.components()
+        .zip(b.components())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a)
+        .collect()
+}
+
+/// How a [`ScanState`] is saved; maps are stored as lists, since JSON object
+/// keys have to be strings
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedState {
+    scan_root: PathBuf,
+    scanned_at: SystemTime,
+    /// `(directory, owner, bytes, files)`
+    dirs: Vec<(PathBuf, UserId, u64, u64)>,
+    links: Vec<(u64, u64, PathBuf, UserId, u64)>,
+}
+
+impl From<ScanState> for SavedState {
+    fn from(state: ScanState) -> Self {
+        Self {
+            scan_root: state.scan_root,
+            scanned_at: state.scanned_at,
+            dirs: state
+                .dirs
+                .into_iter()
+                .flat_map(|(dir, owners)| {
+                    owners
+                        .into_iter()
+                        .map(move |(user_id, (bytes, files))| (dir.clone(), user_id, bytes, files))
+                })
+                .collect(),
+            links: state
+                .links
+                .into_iter()
+                .map(|((dev, ino), (dir, user_id, size))| (dev, ino, dir, user_id, size))
+                .collect(),
+        }
+    }
+}
+
+impl From<SavedState> for ScanState {
+    fn from(saved: SavedState) -> Self {
+        let mut dirs: HashMap<PathBuf, HashMap<UserId, (u64, u64)>> = HashMap::new();
+        for (dir, user_id, bytes, files) in saved.dirs {
+            dirs.entry(dir).or_default().insert(user_id, (bytes, files));
+        }
+        Self {
+            scan_root: saved.scan_root,
+            scanned_at: saved.scanned_at,
+            dirs,
+            links: saved
+                .links
+                .into_iter()
+                .map(|(dev, ino, dir, user_id, size)| ((dev, ino), (dir, user_id, size)))
+                .collect(),
+        }
+    }
+}
+
+/// `previous`, brought up to date by rescanning the subtree under each of
+/// `changes` and keeping everything else as it was.
+///
+/// Each change is a directory whose contents, at any depth, may have changed
+/// or which may have been removed; a file, present or removed, stands for the
+/// directory it's in.
+/// Paths have to be spelled the same way as the root of the original scan.
+/// A hard link removed from one directory and still present in another is
+/// only counted again once the other directory is rescanned too.
+pub fn scan_incremental(mut previous: ScanState, changes: &[PathBuf]) -> ScanState {
+    previous.scanned_at = SystemTime::now();
+    for change in changes {
+        let is_dir = match std::fs::symlink_metadata(change) {
+            Ok(metadata) => metadata.is_dir(),
+            // gone, so only a directory if one was counted there
+            Err(_) => previous.dirs.keys().any(|dir| dir.starts_with(change)),
+        };
+        let dir = if is_dir {
+            change.as_path()
+        } else {
+            change.parent().unwrap_or(change)
+        };
+        previous.forget(dir);
+        previous.walk(dir);
+    }
+    previous
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{scan_incremental, ScanState};
+
+    #[test]
+    fn test_matches_full_rescan() {
+        let root =
+            std::env::temp_dir().join(format!("du-by-user-incremental-{}", std::process::id()));
+        let (kept, changed) = (root.join("kept"), root.join("changed"));
+        std::fs::create_dir_all(kept.join("deep")).unwrap();
+        std::fs::create_dir_all(&changed).unwrap();
+        std::fs::write(kept.join("deep/a"), [0; 10]).unwrap();
+        std::fs::write(changed.join("b"), [0; 20]).unwrap();
+        std::fs::write(changed.join("c"), [0; 30]).unwrap();
+        let state = ScanState::new(&root);
+        assert_eq!(state.usage().values().sum::<u64>(), 60);
+
+        std::fs::remove_file(changed.join("c")).unwrap();
+        std::fs::write(changed.join("b"), [0; 25]).unwrap();
+        std::fs::create_dir(changed.join("new")).unwrap();
+        std::fs::write(changed.join("new/d"), [0; 5]).unwrap();
+        let state = scan_incremental(state, &[changed.join("b"), changed.join("new")]);
+        let fresh = ScanState::new(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(state.usage(), fresh.usage());
+        assert_eq!(state.usage().values().sum::<u64>(), 40);
+    }
+
+    #[test]
+    fn test_removed_file() {
+        let root = std::env::temp_dir().join(format!("du-by-user-removed-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a"), [0; 10]).unwrap();
+        std::fs::write(root.join("b"), [0; 20]).unwrap();
+        let state = ScanState::new(&root);
+        std::fs::remove_file(root.join("b")).unwrap();
+        let state = scan_incremental(state, &[root.join("b")]);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(state.usage().values().sum::<u64>(), 10);
+        assert_eq!(state.files().values().sum::<u64>(), 1);
+    }
+
+    #[test]
+    fn test_merge_matches_whole_scan() {
+        let root = std::env::temp_dir().join(format!("du-by-user-merge-{}", std::process::id()));
+        let (a, b) = (root.join("a"), root.join("b"));
+        std::fs::create_dir_all(&a).unwrap();
+        std::fs::create_dir_all(&b).unwrap();
+        std::fs::write(a.join("x"), [0; 10]).unwrap();
+        std::fs::write(b.join("y"), [0; 20]).unwrap();
+        std::fs::hard_link(a.join("x"), b.join("x")).unwrap();
+        let merged = ScanState::new(&a).merge(ScanState::new(&b));
+        let whole = ScanState::new(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(merged.scan_root(), root);
+        assert_eq!(merged.usage(), whole.usage());
+        assert_eq!(merged.files(), whole.files());
+        assert_eq!(merged.files().values().sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let root = std::env::temp_dir().join(format!("du-by-user-state-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a"), [0; 10]).unwrap();
+        let state = ScanState::new(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+        let json = serde_json::to_string(&state).unwrap();
+        let restored: ScanState = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.scan_root(), state.scan_root());
+        assert_eq!(restored.scanned_at(), state.scanned_at());
+        assert_eq!(restored.usage(), state.usage());
+    }
+}
@@ -0,0 +1,9 @@
+//! Disk usage summarized by owning user.
+//!
+//! Most of the work happens in the `du-by-user` binary; the pieces that are
+//! useful on their own are exposed here.
+
+pub mod id;
+pub mod incremental;
+pub mod size;
+pub mod time;
@@ -0,0 +1,105 @@
+//! Hard-linked files reachable from more than one user's directories, for
+//! `--report-by-link-group`.
+
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use du_by_user::id::UserId;
+use itertools::Itertools;
+
+/// One multiply-linked inode and every path to it that was found
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct LinkGroup {
+    pub size: u64,
+    /// Each path, with the owner of the directory it's in: whoever "has" that
+    /// link, whoever owns the inode itself
+    pub paths: Vec<(UserId, PathBuf)>,
+}
+
+impl LinkGroup {
+    fn owners(&self) -> usize {
+        self.paths
+            .iter()
+            .map(|(user_id, _)| user_id)
+            .unique()
+            .count()
+    }
+}
+
+/// Every path to every multiply-linked inode seen during the walk
+#[derive(Debug, Default)]
+pub struct LinkGroups {
+    by_inode: HashMap<(u64, u64), LinkGroup>,
+    /// The owner of the last directory looked up, since a walk sees each
+    /// directory's files one after another
+    last_dir: Option<(PathBuf, UserId)>,
+}
+
+impl LinkGroups {
+    pub fn push(&mut self, path: &Path, metadata: &std::fs::Metadata) {
+        let Some(dir) = path.parent() else {
+            return;
+        };
+        let dir_owner = match &self.last_dir {
+            Some((last, owner)) if last == dir => *owner,
+            _ => match std::fs::metadata(dir) {
+                Ok(dir_metadata) => {
+                    let owner = UserId(dir_metadata.uid());
+                    self.last_dir = Some((dir.to_owned(), owner));
+                    owner
+                }
+                Err(err) => {
+                    log::debug!("could not stat {}: {}", dir.display(), err);
+                    return;
+                }
+            },
+        };
+        self.by_inode
+            .entry((metadata.dev(), metadata.ino()))
+            .or_insert_with(|| LinkGroup {
+                size: metadata.len(),
+                paths: Vec::new(),
+            })
+            .paths
+            .push((dir_owner, path.to_owned()));
+    }
+
+    /// The `limit` largest inodes linked from more than one owner's
+    /// directories, largest first
+    pub fn largest(self, limit: usize) -> Vec<LinkGroup> {
+        self.by_inode
+            .into_values()
+            .filter(|group| group.owners() > 1)
+            .sorted_by(|a, b| b.size.cmp(&a.size).then_with(|| a.paths.cmp(&b.paths)))
+            .take(limit)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LinkGroup, LinkGroups};
+    use du_by_user::id::UserId;
+
+    #[test]
+    fn test_largest_multi_owner() {
+        let mut groups = LinkGroups::default();
+        for (inode, size, owners) in [(1, 10, [1, 2]), (2, 30, [1, 1]), (3, 20, [1, 3])] {
+            let paths = owners
+                .into_iter()
+                .map(|owner| (UserId(owner), format!("/{}/{}", owner, inode).into()))
+                .collect();
+            groups
+                .by_inode
+                .insert((0, inode), LinkGroup { size, paths });
+        }
+        let sizes = groups
+            .largest(5)
+            .into_iter()
+            .map(|group| group.size)
+            .collect::<Vec<_>>();
+        // inode 2 is only linked from one user's directories
+        assert_eq!(sizes, vec![20, 10]);
+    }
+}
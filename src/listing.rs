@@ -0,0 +1,327 @@
+//! Reading file listings in `find -ls` format, for `--stdin`, so that usage
+//! can be summarized from a listing made elsewhere (or by a faster walker).
+//! `du` output can be read too, and is re-attributed to each path's owner.
+
+use std::io::BufRead;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
+
+use du_by_user::size::ParsedSize;
+
+/// How records are separated in a `--stdin` listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdinFormat {
+    /// One `find -ls` line per file
+    FindLs,
+    /// `find -print0 -ls` output: each file's exact path, NUL-terminated,
+    /// then its `find -ls` line, which survives any file name
+    FindPrint0,
+    /// `size<TAB>path` lines from `du` (or `du -h`), owned by whoever owns
+    /// each path on this machine
+    Du,
+}
+
+impl StdinFormat {
+    pub const NAMES: [&'static str; 3] = ["find-ls", "find-print0", "du"];
+
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        match matches.value_of("stdin-format") {
+            Some("find-print0") => Self::FindPrint0,
+            Some("du") => Self::Du,
+            _ => Self::FindLs,
+        }
+    }
+
+    /// Parse one record, which for [`Self::Du`] means `stat`ing its path
+    pub fn parse(self, record: &Record) -> Result<ListedFile, String> {
+        match self {
+            Self::FindLs | Self::FindPrint0 => {
+                let mut file = record.line.parse::<ListedFile>()?;
+                if let Some(path) = &record.path {
+                    file.path = path.clone();
+                }
+                Ok(file)
+            }
+            Self::Du => parse_du(&record.line),
+        }
+    }
+}
+
+/// One file's worth of a listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub line: String,
+    /// The path exactly as `find -print0` wrote it, for
+    /// [`StdinFormat::FindPrint0`]
+    pub path: Option<std::path::PathBuf>,
+}
+
+/// One file from a `find -ls` listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedFile {
+    pub inode: u64,
+    /// Allocated space in 1K blocks
+    pub blocks: u64,
+    /// The first character of the permissions, like `-` or `d`
+    pub kind: char,
+    /// Whether the permissions have the setuid bit (`s` or `S` for the owner)
+    pub setuid: bool,
+    pub nlink: u64,
+    /// A name, or a number if `find` couldn't resolve it
+    pub owner: String,
+    pub size: u64,
+    pub path: std::path::PathBuf,
+}
+
+/// The next whitespace-separated field of `line`, and what follows it
+fn field(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    let end = line.find(char::is_whitespace)?;
+    Some((&line[..end], &line[end..]))
+}
+
+impl std::str::FromStr for ListedFile {
+    type Err = String;
+
+    /// Parse `inode blocks perms nlink owner group size month day time path`
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut fields = Vec::with_capacity(10);
+        let mut rest = line;
+        while fields.len() < 10 {
+            let (value, tail) =
+                field(rest).ok_or_else(|| format!("truncated record {:?}", line))?;
+            fields.push(value);
+            rest = tail;
+        }
+        let number = |i: usize| {
+            fields[i]
+                .parse::<u64>()
+                .map_err(|_| format!("invalid number {:?} in {:?}", fields[i], line))
+        };
+        // the path starts after the single space following the time; spaces
+        // in names are escaped, so the first ` -> ` is the link's
+        let mut path = rest.strip_prefix(' ').unwrap_or(rest);
+        let kind = fields[2].chars().next().unwrap_or('-');
+        if kind == 'l' {
+            path = path.split(" -> ").next().unwrap_or(path);
+        }
+        Ok(Self {
+            inode: number(0)?,
+            blocks: number(1)?,
+            kind,
+            setuid: matches!(fields[2].chars().nth(3), Some('s' | 'S')),
+            nlink: number(3)?,
+            owner: fields[4].to_owned(),
+            size: number(6)?,
+            path: unescape(path),
+        })
+    }
+}
+
+/// Undo GNU `find -ls` quoting of a name: `\ ` for a space, C escapes like
+/// `\n`, and octal escapes like `\303` for bytes that aren't printable
+fn unescape(quoted: &str) -> std::path::PathBuf {
+    let mut bytes = Vec::with_capacity(quoted.len());
+    let mut rest = quoted.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let Some((&escaped, tail)) = rest.split_first() else {
+            bytes.push(byte);
+            break;
+        };
+        rest = tail;
+        bytes.push(match escaped {
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b'f' => 0x0c,
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'v' => 0x0b,
+            b'0'..=b'7' => {
+                let mut value = escaped - b'0';
+                for _ in 0..2 {
+                    match rest.split_first() {
+                        Some((&digit @ b'0'..=b'7', tail)) => {
+                            value = value.wrapping_mul(8) + (digit - b'0');
+                            rest = tail;
+                        }
+                        _ => break,
+                    }
+                }
+                value
+            }
+            other => other,
+        });
+    }
+    std::ffi::OsString::from_vec(bytes).into()
+}
+
+/// Parse a `du` line, whose size is in 1K blocks unless it has a suffix like
+/// `du -h` output's `4.0K` or `1.5G`. The path's owner and inode come from
+/// `lstat(2)`, but not its size: `du` already added up what's under it.
+fn parse_du(line: &str) -> Result<ListedFile, String> {
+    let (size, path) = line
+        .split_once('\t')
+        .ok_or_else(|| format!("expected size<TAB>path in {:?}", line))?;
+    let size = match size.trim() {
+        blocks if blocks.bytes().all(|b| b.is_ascii_digit()) => blocks
+            .parse::<u64>()
+            .ok()
+            .and_then(|blocks| blocks.checked_mul(1024)),
+        human => human.parse::<ParsedSize>().ok().map(|size| size.bytes()),
+    }
+    .ok_or_else(|| format!("invalid size {:?} in {:?}", size, line))?;
+    let metadata = std::fs::symlink_metadata(path).map_err(|err| format!("{}: {}", path, err))?;
+    Ok(ListedFile {
+        inode: metadata.ino(),
+        blocks: size.div_ceil(1024),
+        // counted like a file, whatever it is, since the size covers it all
+        kind: '-',
+        setuid: metadata.mode() & 0o4000 != 0,
+        // and `du` never counts a hard-linked file twice either
+        nlink: 1,
+        owner: metadata.uid().to_string(),
+        size,
+        path: path.into(),
+    })
+}
+
+/// Split a listing into records according to `format`, leaving out blank
+/// lines
+pub fn records<R: BufRead>(
+    mut reader: R,
+    format: StdinFormat,
+) -> impl Iterator<Item = std::io::Result<Record>> {
+    std::iter::from_fn(move || loop {
+        let path = match format {
+            StdinFormat::FindPrint0 => match read_until(&mut reader, b'\0') {
+                Ok(Some(path)) => Some(path),
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err)),
+            },
+            StdinFormat::FindLs | StdinFormat::Du => None,
+        };
+        let line = match read_until(&mut reader, b'\n') {
+            Ok(Some(line)) => line,
+            // a newline after the last record
+            Ok(None) if path.as_ref().is_none_or(|path| path.is_empty()) => return None,
+            Ok(None) => {
+                return Some(Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "no find -ls line after the last path",
+                )))
+            }
+            Err(err) => return Some(Err(err)),
+        };
+        if line.is_empty() && path.is_none() {
+            continue;
+        }
+        return Some(Ok(Record {
+            line: String::from_utf8_lossy(&line).into_owned(),
+            path: path.map(|path| std::ffi::OsString::from_vec(path).into()),
+        }));
+    })
+}
+
+/// The bytes up to `terminator`, without it, or `None` at the end of the input
+fn read_until<R: BufRead>(reader: &mut R, terminator: u8) -> std::io::Result<Option<Vec<u8>>> {
+    let mut bytes = Vec::new();
+    if reader.read_until(terminator, &mut bytes)? == 0 {
+        return Ok(None);
+    }
+    if bytes.last() == Some(&terminator) {
+        bytes.pop();
+    }
+    Ok(Some(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_du, records, ListedFile, StdinFormat};
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_parse_find_ls() {
+        // from GNU find 4.9.0
+        let input = concat!(
+            " 16171025      4 -rw-r--r--   1 root     root            3 Oct 15 02:24 /tmp/d/a\\ b.txt\n",
+            " 16171041      4 -rw-r--r--   1 root     root            1 Oct 15 02:24 /tmp/d/nl\\nend\n",
+            " 16171011      0 lrwxrwxrwx   1 root     root            7 Oct 15 02:25 /tmp/d/link\\ one -> a\\ b.txt\n",
+            " 16171056      4 -rw-r--r--   1 root     root            1 Oct 15 02:24 /tmp/d/tab\\there\\\\back\n",
+            "\n",
+            " 16171084      4 -rw-r--r--   1 1001     1001            1 Jan  1  2001 /tmp/d/\\303\\251\n",
+        );
+        let files = records(input.as_bytes(), StdinFormat::FindLs)
+            .map(|record| StdinFormat::FindLs.parse(&record.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files[0],
+            ListedFile {
+                inode: 16171025,
+                blocks: 4,
+                kind: '-',
+                setuid: false,
+                nlink: 1,
+                owner: "root".to_owned(),
+                size: 3,
+                path: "/tmp/d/a b.txt".into(),
+            }
+        );
+        let paths = files
+            .iter()
+            .map(|file| file.path.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "/tmp/d/a b.txt",
+                "/tmp/d/nl\nend",
+                "/tmp/d/link one",
+                "/tmp/d/tab\there\\back",
+                "/tmp/d/\u{e9}",
+            ]
+        );
+        assert_eq!(files[4].owner, "1001");
+        assert!("1 2 -rw-r--r--".parse::<ListedFile>().is_err());
+    }
+
+    #[test]
+    fn test_parse_find_print0() {
+        // from GNU find 4.9.0's -print0 -ls
+        let input = concat!(
+            "/tmp/d/a b.txt\0",
+            " 16171025      4 -rw-r--r--   1 root     root            3 Oct 15 02:24 /tmp/d/a\\ b.txt\n",
+            "/tmp/d/trail\n\0",
+            " 16171058      4 -rw-r--r--   1 root     root            1 Oct 15 02:24 /tmp/d/trail\\n\n",
+        );
+        let files = records(input.as_bytes(), StdinFormat::FindPrint0)
+            .map(|record| StdinFormat::FindPrint0.parse(&record.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, std::path::Path::new("/tmp/d/a b.txt"));
+        assert_eq!(files[1].path, std::path::Path::new("/tmp/d/trail\n"));
+        assert_eq!(files[1].inode, 16171058);
+    }
+
+    #[test]
+    fn test_parse_du() {
+        let dir = std::env::temp_dir().join(format!("du-by-user-listing-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let uid = std::fs::metadata(&dir).map(|m| m.uid()).unwrap();
+        let file = parse_du(&format!("1.5K\t{}", dir.display())).unwrap();
+        assert_eq!((file.size, file.kind), (1536, '-'));
+        assert_eq!(file.owner, uid.to_string());
+        assert_eq!(
+            parse_du(&format!("8\t{}", dir.display())).unwrap().size,
+            8192
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(parse_du("8 /nowhere").is_err());
+        assert!(parse_du(&format!("8\t{}", dir.display())).is_err());
+    }
+}
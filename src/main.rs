@@ -1,20 +1,39 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io::Write;
 use std::os::unix::fs::MetadataExt;
+use std::time::SystemTime;
 
-use itertools::Itertools;
+use compare::DiffFormat;
+use du_by_user::id::{GroupId, UidRange, UserId};
+use du_by_user::size::{ParsedSize, SizeFormatter, SizeRange};
+use du_by_user::time::{TimeFormat, YearMonth};
+use duplicates::{ContentHash, DuplicateGroup};
+use linkgroups::{LinkGroup, LinkGroups};
+use listing::StdinFormat;
+use memory::MemoryLimit;
+use output::{Column, OutputEncoding, OutputFormat, PathPrefix, ScanSummary, Table, Value, Writer};
+use progress::{Progress, ProgressFormat};
+use sample::Sampler;
+use walk::{WalkOptions, WalkOrder};
 
-const DIVISORS_SI: [(u64, &'static str); 4] = [
-    (1_000_000_000_000, "T"),
-    (1_000_000_000, "G"),
-    (1_000_000, "M"),
-    (1_000, "K"),
-];
-const DIVISORS_NON_SI: [(u64, &'static str); 4] = [
-    (1_099_511_627_776, "T"),
-    (1_073_741_824, "G"),
-    (1_048_576, "M"),
-    (1_024, "K"),
-];
+mod cgroup;
+mod compare;
+mod duplicates;
+mod freespace;
+mod fsflags;
+mod linkgroups;
+mod listing;
+mod memory;
+mod mounts;
+mod output;
+mod pidfile;
+mod progress;
+mod reflink;
+mod sample;
+mod snapshot;
+mod walk;
+use itertools::Itertools;
 
 fn cli() -> clap::Command<'static> {
     clap::Command::new(env!("CARGO_PKG_NAME"))
@@ -55,6 +74,18 @@ fn cli() -> clap::Command<'static> {
                 .takes_value(false)
                 .help("Output gigabytes"),
         )
+        .arg(
+            clap::Arg::new("block-size")
+                .long("block-size")
+                .takes_value(true)
+                .value_name("SIZE")
+                .validator(|s| match s.parse::<ParsedSize>() {
+                    Ok(ParsedSize(0)) => Err("must be at least 1 byte".to_owned()),
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.to_string()),
+                })
+                .help("Output the number of SIZE-byte blocks, rounded up (SIZE like 4096, 1M or 1MB)"),
+        )
         .arg(
             clap::Arg::new("human")
                 .short('h')
@@ -68,6 +99,41 @@ fn cli() -> clap::Command<'static> {
                 .takes_value(false)
                 .help("Interpret things as powers of 10 instead of powers of 2"),
         )
+        .arg(
+            clap::Arg::new("output-precision")
+                .long("output-precision")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("1")
+                .validator(|s| match s.parse::<usize>() {
+                    Ok(n) if n > du_by_user::size::MAX_PRECISION => {
+                        Err(format!("must be at most {}", du_by_user::size::MAX_PRECISION))
+                    }
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.to_string()),
+                })
+                .help("Decimal places to show with --human, up to 9"),
+        )
+        .arg(
+            clap::Arg::new("human-threshold-multiplier")
+                .long("human-threshold-multiplier")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("1")
+                .validator(|s| match s.parse::<u64>() {
+                    Ok(0) => Err("must be at least 1".to_owned()),
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.to_string()),
+                })
+                .help("With --human, only switch to a unit once the size is N of it (10 gives the old behavior)"),
+        )
+        .arg(
+            clap::Arg::new("locale")
+                .long("locale")
+                .takes_value(false)
+                .conflicts_with("machine-readable")
+                .help("Group digits with the thousands separator of the current locale (LC_NUMERIC)"),
+        )
         .arg(
             clap::Arg::new("numeric-uid")
                 .short('U')
@@ -75,149 +141,5291 @@ fn cli() -> clap::Command<'static> {
                 .takes_value(false)
                 .help("Only print numeric UIDs (otherwise, will try to resolve to usernames)"),
         )
-        .group(clap::ArgGroup::new("output").args(&[
-            "bytes",
-            "kilobytes",
-            "megabytes",
-            "gigabytes",
-            "human",
-        ]))
-}
-
-#[derive(Debug)]
-enum SizeMode {
-    Bytes,
-    Kilobytes,
-    Megabytes,
-    Gigabytes,
-    Human,
-}
-
-impl SizeMode {
-    fn from_matches(matches: &clap::ArgMatches) -> Self {
-        if matches.is_present("gigabytes") {
-            Self::Gigabytes
-        } else if matches.is_present("megabytes") {
-            Self::Megabytes
-        } else if matches.is_present("kilobytes") {
-            Self::Kilobytes
-        } else if matches.is_present("human") {
-            Self::Human
-        } else {
-            Self::Bytes
-        }
-    }
-}
-
-struct FormattedSize<'s> {
-    size: u64,
-    formatter: &'s SizeFormatter,
-}
-
-impl<'s> std::fmt::Display for FormattedSize<'s> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        let (base, unit) = self.formatter.get_parts(self.size);
-        write!(f, "{}", base)?;
-        if let Some(unit) = unit {
-            write!(f, "{}", unit)?;
-        }
-        Ok(())
-    }
-}
-
-#[derive(Debug)]
-struct SizeFormatter {
-    mode: SizeMode,
-    si: bool,
-}
-
-impl SizeFormatter {
-    fn from_matches(matches: &clap::ArgMatches) -> Self {
-        Self {
-            si: matches.is_present("si"),
-            mode: SizeMode::from_matches(matches),
-        }
-    }
-
-    fn get_parts_divisor(&self, size: u64, divisor: u64) -> (u64, Option<&'static str>) {
-        (size / divisor, None)
-    }
-
-    fn get_parts_human(&self, size: u64) -> (u64, Option<&'static str>) {
-        let divisors = if self.si {
-            DIVISORS_SI
-        } else {
-            DIVISORS_NON_SI
-        };
-        for (divisor, unit) in divisors {
-            if size > divisor * 10 {
-                return (size / divisor, Some(unit));
-            }
-        }
-        return (size, Some("B"));
-    }
-
-    fn get_parts(&self, size: u64) -> (u64, Option<&'static str>) {
-        match (&self.mode, self.si) {
-            (SizeMode::Bytes, _) => (size, None),
-            (SizeMode::Kilobytes, false) => self.get_parts_divisor(size, 1024),
-            (SizeMode::Kilobytes, true) => self.get_parts_divisor(size, 1000),
-            (SizeMode::Megabytes, false) => self.get_parts_divisor(size, 1048576),
-            (SizeMode::Megabytes, true) => self.get_parts_divisor(size, 1000000),
-            (SizeMode::Gigabytes, false) => self.get_parts_divisor(size, 1073741824),
-            (SizeMode::Gigabytes, true) => self.get_parts_divisor(size, 1000000000),
-            (SizeMode::Human, _) => self.get_parts_human(size),
-        }
-    }
-
-    fn wrap(&self, size: u64) -> FormattedSize {
-        FormattedSize {
-            size,
-            formatter: &self,
-        }
-    }
-}
-
-fn main() {
-    let matches = cli().get_matches();
-    let formatter = SizeFormatter::from_matches(&matches);
-    let paths = matches.values_of_t_or_exit::<std::path::PathBuf>("path");
-    let print_headers = paths.len() > 1;
-    for path in paths {
-        if print_headers {
-            println!("--- {:?}", path);
-        }
-        let mut by_user: HashMap<u32, u64> = HashMap::new();
-        let walker = walkdir::WalkDir::new(path).follow_links(false);
-        for entry in walker {
-            if let Ok(metadata) = entry.and_then(|e| e.metadata()) {
-                if metadata.is_file() {
-                    *by_user.entry(metadata.uid()).or_insert_with(|| 0) += metadata.size();
-                }
-            }
-        }
-        by_user
-            .into_iter()
-            .sorted_by_key(|&(_, v)| v)
-            .for_each(|(user_id, size)| {
-                let u = if matches.is_present("numeric-uid") {
-                    user_id.to_string()
-                } else {
-                    users::get_user_by_uid(user_id)
-                        .map(|u| u.name().to_string_lossy().into_owned())
-                        .unwrap_or_else(|| user_id.to_string())
-                };
-                println!("{}\t{}", formatter.wrap(size), u);
-            })
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::cli;
-
-    #[test]
-    fn test_debug_assert_cli() {
-        cli().debug_assert()
+        .arg(
+            clap::Arg::new("anonymize")
+                .long("anonymize")
+                .takes_value(false)
+                .help("Replace usernames and UIDs with user_1, user_2, ... (numbered in UID order)"),
+        )
+        .arg(
+            clap::Arg::new("anonymize-map")
+                .long("anonymize-map")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("anonymize")
+                .help("Write the --anonymize mapping to FILE instead of stderr"),
+        )
+        .arg(
+            clap::Arg::new("uid-map")
+                .long("uid-map")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Read uid<TAB>name lines from FILE and prefer those names to the system's"),
+        )
+        .arg(
+            clap::Arg::new("top-files")
+                .long("top-files")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|s| s.parse::<usize>())
+                .help("After the per-user table, list the N largest files regardless of owner"),
+        )
+        .arg(
+            clap::Arg::new("print-inode")
+                .long("print-inode")
+                .takes_value(false)
+                .requires("top-files")
+                .help("Show each file's inode number in the --top-files listing"),
+        )
+        .arg(
+            clap::Arg::new("strip-path-prefix")
+                .long("strip-path-prefix")
+                .takes_value(true)
+                .value_name("SRC")
+                .help("Take SRC off the front of reported paths, as when scanning a disk image mounted at SRC"),
+        )
+        .arg(
+            clap::Arg::new("add-path-prefix")
+                .long("add-path-prefix")
+                .takes_value(true)
+                .value_name("DST")
+                .help("Put DST in front of reported paths (those under --strip-path-prefix, if given)"),
+        )
+        .arg(
+            clap::Arg::new("top-dirs")
+                .long("top-dirs")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|s| s.parse::<usize>())
+                .help("After the per-user table, list the N directories with the most under them, regardless of owner"),
+        )
+        .arg(
+            clap::Arg::new("max-entries")
+                .long("max-entries")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|s| match s.parse::<usize>() {
+                    Ok(0) => Err("must be at least 1".to_owned()),
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.to_string()),
+                })
+                .help("Keep at most N users, adding up the rest as (other), to bound memory with many UIDs"),
+        )
+        .arg(
+            clap::Arg::new("ignore-errors")
+                .long("ignore-errors")
+                .takes_value(false)
+                .help("Silently skip entries that cannot be read instead of reporting them"),
+        )
+        .arg(
+            clap::Arg::new("follow-links")
+                .short('L')
+                .long("follow-links")
+                .takes_value(false)
+                .help("Follow symbolic links, counting what they point to"),
+        )
+        .arg(
+            clap::Arg::new("walk-order")
+                .long("walk-order")
+                .takes_value(true)
+                .possible_values(WalkOrder::NAMES)
+                .default_value("dfs")
+                .help("Walk depth-first (dfs) or breadth-first (bfs), which reaches every shallow directory before any deep one"),
+        )
+        .arg(
+            clap::Arg::new("realpath")
+                .long("realpath")
+                .takes_value(false)
+                .help("Resolve symbolic links in each path before scanning, so reported paths are canonical"),
+        )
+        .arg(
+            clap::Arg::new("show-symlink-targets")
+                .long("show-symlink-targets")
+                .takes_value(false)
+                .requires("follow-links")
+                .help("Count followed links toward the owner of the target rather than of the link"),
+        )
+        .arg(
+            clap::Arg::new("one-file-system")
+                .short('x')
+                .long("one-file-system")
+                .takes_value(false)
+                .help("Don't descend into directories on other filesystems"),
+        )
+        .arg(
+            clap::Arg::new("include-mount-point")
+                .long("include-mount-point")
+                .takes_value(true)
+                .value_name("PATH")
+                .multiple_occurrences(true)
+                .conflicts_with("stdin")
+                .help("Only count entries on the same filesystem as PATH (may be repeated)"),
+        )
+        .arg(
+            clap::Arg::new("exclude")
+                .long("exclude")
+                .takes_value(true)
+                .value_name("PATH")
+                .multiple_occurrences(true)
+                .help("Skip this path and everything under it; relative paths are taken from each scan root (may be repeated)"),
+        )
+        .arg(
+            clap::Arg::new("exclude-pattern-file")
+                .long("exclude-pattern-file")
+                .alias("exclude-from")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Also skip anything matching the glob patterns in FILE, one per line"),
+        )
+        .arg(
+            clap::Arg::new("link-dest")
+                .long("link-dest")
+                .takes_value(true)
+                .value_name("DIR")
+                .conflicts_with("stdin")
+                .help("Skip files that are hard links to a file under DIR, like rsnapshot's unchanged files"),
+        )
+        .arg(
+            clap::Arg::new("filter-uid-file")
+                .long("filter-uid-file")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Only count the owners listed in FILE, one UID or username per line; +name includes, -name excludes"),
+        )
+        .arg(
+            clap::Arg::new("exclude-uid-range")
+                .long("exclude-uid-range")
+                .takes_value(true)
+                .value_name("MIN..MAX")
+                .multiple_occurrences(true)
+                .validator(|s| s.parse::<UidRange>())
+                .help("Don't count owners in this UID range; MIN..MAX leaves MAX out, MIN..=MAX includes it (may be repeated)"),
+        )
+        .arg(
+            clap::Arg::new("auto-exclude-proc-sys")
+                .long("auto-exclude-proc-sys")
+                .takes_value(false)
+                .help("Exclude /proc, /sys, /dev and /run (the default when scanning /)"),
+        )
+        .arg(
+            clap::Arg::new("no-auto-exclude")
+                .long("no-auto-exclude")
+                .takes_value(false)
+                .conflicts_with("auto-exclude-proc-sys")
+                .help("Scan /proc, /sys, /dev and /run even when scanning /"),
+        )
+        .arg(
+            clap::Arg::new("progress-format")
+                .long("progress-format")
+                .takes_value(true)
+                .possible_values(ProgressFormat::NAMES)
+                .help("Show progress on stderr as a bar, dots (one per 1000 files), a running count, or not at all [default: bar when stderr is a terminal, otherwise none]"),
+        )
+        .arg(
+            clap::Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .takes_value(false)
+                .conflicts_with("ignore-errors")
+                .help("Report every entry that could not be read, not just how many"),
+        )
+        .arg(
+            clap::Arg::new("executable-only")
+                .long("executable-only")
+                .takes_value(false)
+                .conflicts_with("stdin")
+                .help("Only count files with at least one execute bit set"),
+        )
+        .arg(
+            clap::Arg::new("non-executable-only")
+                .long("non-executable-only")
+                .takes_value(false)
+                .conflicts_with("executable-only")
+                .conflicts_with("stdin")
+                .help("Only count files with no execute bits set"),
+        )
+        .arg(
+            clap::Arg::new("glob")
+                .long("glob")
+                .takes_value(true)
+                .value_name("PATTERN")
+                .multiple_occurrences(true)
+                .validator(globset::Glob::new)
+                .help("Only count files whose name matches PATTERN, like '*.log' (may be repeated)"),
+        )
+        .arg(
+            clap::Arg::new("hardlink-only")
+                .long("hardlink-only")
+                .takes_value(false)
+                .help("Only count files with more than one hard link"),
+        )
+        .arg(
+            clap::Arg::new("filter-size-range")
+                .long("filter-size-range")
+                .takes_value(true)
+                .value_name("MIN..MAX")
+                .validator(|s| s.parse::<SizeRange>())
+                .help("Only count files whose size is in this range, like 1M..100M, ..100M or 1M.."),
+        )
+        .arg(
+            clap::Arg::new("min-age")
+                .long("min-age")
+                .takes_value(true)
+                .value_name("DURATION")
+                .validator(humantime::parse_duration)
+                .conflicts_with("stdin")
+                .help("Only count files last modified at least DURATION (like 30d or 1y) ago"),
+        )
+        .arg(
+            clap::Arg::new("exclude-newer-than")
+                .long("exclude-newer-than")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("stdin")
+                .help("Only count files last modified before FILE was, to see usage as it was then"),
+        )
+        .arg(
+            clap::Arg::new("max-age")
+                .long("max-age")
+                .takes_value(true)
+                .value_name("DURATION")
+                .validator(humantime::parse_duration)
+                .conflicts_with("stdin")
+                .help("Only count files last modified within the last DURATION (like 7d or 2w)"),
+        )
+        .arg(
+            clap::Arg::new("exclude-extension")
+                .long("exclude-extension")
+                .takes_value(true)
+                .value_name("EXT")
+                .multiple_occurrences(true)
+                .help("Don't count files with this extension (case-insensitive, may be repeated)"),
+        )
+        .arg(
+            clap::Arg::new("include-extension")
+                .long("include-extension")
+                .takes_value(true)
+                .value_name("EXT")
+                .multiple_occurrences(true)
+                .help("Only count files with this extension (case-insensitive, may be repeated)"),
+        )
+        .arg(
+            clap::Arg::new("acl-xattr-size")
+                .long("acl-xattr-size")
+                .takes_value(false)
+                .help("Add the size of each file's POSIX access ACL (stored as an xattr) to its usage"),
+        )
+        .arg(
+            clap::Arg::new("count-xattr-size")
+                .long("count-xattr-size")
+                .takes_value(false)
+                .help("Add the size of all of each file's extended attributes (including ACLs) to its usage"),
+        )
+        .arg(
+            clap::Arg::new("show-newest-file")
+                .long("show-newest-file")
+                .takes_value(false)
+                .help("Show each user's most recently modified file and when it was modified"),
+        )
+        .arg(
+            clap::Arg::new("show-first-seen")
+                .long("show-first-seen")
+                .takes_value(false)
+                .help("Show each user's least recently modified file and when it was modified"),
+        )
+        .arg(
+            clap::Arg::new("show-access-time")
+                .long("show-access-time")
+                .takes_value(false)
+                .help("Show each user's most recently accessed file and when it was accessed"),
+        )
+        .arg(
+            clap::Arg::new("time-format")
+                .long("time-format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(TimeFormat::NAMES)
+                .default_value("iso8601")
+                .help("How to show timestamps; \"relative\" is relative to the start of the scan"),
+        )
+        .arg(
+            clap::Arg::new("per-user-report")
+                .long("per-user-report")
+                .takes_value(true)
+                .value_name("DIR")
+                .conflicts_with("anonymize")
+                .help("Write a list of each user's files, largest first, to DIR/<user>.txt (or .json), or DIR/<uid>.txt where the name would make a bad file name"),
+        )
+        .arg(
+            clap::Arg::new("report-missing-users")
+                .long("report-missing-users")
+                .takes_value(false)
+                .help("After the per-user table, list UIDs that own files but have no account"),
+        )
+        .arg(
+            clap::Arg::new("emit-shell-script")
+                .long("emit-shell-script")
+                .takes_value(true)
+                .value_name("SCRIPT")
+                .requires("report-missing-users")
+                .conflicts_with("anonymize")
+                .help("Write a shell script to SCRIPT that removes (or with --chown-to, reassigns) the files of UIDs without accounts; its commands are commented out unless --execute is given"),
+        )
+        .arg(
+            clap::Arg::new("chown-to")
+                .long("chown-to")
+                .takes_value(true)
+                .value_name("USER")
+                .requires("emit-shell-script")
+                .help("Make the --emit-shell-script commands chown files to USER instead of removing them"),
+        )
+        .arg(
+            clap::Arg::new("execute")
+                .long("execute")
+                .takes_value(false)
+                .requires("emit-shell-script")
+                .help("Leave the --emit-shell-script commands uncommented, so running the script acts on them"),
+        )
+        .arg(
+            clap::Arg::new("checksum")
+                .long("checksum")
+                .takes_value(false)
+                .help("Finish with a SHA-256 of the per-user totals, for cheap change detection (on stderr for JSON)"),
+        )
+        .arg(
+            clap::Arg::new("group-by-month")
+                .long("group-by-month")
+                .takes_value(false)
+                .help("Instead of one line per user, show usage per modification month and user"),
+        )
+        .arg(
+            clap::Arg::new("sparse-savings")
+                .long("sparse-savings")
+                .takes_value(false)
+                .help("Also show how much less space each user's files take up than their apparent size"),
+        )
+        .arg(
+            clap::Arg::new("show-size-and-blocks")
+                .long("show-size-and-blocks")
+                .takes_value(false)
+                .help("Show the space allocated on disk (st_blocks × 512) next to the apparent size"),
+        )
+        .arg(
+            clap::Arg::new("count-links")
+                .short('l')
+                .long("count-links")
+                .takes_value(false)
+                .help("Count a file once for every hard link to it, instead of only the first time it is seen"),
+        )
+        .arg(
+            clap::Arg::new("show-hardlink-savings")
+                .long("show-hardlink-savings")
+                .takes_value(false)
+                .help("Also show how many bytes of each user's files were extra hard links to files already counted"),
+        )
+        .arg(
+            clap::Arg::new("show-dir-count")
+                .long("show-dir-count")
+                .takes_value(false)
+                .help("Also show how many directories each user owns"),
+        )
+        .arg(
+            clap::Arg::new("show-nlink")
+                .long("show-nlink")
+                .takes_value(false)
+                .help("Also show the average hard link count of each user's files"),
+        )
+        .arg(
+            clap::Arg::new("show-allocation-efficiency")
+                .long("show-allocation-efficiency")
+                .takes_value(false)
+                .help("Also show each user's allocated space as a percentage of their apparent size"),
+        )
+        .arg(
+            clap::Arg::new("show-cumulative")
+                .long("show-cumulative")
+                .takes_value(false)
+                .help("Also show the running total of each user's usage and everyone's larger than theirs, in bytes and as a percentage"),
+        )
+        .arg(
+            clap::Arg::new("show-link-count")
+                .long("show-link-count")
+                .takes_value(false)
+                .help("Also show how many distinct inodes and how many links to them each user has"),
+        )
+        .arg(
+            clap::Arg::new("show-setuid-count")
+                .long("show-setuid-count")
+                .takes_value(false)
+                .help("Also show how many setuid files each user owns; any non-root user with some is shown in red"),
+        )
+        .arg(
+            clap::Arg::new("show-acl")
+                .long("show-acl")
+                .takes_value(false)
+                .help("Also show how many of each user's files have a POSIX access ACL beyond their mode bits"),
+        )
+        .arg(
+            clap::Arg::new("show-mode")
+                .long("show-mode")
+                .takes_value(false)
+                .help("Also show the permission mode most common among each user's files, like 0644"),
+        )
+        .arg(
+            clap::Arg::new("omit-if-only-dirs")
+                .long("omit-if-only-dirs")
+                .takes_value(false)
+                .help("Leave out users who are only listed for the directories they own (with --show-dir-count or --show-empty-dirs), not for any files"),
+        )
+        .arg(
+            clap::Arg::new("show-empty-dirs")
+                .long("show-empty-dirs")
+                .takes_value(false)
+                .help("Also show how many empty directories each user owns"),
+        )
+        .arg(
+            clap::Arg::new("show-gid-breakdown")
+                .long("show-gid-breakdown")
+                .takes_value(false)
+                .conflicts_with("anonymize")
+                .help("Under each user, show how much of their usage, and how many files, belong to each group"),
+        )
+        .arg(
+            clap::Arg::new("show-p95-size")
+                .long("show-p95-size")
+                .takes_value(false)
+                .help("Also show the 95th percentile of each user's file sizes"),
+        )
+        .arg(
+            clap::Arg::new("max-collect-files")
+                .long("max-collect-files")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("1000000")
+                .validator(|s| s.parse::<usize>())
+                .help("Remember at most N file sizes per user for percentiles, leaving them out past that"),
+        )
+        .arg(
+            clap::Arg::new("setuid-report")
+                .long("setuid-report")
+                .takes_value(false)
+                .help("After the per-user table, list every setuid and setgid file found"),
+        )
+        .arg(
+            clap::Arg::new("report-world-writable")
+                .long("report-world-writable")
+                .takes_value(false)
+                .help("After the per-user table, list every world-writable file found, root's first"),
+        )
+        .arg(
+            clap::Arg::new("show-rename-hazards")
+                .long("show-rename-hazards")
+                .takes_value(false)
+                .help("After the per-user table, list every file whose name has anything but printable ASCII in it, escaped"),
+        )
+        .arg(
+            clap::Arg::new("report-long-paths")
+                .long("report-long-paths")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|s| s.parse::<usize>())
+                .help("After the per-user table, list every path longer than N bytes, which may be too long for backup tools and the like"),
+        )
+        .arg(
+            clap::Arg::new("show-sparse-files")
+                .long("show-sparse-files")
+                .takes_value(false)
+                .help("After the per-user table, list each user's sparse files (more than 10% unallocated)"),
+        )
+        .arg(
+            clap::Arg::new("show-path-depth-histogram")
+                .long("show-path-depth-histogram")
+                .takes_value(false)
+                .help("After the per-user table, show how many of each user's files are at each depth below the scan root"),
+        )
+        .arg(
+            clap::Arg::new("show-file-count-histogram")
+                .long("show-file-count-histogram")
+                .takes_value(false)
+                .help("Also show how many of each user's files are under 1K, up to 1M, up to 1G and larger (after the per-user table, or as a size_histogram array in JSON)"),
+        )
+        .arg(
+            clap::Arg::new("report-sticky-dirs")
+                .long("report-sticky-dirs")
+                .takes_value(false)
+                .help("After the per-user table, list every sticky directory (like /tmp) and each user's usage within it"),
+        )
+        .arg(
+            clap::Arg::new("show-total-and-free")
+                .long("show-total-and-free")
+                .takes_value(false)
+                .help("After the per-user table, print everyone's total (TOTAL_USED) and the filesystem's free space (TOTAL_FREE)"),
+        )
+        .arg(
+            clap::Arg::new("report-duplicate-content")
+                .long("report-duplicate-content")
+                .takes_value(false)
+                .help("After the per-user table, list files with identical content owned by different users"),
+        )
+        .arg(
+            clap::Arg::new("report-by-link-group")
+                .long("report-by-link-group")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|s| s.parse::<usize>())
+                .help("After the per-user table, list the N largest hard-linked files with links in more than one user's directories"),
+        )
+        .arg(
+            clap::Arg::new("duplicate-hash")
+                .long("duplicate-hash")
+                .takes_value(true)
+                .possible_values(ContentHash::NAMES)
+                .default_value("sha256")
+                .requires("report-duplicate-content")
+                .help("How --report-duplicate-content compares contents; md5 is faster, sha256 can't be fooled"),
+        )
+        .arg(
+            clap::Arg::new("summarize-by-toplevel")
+                .long("summarize-by-toplevel")
+                .takes_value(false)
+                .conflicts_with("group-by-month")
+                .help("Instead of one line per user, show the usage under each immediate subdirectory (like du -d 1)"),
+        )
+        .arg(
+            clap::Arg::new("group-by-project")
+                .long("group-by-project")
+                .takes_value(false)
+                .requires("project-map")
+                .conflicts_with_all(&["group-by-month", "summarize-by-toplevel"])
+                .help("Instead of one line per user, show usage per project (from --project-map) and user"),
+        )
+        .arg(
+            clap::Arg::new("cross-compare-users")
+                .long("cross-compare-users")
+                .takes_value(true)
+                .number_of_values(2)
+                .value_names(&["USER1", "USER2"])
+                .conflicts_with_all(&["group-by-month", "summarize-by-toplevel", "group-by-project"])
+                .help("Instead of one line per user, show the directories holding files of both USER1 and USER2, and how their bytes split"),
+        )
+        .arg(
+            clap::Arg::new("summarize-by-extension")
+                .long("summarize-by-extension")
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    "group-by-month",
+                    "summarize-by-toplevel",
+                    "group-by-project",
+                    "cross-compare-users",
+                ])
+                .help("Instead of one line per user, show the usage and file count of each file extension, whoever owns the files"),
+        )
+        .arg(
+            clap::Arg::new("project-map")
+                .long("project-map")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("group-by-project")
+                .help("Read `path_prefix<TAB>project` pairs from FILE; the longest matching prefix wins"),
+        )
+        .arg(
+            clap::Arg::new("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .validator(|s| s.parse::<u64>())
+                .help("Stop scanning after SECONDS seconds, print what was found so far and exit with status 2"),
+        )
+        .arg(
+            clap::Arg::new("sample")
+                .long("sample")
+                .takes_value(true)
+                .value_name("FRACTION")
+                .validator(sample::parse_fraction)
+                .conflicts_with_all(&["stdin", "restore"])
+                .help("Only count a random FRACTION (like 0.1) of files, and scale the results up to match"),
+        )
+        .arg(
+            clap::Arg::new("max-memory")
+                .long("max-memory")
+                .takes_value(true)
+                .value_name("BYTES")
+                .validator(|s| s.parse::<ParsedSize>())
+                .help("Once the heap grows past BYTES (like 2G), warn and stop collecting per-file details such as file sizes for percentiles"),
+        )
+        .arg(
+            clap::Arg::new("watch")
+                .long("watch")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .validator(|s| s.parse::<u64>())
+                .help("Rescan every SECONDS seconds until interrupted (same as --rescan-interval SECONDSs)"),
+        )
+        .arg(
+            clap::Arg::new("rescan-interval")
+                .long("rescan-interval")
+                .takes_value(true)
+                .value_name("DURATION")
+                .validator(humantime::parse_duration)
+                .help("Rescan after each DURATION (like 30s, 5m or 1h) until interrupted"),
+        )
+        .arg(
+            clap::Arg::new("stdin")
+                .long("stdin")
+                .takes_value(false)
+                .conflicts_with_all(&["path", "rescan"])
+                .help("Summarize a `find -ls` listing read from standard input instead of scanning"),
+        )
+        .arg(
+            clap::Arg::new("snapshot")
+                .long("snapshot")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Save everything the scan found to FILE, to report on later with --restore"),
+        )
+        .arg(
+            clap::Arg::new("restore")
+                .long("restore")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with_all(&["path", "stdin", "rescan", "snapshot", "realpath"])
+                .help("Report on a scan saved with --snapshot instead of scanning again"),
+        )
+        .arg(
+            clap::Arg::new("stdin-format")
+                .long("stdin-format")
+                .takes_value(true)
+                .possible_values(StdinFormat::NAMES)
+                .default_value("find-ls")
+                .requires("stdin")
+                .alias("input-format")
+                .help("What --stdin reads: find-ls lines, find-print0 (find -print0 -ls output) or du output"),
+        )
+        .arg(
+            clap::Arg::new("report-scan-metadata")
+                .long("report-scan-metadata")
+                .takes_value(false)
+                .help("Include scan totals, timing and error counts in json and csv output"),
+        )
+        .arg(
+            clap::Arg::new("bytes-per-inode")
+                .long("bytes-per-inode")
+                .takes_value(false)
+                .conflicts_with_all(&["group-by-month", "summarize-by-toplevel", "group-by-project", "cross-compare-users", "summarize-by-extension", "columns"])
+                .help("Instead of the usual columns, show each user's average file size (bytes per inode), and sort by it unless --sort-by is given"),
+        )
+        .arg(
+            clap::Arg::new("summarize")
+                .long("summarize")
+                .takes_value(false)
+                .conflicts_with_all(&["output-format", "ndjson", "machine-readable", "watch-diff"])
+                .help("Print only a one-line JSON object with the totals and the largest user, for monitoring checks"),
+        )
+        .arg(
+            clap::Arg::new("profile")
+                .long("profile")
+                .takes_value(false)
+                .help("When done, print how long each phase of the run took to stderr"),
+        )
+        .arg(
+            clap::Arg::new("pid-file")
+                .long("pid-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Write our PID to PATH and hold a lock on it until exit, refusing to start if another process holds it"),
+        )
+        .arg(
+            clap::Arg::new("watch-diff")
+                .long("watch-diff")
+                .takes_value(false)
+                .requires("rescan")
+                .help("After the first --watch cycle, only show users whose usage changed, and by how much"),
+        )
+        .arg(
+            clap::Arg::new("show-recent-growth")
+                .long("show-recent-growth")
+                .takes_value(false)
+                .requires("rescan")
+                .help("Show how fast each user's usage grew since the previous --watch cycle (or the last --snapshot), per hour"),
+        )
+        .arg(
+            clap::Arg::new("compare")
+                .long("compare")
+                .takes_value(true)
+                .number_of_values(2)
+                .value_names(&["BEFORE", "AFTER"])
+                .help("Instead of scanning, show the per-user change between two saved --output-format json reports"),
+        )
+        .arg(
+            clap::Arg::new("diff-format")
+                .long("diff-format")
+                .takes_value(true)
+                .possible_values(DiffFormat::NAMES)
+                .default_value("human")
+                .requires("compare")
+                .help("How --compare shows its results"),
+        )
+        .arg(
+            clap::Arg::new("show-owner-changes")
+                .long("show-owner-changes")
+                .takes_value(false)
+                .requires("compare")
+                .help("Treat the --compare files as --snapshot files, and list the inodes whose owner changed between them"),
+        )
+        .arg(
+            clap::Arg::new("report-unchanged")
+                .long("report-unchanged")
+                .takes_value(false)
+                .requires("compare")
+                .help("Also list users whose usage is the same in both --compare reports"),
+        )
+        .arg(
+            clap::Arg::new("relative-to")
+                .long("relative-to")
+                .takes_value(true)
+                .value_name("USER")
+                .help("Also show each user's usage as a multiple of USER's usage (name or UID)"),
+        )
+        .arg(
+            clap::Arg::new("machine-readable")
+                .long("machine-readable")
+                .takes_value(false)
+                .conflicts_with_all(&["output", "output-format", "ndjson", "relative-to"])
+                .help("Print raw byte counts and numeric UIDs as <bytes><TAB><uid>, for pipelines"),
+        )
+        .arg(
+            clap::Arg::new("sort-by")
+                .long("sort-by")
+                .takes_value(true)
+                .possible_values(SortKey::NAMES)
+                .default_value("size")
+                .help("Order users by total size, number of files, name, UID or average file size, smallest first"),
+        )
+        .arg(
+            clap::Arg::new("min-ratio")
+                .long("min-ratio")
+                .takes_value(true)
+                .value_name("FRACTION")
+                .validator(parse_ratio)
+                .help("Leave out users with less than FRACTION (like 0.01) of everyone's usage"),
+        )
+        .arg(
+            clap::Arg::new("max-ratio")
+                .long("max-ratio")
+                .takes_value(true)
+                .value_name("FRACTION")
+                .validator(parse_ratio)
+                .help("Leave out users with more than FRACTION (like 0.5) of everyone's usage, to see past the largest"),
+        )
+        .arg(
+            clap::Arg::new("reverse")
+                .short('r')
+                .long("reverse")
+                .takes_value(false)
+                .help("List users in the opposite order, so largest first by default"),
+        )
+        .arg(
+            clap::Arg::new("cap-at")
+                .long("cap-at")
+                .takes_value(true)
+                .value_name("SIZE")
+                .validator(|s| s.parse::<ParsedSize>())
+                .help("Show any user over SIZE as >SIZE, so one huge user doesn't dwarf the rest"),
+        )
+        .arg(
+            clap::Arg::new("no-sort")
+                .long("no-sort")
+                .takes_value(false)
+                .conflicts_with_all(&["sort-by", "reverse"])
+                .help("List users in no particular order, which is a little faster for pipelines that sort anyway"),
+        )
+        .arg(
+            clap::Arg::new("columns")
+                .long("columns")
+                .takes_value(true)
+                .value_name("LIST")
+                .multiple_values(true)
+                .use_value_delimiter(true)
+                .require_value_delimiter(true)
+                .possible_values(UserColumn::NAMES)
+                .help("Comma-separated columns to show, in order (overrides the defaults and flags like --sparse-savings)"),
+        )
+        .arg(
+            clap::Arg::new("color")
+                .long("color")
+                .takes_value(true)
+                .value_name("WHEN")
+                .possible_values(["auto", "always", "never"])
+                .default_value("auto")
+                .help("Highlight the largest users in red (auto: only on a terminal, unless NO_COLOR is set)"),
+        )
+        .arg(
+            clap::Arg::new("color-threshold")
+                .long("color-threshold")
+                .takes_value(true)
+                .value_name("SIZE")
+                .validator(|s| s.parse::<ParsedSize>())
+                .help("Highlight users over SIZE (like 10G) instead of those over 80% of the largest user"),
+        )
+        .arg(
+            clap::Arg::new("output-width")
+                .long("output-width")
+                .takes_value(true)
+                .value_name("N")
+                .validator(|s| s.parse::<usize>())
+                .help("Shorten paths so that table output fits in N columns (defaults to the terminal width)"),
+        )
+        .arg(
+            clap::Arg::new("output-format")
+                .long("output-format")
+                .takes_value(true)
+                .possible_values(OutputFormat::NAMES)
+                .default_value("tsv")
+                .help("Output format; metrics is the Prometheus text format"),
+        )
+        .arg(
+            clap::Arg::new("output-encoding")
+                .long("output-encoding")
+                .takes_value(true)
+                .possible_values(OutputEncoding::NAMES)
+                .default_value("utf8")
+                .help("Write usernames as they are (utf8), or with non-ASCII characters escaped as \\uXXXX (ascii)"),
+        )
+        .arg(
+            clap::Arg::new("no-unicode")
+                .long("no-unicode")
+                .alias("ascii")
+                .takes_value(false)
+                .help("Draw tables with +, - and |, and write x for \u{d7} and ... for \u{2026}, for terminals and logs without Unicode"),
+        )
+        .arg(
+            clap::Arg::new("influxdb-measurement")
+                .long("influxdb-measurement")
+                .takes_value(true)
+                .value_name("NAME")
+                .default_value("du_by_user")
+                .help("Measurement name for --output-format influxdb"),
+        )
+        .arg(
+            clap::Arg::new("sql-table")
+                .long("sql-table")
+                .takes_value(true)
+                .value_name("NAME")
+                .default_value("disk_usage")
+                .help("Table name for --output-format sql"),
+        )
+        .arg(
+            clap::Arg::new("sql-create-table")
+                .long("sql-create-table")
+                .takes_value(false)
+                .help("Start --output-format sql with a CREATE TABLE IF NOT EXISTS statement"),
+        )
+        .arg(
+            clap::Arg::new("format-header")
+                .long("format-header")
+                .takes_value(false)
+                .help("Start tab-separated output with a # line naming the columns (csv always has a header row)"),
+        )
+        .arg(
+            clap::Arg::new("ndjson")
+                .long("ndjson")
+                .alias("output-newline-delimited-json")
+                .takes_value(false)
+                .conflicts_with("output-format")
+                .help("Shorthand for --output-format jsonlines (one JSON object per line)"),
+        )
+        .arg(
+            clap::Arg::new("ndjson-buffer-size")
+                .long("ndjson-buffer-size")
+                .takes_value(true)
+                .value_name("BYTES")
+                .default_value("64K")
+                .validator(|s| s.parse::<ParsedSize>())
+                .help("How much output to hold before writing it out, which matters most for jsonlines piped somewhere slow"),
+        )
+        .args(async_lookup_args())
+        .args(reflink_args())
+        .args(compression_args())
+        .args(immutable_args())
+        .args(bind_mount_args())
+        .args(cgroup_args())
+        .args(capability_args())
+        .group(clap::ArgGroup::new("output").args(&[
+            "bytes",
+            "kilobytes",
+            "megabytes",
+            "gigabytes",
+            "block-size",
+            "human",
+        ]))
+        .group(clap::ArgGroup::new("rescan").args(&["watch", "rescan-interval"]))
+}
+
+#[cfg(feature = "tokio")]
+fn async_lookup_args() -> Vec<clap::Arg<'static>> {
+    vec![clap::Arg::new("async-lookup")
+        .long("async-lookup")
+        .takes_value(false)
+        .help("Resolve usernames concurrently (helps when NSS lookups go over the network)")]
+}
+
+#[cfg(not(feature = "tokio"))]
+fn async_lookup_args() -> Vec<clap::Arg<'static>> {
+    Vec::new()
+}
+
+#[cfg(all(target_os = "linux", feature = "btrfs"))]
+fn reflink_args() -> Vec<clap::Arg<'static>> {
+    vec![clap::Arg::new("count-reflinks")
+        .long("count-reflinks")
+        .takes_value(false)
+        .help("Also show how much of each user's usage is in extents shared with other files (reflinks, dedup, snapshots)")]
+}
+
+#[cfg(not(all(target_os = "linux", feature = "btrfs")))]
+fn reflink_args() -> Vec<clap::Arg<'static>> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+fn compression_args() -> Vec<clap::Arg<'static>> {
+    vec![clap::Arg::new("show-compression-ratio")
+        .long("show-compression-ratio")
+        .takes_value(false)
+        .help("Also show the ratio of each user's apparent size to the space it takes on disk (only meaningful where the filesystem reports compressed allocation)")]
+}
+
+#[cfg(not(target_os = "linux"))]
+fn compression_args() -> Vec<clap::Arg<'static>> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+fn immutable_args() -> Vec<clap::Arg<'static>> {
+    vec![clap::Arg::new("ignore-immutable")
+        .long("ignore-immutable")
+        .takes_value(false)
+        .conflicts_with("stdin")
+        .help("Leave out files with the immutable attribute (chattr +i), which their owners can't remove")]
+}
+
+#[cfg(not(target_os = "linux"))]
+fn immutable_args() -> Vec<clap::Arg<'static>> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+fn capability_args() -> Vec<clap::Arg<'static>> {
+    vec![clap::Arg::new("count-cap-files")
+        .long("count-cap-files")
+        .takes_value(false)
+        .help("Also show how many of each user's files have Linux capabilities set (with setcap)")]
+}
+
+#[cfg(not(target_os = "linux"))]
+fn capability_args() -> Vec<clap::Arg<'static>> {
+    Vec::new()
+}
+
+#[cfg(all(target_os = "linux", feature = "cgroup"))]
+fn cgroup_args() -> Vec<clap::Arg<'static>> {
+    vec![clap::Arg::new("cgroup-aware")
+        .long("cgroup-aware")
+        .takes_value(false)
+        .conflicts_with_all(&["group-by-project", "group-by-month", "summarize-by-toplevel", "cross-compare-users", "summarize-by-extension"])
+        .help("Instead of one line per user, show usage per running container (found from its processes' cgroups and overlay mounts) and user")]
+}
+
+#[cfg(not(all(target_os = "linux", feature = "cgroup")))]
+fn cgroup_args() -> Vec<clap::Arg<'static>> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+fn bind_mount_args() -> Vec<clap::Arg<'static>> {
+    vec![clap::Arg::new("include-bind-mounts")
+        .long("include-bind-mounts")
+        .takes_value(false)
+        .requires("one-file-system")
+        .help("With --one-file-system, also descend into bind mounts of the same filesystem, found in /proc/self/mountinfo")]
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_mount_args() -> Vec<clap::Arg<'static>> {
+    Vec::new()
+}
+
+fn resolve_user_id(user: &str, uid_map: &HashMap<UserId, String>) -> Option<UserId> {
+    let mapped = uid_map.iter().find(|(_, name)| *name == user);
+    user.parse::<u32>()
+        .ok()
+        .map(UserId)
+        .or_else(|| mapped.map(|(&user_id, _)| user_id))
+        .or_else(|| users::get_user_by_name(user).map(|u| UserId(u.uid())))
+}
+
+/// Parse `--exclude-pattern-file`, like rsync's `--exclude-from`: one glob per
+/// line, matched against both the full path and the file name; blank lines and
+/// lines starting with `#` are ignored
+fn load_exclude_patterns(path: &std::path::Path) -> std::io::Result<globset::GlobSet> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut globs = globset::GlobSetBuilder::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let glob = globset::Glob::new(line).map_err(|err| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("line {}: {}", number + 1, err),
+            )
+        })?;
+        globs.add(glob);
+    }
+    globs
+        .build()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+/// Every file under `dir` that other paths could be hard links to, for
+/// `--link-dest`; what can't be read there is left out
+fn linked_inodes(dir: &std::path::Path) -> HashSet<(u64, u64)> {
+    let options = WalkOptions {
+        order: WalkOrder::DepthFirst,
+        follow_links: false,
+        same_file_system: false,
+        bind_mounts: false,
+    };
+    let mut inodes = HashSet::new();
+    for entry in walk::walk(dir, options, |_| false) {
+        match entry.and_then(|entry| {
+            entry.metadata().map_err(|reason| SkippedEntry {
+                path: entry.path().to_owned(),
+                reason,
+            })
+        }) {
+            Ok(metadata) if metadata.is_file() && metadata.nlink() > 1 => {
+                inodes.insert((metadata.dev(), metadata.ino()));
+            }
+            Ok(_) => {}
+            Err(err) => log::debug!(
+                "skipped {} in --link-dest: {}",
+                err.path.display(),
+                err.reason
+            ),
+        }
+    }
+    if inodes.is_empty() {
+        log::warn!("nothing under {} is hard-linked", dir.display());
+    }
+    inodes
+}
+
+/// Whose files are counted at all, from `--filter-uid-file`
+#[derive(Debug, Default)]
+struct UidFilter {
+    /// If there are any, nobody else is counted
+    include: HashSet<UserId>,
+    exclude: HashSet<UserId>,
+}
+
+impl UidFilter {
+    fn counts(&self, user_id: UserId) -> bool {
+        (self.include.is_empty() || self.include.contains(&user_id))
+            && !self.exclude.contains(&user_id)
+    }
+}
+
+/// Parse `--filter-uid-file`: one UID or username per line, optionally
+/// prefixed with `+` (include, the default) or `-` (exclude); blank lines and
+/// lines starting with `#` are ignored. Names are looked up in `uid_map` first,
+/// as they are everywhere else.
+fn load_uid_filter(
+    path: &std::path::Path,
+    uid_map: &HashMap<UserId, String>,
+) -> std::io::Result<UidFilter> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut filter = UidFilter::default();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (list, user) = if let Some(user) = line.strip_prefix('-') {
+            (&mut filter.exclude, user.trim())
+        } else if let Some(user) = line.strip_prefix('+') {
+            (&mut filter.include, user.trim())
+        } else {
+            (&mut filter.include, line)
+        };
+        let Some(user_id) = resolve_user_id(user, uid_map) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("line {}: unknown user {:?}", number + 1, user),
+            ));
+        };
+        list.insert(user_id);
+    }
+    Ok(filter)
+}
+
+/// Which project each directory belongs to, for `--group-by-project`
+#[derive(Debug)]
+struct ProjectMap {
+    /// Longest prefix first, so that the first match is the most specific
+    prefixes: Vec<(std::path::PathBuf, String)>,
+}
+
+impl ProjectMap {
+    fn new(mut prefixes: Vec<(std::path::PathBuf, String)>) -> Self {
+        prefixes.sort_by_key(|(prefix, _)| Reverse(prefix.components().count()));
+        Self { prefixes }
+    }
+
+    /// The project `path` is in, comparing whole components so that
+    /// `/data/a` doesn't claim `/data/ab`
+    fn project(&self, path: &std::path::Path) -> Option<&str> {
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix))
+            .map(|(_, project)| project.as_str())
+    }
+}
+
+/// Parse `--project-map`: one `path_prefix<TAB>project` pair per line, in the
+/// same layout as `--uid-map`
+fn load_project_map(path: &std::path::Path) -> std::io::Result<ProjectMap> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut prefixes: Vec<(std::path::PathBuf, String)> = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('\t') {
+            Some((prefix, project)) if !prefix.is_empty() && !project.trim().is_empty() => {
+                prefixes.push((prefix.into(), project.trim().to_owned()));
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("line {}: expected path_prefix<TAB>project", number + 1),
+                ))
+            }
+        }
+    }
+    Ok(ProjectMap::new(prefixes))
+}
+
+/// Parse `--uid-map`: one `uid<TAB>name` pair per line; blank lines and
+/// lines starting with `#` are ignored
+fn load_uid_map(path: &std::path::Path) -> std::io::Result<HashMap<UserId, String>> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut uid_map = HashMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line
+            .split_once('\t')
+            .and_then(|(uid, name)| Some((uid.trim().parse::<u32>().ok()?, name.trim())));
+        match parsed {
+            Some((uid, name)) if !name.is_empty() => {
+                uid_map.insert(UserId(uid), name.to_owned());
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("line {}: expected uid<TAB>name", number + 1),
+                ))
+            }
+        }
+    }
+    Ok(uid_map)
+}
+
+fn user_name(user_id: UserId, numeric: bool) -> String {
+    if user_id == OTHER_USERS && !numeric {
+        "(other)".to_owned()
+    } else if numeric {
+        user_id.to_string()
+    } else {
+        users::get_user_by_uid(user_id.0)
+            .map(|u| u.name().to_string_lossy().into_owned())
+            .unwrap_or_else(|| user_id.to_string())
+    }
+}
+
+/// The name of group `group_id`, or its number if it has none
+fn group_name(group_id: GroupId, numeric: bool) -> String {
+    if numeric {
+        return group_id.to_string();
+    }
+    users::get_group_by_gid(group_id.0)
+        .map(|g| g.name().to_string_lossy().into_owned())
+        .unwrap_or_else(|| group_id.to_string())
+}
+
+/// The account name for `user_id`, looked up at most once per `cache`
+fn resolve_uid(user_id: UserId, cache: &mut HashMap<UserId, String>) -> &str {
+    cache
+        .entry(user_id)
+        .or_insert_with(|| user_name(user_id, false))
+}
+
+/// Decides what gets printed for each UID in a report
+struct UserNames {
+    numeric: bool,
+    /// `--anonymize` aliases, numbered from 1 in UID order
+    aliases: Option<HashMap<UserId, u32>>,
+    /// Names that were looked up ahead of time or came from `--uid-map`
+    resolved: HashMap<UserId, String>,
+    encoding: OutputEncoding,
+}
+
+impl UserNames {
+    fn new(numeric: bool) -> Self {
+        Self {
+            numeric,
+            aliases: None,
+            resolved: HashMap::new(),
+            encoding: OutputEncoding::Utf8,
+        }
+    }
+
+    fn anonymized(numeric: bool, user_ids: impl Iterator<Item = UserId>) -> Self {
+        let aliases = user_ids
+            .filter(|&user_id| user_id != OTHER_USERS)
+            .sorted()
+            .dedup()
+            .zip(1..)
+            .collect();
+        Self {
+            numeric,
+            aliases: Some(aliases),
+            resolved: HashMap::new(),
+            encoding: OutputEncoding::Utf8,
+        }
+    }
+
+    /// Use the names in `uid_map` instead of looking those UIDs up
+    fn with_uid_map(mut self, uid_map: &HashMap<UserId, String>) -> Self {
+        self.resolved.extend(
+            uid_map
+                .iter()
+                .map(|(&user_id, name)| (user_id, name.clone())),
+        );
+        self
+    }
+
+    /// Write names in `encoding`, for `--output-encoding`
+    fn with_encoding(mut self, encoding: OutputEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Look up any of `user_ids` that haven't been already, so that each is
+    /// only looked up once however many times it's shown
+    fn resolve(&mut self, user_ids: impl Iterator<Item = UserId>) {
+        if self.numeric || self.aliases.is_some() {
+            return;
+        }
+        for user_id in user_ids {
+            resolve_uid(user_id, &mut self.resolved);
+        }
+    }
+
+    /// Look up all of `user_ids` at once on tokio's blocking pool, so that
+    /// slow LDAP/SSSD round-trips overlap instead of adding up
+    #[cfg(feature = "tokio")]
+    fn prefetch(&mut self, user_ids: impl Iterator<Item = UserId>) {
+        if self.numeric {
+            return;
+        }
+        let user_ids = user_ids
+            .filter(|user_id| !self.resolved.contains_key(user_id))
+            .collect::<Vec<_>>();
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .build()
+            .expect("failed to start tokio runtime");
+        let resolved = runtime.block_on(async {
+            let lookups = user_ids
+                .into_iter()
+                .map(|user_id| {
+                    tokio::task::spawn_blocking(move || (user_id, user_name(user_id, false)))
+                })
+                .collect::<Vec<_>>();
+            let mut resolved = HashMap::new();
+            for lookup in lookups {
+                let (user_id, name) = lookup.await.expect("username lookup panicked");
+                resolved.insert(user_id, name);
+            }
+            resolved
+        });
+        self.resolved.extend(resolved);
+    }
+
+    fn name(&self, user_id: UserId) -> String {
+        if user_id == OTHER_USERS {
+            return "(other)".to_owned();
+        }
+        match self.aliases.as_ref().and_then(|a| a.get(&user_id)) {
+            Some(alias) => format!("user_{}", alias),
+            None if self.numeric => user_id.to_string(),
+            None => self.encoding.encode(self.real_name(user_id)),
+        }
+    }
+
+    fn real_name(&self, user_id: UserId) -> String {
+        match self.resolved.get(&user_id) {
+            Some(name) => name.clone(),
+            None => user_name(user_id, false),
+        }
+    }
+
+    /// What goes in a `uid` column; `OTHER_USERS` isn't anyone's, so it has none
+    fn uid(&self, user_id: UserId) -> Value {
+        if user_id == OTHER_USERS {
+            return Value::Missing;
+        }
+        Value::Id(
+            self.aliases
+                .as_ref()
+                .and_then(|a| a.get(&user_id).copied())
+                .unwrap_or(user_id.0),
+        )
+    }
+
+    /// Lines of `alias<TAB>uid<TAB>username` for internal reference
+    fn write_mapping<W: Write>(&self, mut out: W) -> std::io::Result<()> {
+        if let Some(aliases) = &self.aliases {
+            for (user_id, alias) in aliases.iter().sorted_by_key(|&(_, alias)| alias) {
+                writeln!(
+                    out,
+                    "user_{}\t{}\t{}",
+                    alias,
+                    user_id,
+                    self.real_name(*user_id)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the `limit` largest files seen so far; memory is bounded by `limit`
+/// no matter how many files get pushed through it.
+struct TopFiles {
+    limit: usize,
+    /// `(size, path, owner, inode)`; the inode is 0 where it isn't known
+    heap: BinaryHeap<Reverse<(u64, std::path::PathBuf, UserId, u64)>>,
+}
+
+impl TopFiles {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            heap: BinaryHeap::with_capacity(limit + 1),
+        }
+    }
+
+    fn owners(&self) -> impl Iterator<Item = UserId> + '_ {
+        self.heap.iter().map(|Reverse((_, _, user_id, _))| *user_id)
+    }
+
+    fn push(&mut self, size: u64, path: &std::path::Path, user_id: UserId, inode: u64) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.heap.len() == self.limit {
+            match self.heap.peek() {
+                Some(Reverse((smallest, ..))) if *smallest >= size => return,
+                _ => {}
+            }
+            self.heap.pop();
+        }
+        self.heap
+            .push(Reverse((size, path.to_owned(), user_id, inode)));
+    }
+
+    /// Consume the heap, returning entries ordered from smallest to largest
+    fn into_sorted_vec(self) -> Vec<(u64, std::path::PathBuf, UserId, u64)> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .rev()
+            .map(|Reverse(e)| e)
+            .collect()
+    }
+}
+
+/// Add `size` to the total of every directory `path` is under, up to and
+/// including `root`
+fn add_to_ancestors(
+    totals: &mut HashMap<std::path::PathBuf, u64>,
+    root: &std::path::Path,
+    path: &std::path::Path,
+    size: u64,
+) {
+    let dirs = path
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(root) && !dir.as_os_str().is_empty());
+    for dir in dirs {
+        *totals.entry(dir.to_owned()).or_default() += size;
+    }
+}
+
+/// The `limit` directories with the largest totals, largest first
+fn largest_dirs(
+    totals: HashMap<std::path::PathBuf, u64>,
+    limit: usize,
+) -> Vec<(u64, std::path::PathBuf)> {
+    totals
+        .into_iter()
+        .map(|(dir, size)| (size, dir))
+        // on a tie the outer directory first, which is the one to look at
+        .sorted_by(|(a_size, a), (b_size, b)| b_size.cmp(a_size).then_with(|| a.cmp(b)))
+        .take(limit)
+        .collect()
+}
+
+#[derive(Debug)]
+struct ScanOptions {
+    top_files: Option<usize>,
+    top_dirs: Option<usize>,
+    /// Fold all but this many users into `OTHER_USERS`, for `--max-entries`
+    max_entries: Option<usize>,
+    ignore_errors: bool,
+    acl_xattr_size: bool,
+    count_xattr_size: bool,
+    newest_file: bool,
+    oldest_file: bool,
+    accessed_file: bool,
+    collect_files: bool,
+    /// Keep up to this many file sizes per user, for percentiles
+    collect_sizes: Option<usize>,
+    by_month: bool,
+    /// Only count files whose executable-ness matches, if set
+    executable: Option<bool>,
+    /// `--glob` patterns, matched against file names
+    globs: Option<globset::GlobSet>,
+    hardlink_only: bool,
+    size_range: Option<SizeRange>,
+    /// Bounds on how long ago files were last modified, for `--min-age` and `--max-age`
+    min_age: Option<std::time::Duration>,
+    /// Only files modified before this count, for `--exclude-newer-than`
+    modified_before: Option<SystemTime>,
+    max_age: Option<std::time::Duration>,
+    /// Lowercased extensions, without the leading `.`
+    exclude_extensions: Vec<String>,
+    include_extensions: Vec<String>,
+    setuid_report: bool,
+    world_writable: bool,
+    rename_hazards: bool,
+    /// List paths longer than this many bytes, for `--report-long-paths`
+    long_paths: Option<usize>,
+    sticky_dirs: bool,
+    sparse_files: bool,
+    depth_histogram: bool,
+    size_histogram: bool,
+    /// Set for `--report-duplicate-content`
+    duplicate_content: Option<ContentHash>,
+    /// How many groups `--report-by-link-group` shows
+    link_groups: Option<usize>,
+    by_toplevel: bool,
+    by_extension: bool,
+    /// Set for `--group-by-project`
+    projects: Option<ProjectMap>,
+    /// The two users `--cross-compare-users` compares
+    cross_users: Option<[UserId; 2]>,
+    dir_count: bool,
+    /// Count files with an access ACL, for `--show-acl`
+    acl_count: bool,
+    /// Count files with capabilities, for `--count-cap-files`
+    cap_count: bool,
+    /// Count each user's files by mode, for `--show-mode`
+    mode_count: bool,
+    empty_dirs: bool,
+    /// Add up each user's files by group, for `--show-gid-breakdown`
+    gid_breakdown: bool,
+    count_links: bool,
+    count_reflinks: bool,
+    ignore_immutable: bool,
+    symlink_targets: bool,
+    /// Whether to remember every file's inode, for `--snapshot`
+    record_inodes: bool,
+    walk: WalkOptions,
+    progress: ProgressFormat,
+    /// Device IDs from `--include-mount-point`; empty means every device
+    devices: Vec<u64>,
+    /// When `--timeout` runs out
+    deadline: Option<std::time::Instant>,
+    max_memory: Option<u64>,
+    /// The fraction of files `--sample` counts
+    sample: Option<f64>,
+    exclude: Vec<std::path::PathBuf>,
+    /// Patterns from `--exclude-pattern-file`
+    exclude_globs: Option<globset::GlobSet>,
+    uid_filter: Option<UidFilter>,
+    /// `(device, inode)` of every multiply-linked file under `--link-dest`
+    link_dest: Option<HashSet<(u64, u64)>>,
+    /// From `--exclude-uid-range`
+    excluded_uids: Vec<UidRange>,
+    /// Whether to skip [`PSEUDO_FILESYSTEMS`]; `None` means only when scanning `/`
+    auto_exclude: Option<bool>,
+}
+
+/// Virtual filesystems that can hang or make no sense to a whole-system scan
+const PSEUDO_FILESYSTEMS: [&str; 4] = ["/proc", "/sys", "/dev", "/run"];
+
+impl ScanOptions {
+    fn from_matches(matches: &clap::ArgMatches) -> Self {
+        Self {
+            top_files: if matches.is_present("top-files") {
+                Some(matches.value_of_t_or_exit::<usize>("top-files"))
+            } else {
+                None
+            },
+            top_dirs: if matches.is_present("top-dirs") {
+                Some(matches.value_of_t_or_exit::<usize>("top-dirs"))
+            } else {
+                None
+            },
+            max_entries: if matches.is_present("max-entries") {
+                Some(matches.value_of_t_or_exit::<usize>("max-entries"))
+            } else {
+                None
+            },
+            ignore_errors: matches.is_present("ignore-errors"),
+            acl_xattr_size: matches.is_present("acl-xattr-size"),
+            count_xattr_size: matches.is_present("count-xattr-size"),
+            newest_file: matches.is_present("show-newest-file")
+                || matches
+                    .values_of("columns")
+                    .into_iter()
+                    .flatten()
+                    .any(|name| name.starts_with("newest-")),
+            collect_sizes: (matches.is_present("show-p95-size")
+                || matches
+                    .values_of("columns")
+                    .into_iter()
+                    .flatten()
+                    .any(|name| name == "p95"))
+            .then(|| matches.value_of_t_or_exit("max-collect-files")),
+            accessed_file: matches.is_present("show-access-time")
+                || matches
+                    .values_of("columns")
+                    .into_iter()
+                    .flatten()
+                    .any(|name| name.starts_with("accessed-")),
+            oldest_file: matches.is_present("show-first-seen")
+                || matches
+                    .values_of("columns")
+                    .into_iter()
+                    .flatten()
+                    .any(|name| name.starts_with("oldest-")),
+            collect_files: matches.is_present("per-user-report")
+                || matches.is_present("emit-shell-script"),
+            by_month: matches.is_present("group-by-month"),
+            executable: if matches.is_present("executable-only") {
+                Some(true)
+            } else if matches.is_present("non-executable-only") {
+                Some(false)
+            } else {
+                None
+            },
+            globs: matches.values_of("glob").map(|patterns| {
+                let mut globs = globset::GlobSetBuilder::new();
+                for pattern in patterns {
+                    globs.add(globset::Glob::new(pattern).expect("validated by clap"));
+                }
+                globs.build().expect("validated by clap")
+            }),
+            hardlink_only: matches.is_present("hardlink-only"),
+            size_range: if matches.is_present("filter-size-range") {
+                Some(matches.value_of_t_or_exit("filter-size-range"))
+            } else {
+                None
+            },
+            min_age: duration(matches, "min-age"),
+            modified_before: matches.value_of("exclude-newer-than").map(|path| {
+                std::fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .unwrap_or_else(|err| {
+                        log::error!("could not read the mtime of {:?}: {}", path, err);
+                        std::process::exit(1);
+                    })
+            }),
+            max_age: duration(matches, "max-age"),
+            exclude_extensions: extensions(matches, "exclude-extension"),
+            include_extensions: extensions(matches, "include-extension"),
+            setuid_report: matches.is_present("setuid-report"),
+            world_writable: matches.is_present("report-world-writable"),
+            rename_hazards: matches.is_present("show-rename-hazards"),
+            long_paths: if matches.is_present("report-long-paths") {
+                Some(matches.value_of_t_or_exit::<usize>("report-long-paths"))
+            } else {
+                None
+            },
+            sticky_dirs: matches.is_present("report-sticky-dirs"),
+            sparse_files: matches.is_present("show-sparse-files"),
+            depth_histogram: matches.is_present("show-path-depth-histogram"),
+            size_histogram: matches.is_present("show-file-count-histogram")
+                || matches
+                    .values_of("columns")
+                    .into_iter()
+                    .flatten()
+                    .any(|name| name == "size-histogram"),
+            duplicate_content: matches
+                .is_present("report-duplicate-content")
+                .then(|| ContentHash::from_matches(matches)),
+            link_groups: if matches.is_present("report-by-link-group") {
+                Some(matches.value_of_t_or_exit::<usize>("report-by-link-group"))
+            } else {
+                None
+            },
+            by_toplevel: matches.is_present("summarize-by-toplevel"),
+            by_extension: matches.is_present("summarize-by-extension"),
+            projects: if cfg!(all(target_os = "linux", feature = "cgroup"))
+                && matches.is_present("cgroup-aware")
+            {
+                // each container's layer directory is a project named after it
+                let layers = cgroup::container_layers().unwrap_or_else(|err| {
+                    log::error!("could not list containers: {}", err);
+                    std::process::exit(1);
+                });
+                Some(ProjectMap::new(layers))
+            } else {
+                matches.value_of("project-map").map(|path| {
+                    load_project_map(path.as_ref()).unwrap_or_else(|err| {
+                        log::error!("could not read {:?}: {}", path, err);
+                        std::process::exit(1);
+                    })
+                })
+            },
+            cross_users: matches.values_of("cross-compare-users").map(|users| {
+                let users = users
+                    .map(|user| {
+                        resolve_user_id(user, &HashMap::new()).unwrap_or_else(|| {
+                            log::error!("unknown user {:?}", user);
+                            std::process::exit(1);
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                [users[0], users[1]]
+            }),
+            dir_count: matches.is_present("show-dir-count")
+                || matches
+                    .values_of("columns")
+                    .into_iter()
+                    .flatten()
+                    .any(|name| name == "dirs"),
+            acl_count: matches.is_present("show-acl")
+                || matches
+                    .values_of("columns")
+                    .into_iter()
+                    .flatten()
+                    .any(|name| name == "acl"),
+            cap_count: cfg!(target_os = "linux") && matches.is_present("count-cap-files")
+                || matches
+                    .values_of("columns")
+                    .into_iter()
+                    .flatten()
+                    .any(|name| name == "caps"),
+            mode_count: matches.is_present("show-mode")
+                || matches
+                    .values_of("columns")
+                    .into_iter()
+                    .flatten()
+                    .any(|name| name == "mode"),
+            empty_dirs: matches.is_present("show-empty-dirs")
+                || matches
+                    .values_of("columns")
+                    .into_iter()
+                    .flatten()
+                    .any(|name| name == "empty-dirs"),
+            gid_breakdown: matches.is_present("show-gid-breakdown"),
+            count_links: matches.is_present("count-links"),
+            count_reflinks: cfg!(all(target_os = "linux", feature = "btrfs"))
+                && matches.is_present("count-reflinks"),
+            ignore_immutable: cfg!(target_os = "linux") && matches.is_present("ignore-immutable"),
+            symlink_targets: matches.is_present("show-symlink-targets"),
+            record_inodes: matches.is_present("snapshot"),
+            walk: WalkOptions {
+                order: WalkOrder::from_matches(matches),
+                follow_links: matches.is_present("follow-links"),
+                same_file_system: matches.is_present("one-file-system"),
+                bind_mounts: cfg!(target_os = "linux") && matches.is_present("include-bind-mounts"),
+            },
+            progress: ProgressFormat::from_matches(matches),
+            devices: matches
+                .values_of("include-mount-point")
+                .into_iter()
+                .flatten()
+                .map(|path| match std::fs::metadata(path) {
+                    Ok(metadata) => metadata.dev(),
+                    Err(err) => {
+                        log::error!("could not stat {}: {}", path, err);
+                        std::process::exit(1);
+                    }
+                })
+                .collect(),
+            deadline: if matches.is_present("timeout") {
+                let timeout = matches.value_of_t_or_exit::<u64>("timeout");
+                Some(std::time::Instant::now() + std::time::Duration::from_secs(timeout))
+            } else {
+                None
+            },
+            sample: matches.value_of("sample").map(|fraction| {
+                sample::parse_fraction(fraction).expect("checked by the validator")
+            }),
+            max_memory: matches.is_present("max-memory").then(|| {
+                matches
+                    .value_of_t_or_exit::<ParsedSize>("max-memory")
+                    .bytes()
+            }),
+            exclude: matches
+                .values_of("exclude")
+                .into_iter()
+                .flatten()
+                .map(std::path::PathBuf::from)
+                .collect(),
+            exclude_globs: matches.value_of("exclude-pattern-file").map(|path| {
+                load_exclude_patterns(path.as_ref()).unwrap_or_else(|err| {
+                    log::error!("could not read {:?}: {}", path, err);
+                    std::process::exit(1);
+                })
+            }),
+            uid_filter: matches.value_of("filter-uid-file").map(|path| {
+                let uid_map = match matches.value_of("uid-map") {
+                    Some(path) => load_uid_map(path.as_ref()).unwrap_or_else(|err| {
+                        log::error!("could not read {:?}: {}", path, err);
+                        std::process::exit(1);
+                    }),
+                    None => HashMap::new(),
+                };
+                load_uid_filter(path.as_ref(), &uid_map).unwrap_or_else(|err| {
+                    log::error!("could not read {:?}: {}", path, err);
+                    std::process::exit(1);
+                })
+            }),
+            link_dest: matches
+                .value_of("link-dest")
+                .map(|dir| linked_inodes(dir.as_ref())),
+            excluded_uids: matches.values_of_t("exclude-uid-range").unwrap_or_default(),
+            auto_exclude: if matches.is_present("auto-exclude-proc-sys") {
+                Some(true)
+            } else if matches.is_present("no-auto-exclude") {
+                Some(false)
+            } else {
+                None
+            },
+        }
+    }
+
+    fn on_included_device(&self, metadata: &std::fs::Metadata) -> bool {
+        self.devices.is_empty() || self.devices.contains(&metadata.dev())
+    }
+
+    /// Whether `--filter-uid-file` and `--exclude-uid-range` let `owner`'s
+    /// usage be counted
+    fn counts_owner(&self, owner: UserId) -> bool {
+        self.uid_filter
+            .as_ref()
+            .is_none_or(|filter| filter.counts(owner))
+            && !self.excluded_uids.iter().any(|range| range.contains(owner))
+    }
+
+    fn auto_exclude(&self, root: &std::path::Path) -> bool {
+        self.auto_exclude
+            .unwrap_or_else(|| root == std::path::Path::new("/"))
+    }
+
+    /// `--exclude` paths spelled the way a walk of `root` spells what it finds:
+    /// relative ones are under `root`, and absolute ones are made relative
+    /// again when `root` is. Warns about any that can't be under `root`.
+    fn excludes_under(&self, root: &std::path::Path) -> Vec<std::path::PathBuf> {
+        let absolute_root = std::path::absolute(root).unwrap_or_else(|_| root.to_owned());
+        self.exclude
+            .iter()
+            .map(|excluded| {
+                let resolved = match excluded.strip_prefix(&absolute_root) {
+                    Ok(rest) if root.is_relative() => root.join(rest),
+                    _ => root.join(excluded),
+                };
+                if !resolved.starts_with(root) {
+                    log::warn!(
+                        "--exclude {} is not under {}, so it excludes nothing there",
+                        excluded.display(),
+                        root.display()
+                    );
+                }
+                resolved
+            })
+            .collect()
+    }
+
+    /// Whether the walk should skip `path` (and, for directories, its
+    /// contents), given the `--exclude` paths from [`Self::excludes_under`]
+    fn excluded(
+        &self,
+        path: &std::path::Path,
+        root: &std::path::Path,
+        excludes: &[std::path::PathBuf],
+    ) -> bool {
+        excludes.iter().any(|excluded| path == excluded)
+            || self.exclude_globs.as_ref().is_some_and(|globs| {
+                globs.is_match(path) || path.file_name().is_some_and(|name| globs.is_match(name))
+            })
+            || (self.auto_exclude(root)
+                && PSEUDO_FILESYSTEMS
+                    .iter()
+                    .any(|pseudo| path == std::path::Path::new(pseudo)))
+    }
+}
+
+/// Whether a file name has anything but printable ASCII in it: control
+/// characters like tabs and newlines, which break line-based pipelines, or
+/// anything else a shell script might mangle
+fn is_rename_hazard(name: &std::ffi::OsStr) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    name.as_bytes().iter().any(|b| !(b' '..=b'~').contains(b))
+}
+
+/// `path` with backslashes, control characters and anything outside ASCII
+/// escaped, so that it's one printable line whatever its bytes
+fn escape_path(path: &std::path::Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut escaped = String::new();
+    for &b in path.as_os_str().as_bytes() {
+        match b {
+            b'\\' => escaped.push_str("\\\\"),
+            b'\t' => escaped.push_str("\\t"),
+            b'\n' => escaped.push_str("\\n"),
+            b' '..=b'~' => escaped.push(b as char),
+            _ => escaped.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    escaped
+}
+
+/// `path`'s extension, lowercased and without the leading `.`
+fn lowercase_extension(path: &std::path::Path) -> Option<String> {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+}
+
+fn extensions(matches: &clap::ArgMatches, name: &str) -> Vec<String> {
+    matches
+        .values_of(name)
+        .into_iter()
+        .flatten()
+        .map(|ext| ext.trim_start_matches('.').to_lowercase())
+        .collect()
+}
+
+/// A share of the total between 0 and 1, both included
+fn parse_ratio(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        _ => Err(format!("{:?} is not a ratio between 0.0 and 1.0", s)),
+    }
+}
+
+/// A `humantime` duration argument, if given
+fn duration(matches: &clap::ArgMatches, name: &str) -> Option<std::time::Duration> {
+    matches
+        .value_of(name)
+        .map(|value| humantime::parse_duration(value).expect("validated by clap"))
+}
+
+/// Whether a regular file passes all of the filters in `options`
+fn include_file(
+    path: &std::path::Path,
+    metadata: &std::fs::Metadata,
+    options: &ScanOptions,
+) -> bool {
+    if !options.on_included_device(metadata) {
+        return false;
+    }
+    if let Some(executable) = options.executable {
+        if (metadata.mode() & 0o111 != 0) != executable {
+            return false;
+        }
+    }
+    if !include_listed(path, metadata.len(), metadata.nlink(), options) {
+        return false;
+    }
+    if (options.min_age.is_some() || options.max_age.is_some())
+        && !age_in_range(metadata.modified().ok(), options)
+    {
+        return false;
+    }
+    if let Some(cutoff) = options.modified_before {
+        if !metadata.modified().is_ok_and(|mtime| mtime < cutoff) {
+            return false;
+        }
+    }
+    if options
+        .link_dest
+        .as_ref()
+        .is_some_and(|inodes| inodes.contains(&(metadata.dev(), metadata.ino())))
+    {
+        return false;
+    }
+    // last, since it's an open(2) and an ioctl(2) rather than a look at the metadata
+    if options.ignore_immutable {
+        match fsflags::is_immutable(path) {
+            Ok(immutable) => return !immutable,
+            Err(err) => log::debug!("could not read flags of {}: {}", path.display(), err),
+        }
+    }
+    true
+}
+
+/// Whether a file last modified at `mtime` is within `--min-age` and
+/// `--max-age`, both inclusive
+fn age_in_range(mtime: Option<SystemTime>, options: &ScanOptions) -> bool {
+    // files from the future are brand new; files without an mtime can't match
+    let Some(age) = mtime.map(|mtime| mtime.elapsed().unwrap_or_default()) else {
+        return false;
+    };
+    !(options.min_age.is_some_and(|min| age < min) || options.max_age.is_some_and(|max| age > max))
+}
+
+/// The filters that only need a file's path, size and link count, which is
+/// all a `--stdin` listing has to go on
+fn include_listed(path: &std::path::Path, size: u64, nlink: u64, options: &ScanOptions) -> bool {
+    if let Some(globs) = &options.globs {
+        if !path.file_name().is_some_and(|name| globs.is_match(name)) {
+            return false;
+        }
+    }
+    if options.hardlink_only && nlink == 1 {
+        return false;
+    }
+    if let Some(range) = options.size_range {
+        if !range.contains(size) {
+            return false;
+        }
+    }
+    if !options.exclude_extensions.is_empty() || !options.include_extensions.is_empty() {
+        let extension = lowercase_extension(path);
+        let listed = |list: &[String]| extension.as_ref().is_some_and(|ext| list.contains(ext));
+        if listed(&options.exclude_extensions) {
+            return false;
+        }
+        if !options.include_extensions.is_empty() && !listed(&options.include_extensions) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Whether more than a tenth of a file of apparent size `size` is left
+/// unallocated, for `--show-sparse-files`
+fn is_sparse(size: u64, allocated: u64) -> bool {
+    // in u128, since sizes of a few exabytes are easy to make with truncate(1)
+    allocated as u128 * 10 < size as u128 * 9
+}
+
+const ACL_ACCESS_XATTR: &str = "system.posix_acl_access";
+
+/// Whether `path` has an access ACL; the kernel only stores one when it
+/// says more than the mode bits do
+fn has_acl(path: &std::path::Path) -> bool {
+    xattr::get(path, ACL_ACCESS_XATTR).is_ok_and(|acl| acl.is_some_and(|acl| !acl.is_empty()))
+}
+
+#[cfg(target_os = "linux")]
+const CAPABILITY_XATTR: &str = "security.capability";
+
+/// Whether `path` has file capabilities, which `setcap` stores as an xattr
+#[cfg(target_os = "linux")]
+fn has_capabilities(path: &std::path::Path) -> bool {
+    xattr::get(path, CAPABILITY_XATTR).is_ok_and(|caps| caps.is_some())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn has_capabilities(_path: &std::path::Path) -> bool {
+    false
+}
+
+/// How many bytes a single file contributes to its owner's total
+fn file_usage(path: &std::path::Path, metadata: &std::fs::Metadata, options: &ScanOptions) -> u64 {
+    let mut usage = metadata.size();
+    if options.count_xattr_size {
+        // the ACL is just another xattr, so this already covers --acl-xattr-size
+        if let Ok(names) = xattr::list(path) {
+            for name in names {
+                if let Ok(Some(value)) = xattr::get(path, &name) {
+                    usage += value.len() as u64;
+                }
+            }
+        }
+    } else if options.acl_xattr_size {
+        if let Ok(Some(acl)) = xattr::get(path, ACL_ACCESS_XATTR) {
+            usage += acl.len() as u64;
+        }
+    }
+    usage
+}
+
+/// How many depths `--show-path-depth-histogram` tells apart; files any
+/// deeper are counted with the deepest
+const HISTOGRAM_DEPTHS: usize = 10;
+
+/// `(label, upper bound)` of each bucket of `--show-file-count-histogram`;
+/// the last holds everything else
+const SIZE_BUCKETS: [(&str, u64); 4] = [
+    ("<1K", 1 << 10),
+    ("1K-1M", 1 << 20),
+    ("1M-1G", 1 << 30),
+    ("1G+", u64::MAX),
+];
+
+/// Stands in for everyone `--max-entries` left out; `(uid_t)-1` is never
+/// anyone's UID
+const OTHER_USERS: UserId = UserId(u32::MAX);
+
+/// Keep the `max` largest users in `by_user`, adding up everyone else under
+/// `OTHER_USERS`
+fn keep_largest(by_user: &mut HashMap<UserId, UserStats>, max: usize) {
+    let smaller = by_user
+        .iter()
+        .filter(|&(&user_id, _)| user_id != OTHER_USERS)
+        .map(|(&user_id, stats)| (Reverse(stats.bytes), user_id))
+        .sorted()
+        .skip(max)
+        .map(|(_, user_id)| user_id)
+        .collect::<Vec<_>>();
+    for user_id in smaller {
+        let stats = by_user.remove(&user_id).expect("listed above");
+        by_user.entry(OTHER_USERS).or_default().absorb(stats);
+    }
+}
+
+/// Everything accumulated about one user's files
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+struct UserStats {
+    bytes: u64,
+    /// Space actually allocated on disk (`st_blocks`), which differs from the
+    /// apparent size for sparse and compressed files
+    allocated: u64,
+    newest: Option<(std::time::SystemTime, std::path::PathBuf)>,
+    oldest: Option<(std::time::SystemTime, std::path::PathBuf)>,
+    accessed: Option<(std::time::SystemTime, std::path::PathBuf)>,
+    files: u64,
+    dirs: u64,
+    /// Directories with nothing in them, for `--show-empty-dirs`
+    #[serde(default)]
+    empty_dirs: u64,
+    /// Total `st_nlink` over every counted file, for the average
+    sum_nlink: u64,
+    /// Usage of hard links to files that were already counted
+    linked: u64,
+    /// Distinct `(device, inode)` pairs among the counted files
+    #[serde(default)]
+    inodes: u64,
+    /// Directory entries for the counted files, whether or not they're
+    /// links to an inode that was already seen
+    #[serde(default)]
+    links: u64,
+    /// Counted files with the setuid bit set
+    #[serde(default)]
+    setuid: u64,
+    /// Counted files with a non-trivial access ACL, for `--show-acl`
+    #[serde(default)]
+    acl: u64,
+    /// Counted files with capabilities, for `--count-cap-files`
+    #[serde(default)]
+    caps: u64,
+    /// Counted files by permission bits (`mode & 0o7777`), for `--show-mode`
+    #[serde(default)]
+    modes: HashMap<u32, u64>,
+    /// Usage in extents shared with other files, for `--count-reflinks`
+    reflinked: u64,
+    /// Counted files at each depth below the scan root, from 1, with the
+    /// last bucket also holding everything deeper; for
+    /// `--show-path-depth-histogram`
+    #[serde(default)]
+    depths: Vec<u64>,
+    /// Counted files in each of `SIZE_BUCKETS`, for
+    /// `--show-file-count-histogram`
+    #[serde(default)]
+    size_buckets: [u64; SIZE_BUCKETS.len()],
+    /// Every file, for `--per-user-report`
+    listing: Vec<(u64, std::path::PathBuf)>,
+    /// Every file size, for percentiles, unless there were too many
+    sizes: Vec<u64>,
+    too_many_sizes: bool,
+}
+
+impl UserStats {
+    /// Scale the totals up by `factor`, for what `--sample` left out; the
+    /// per-file details are of files that really were seen, so they stay
+    fn scale(&mut self, factor: f64) {
+        for total in [
+            &mut self.bytes,
+            &mut self.allocated,
+            &mut self.files,
+            &mut self.sum_nlink,
+            &mut self.linked,
+            &mut self.reflinked,
+            &mut self.inodes,
+            &mut self.links,
+            &mut self.setuid,
+            &mut self.acl,
+            &mut self.caps,
+        ] {
+            *total = (*total as f64 * factor).round() as u64;
+        }
+        for files in self.depths.iter_mut().chain(&mut self.size_buckets) {
+            *files = (*files as f64 * factor).round() as u64;
+        }
+    }
+
+    /// Add in `other`'s totals; its per-file details are dropped, as what's
+    /// left of them wouldn't describe anyone
+    fn absorb(&mut self, other: UserStats) {
+        for (total, more) in [
+            (&mut self.bytes, other.bytes),
+            (&mut self.allocated, other.allocated),
+            (&mut self.files, other.files),
+            (&mut self.dirs, other.dirs),
+            (&mut self.empty_dirs, other.empty_dirs),
+            (&mut self.sum_nlink, other.sum_nlink),
+            (&mut self.linked, other.linked),
+            (&mut self.reflinked, other.reflinked),
+            (&mut self.inodes, other.inodes),
+            (&mut self.links, other.links),
+            (&mut self.setuid, other.setuid),
+            (&mut self.acl, other.acl),
+            (&mut self.caps, other.caps),
+        ] {
+            *total += more;
+        }
+        if self.depths.len() < other.depths.len() {
+            self.depths.resize(other.depths.len(), 0);
+        }
+        for (total, more) in self.depths.iter_mut().zip(other.depths) {
+            *total += more;
+        }
+        for (total, more) in self.size_buckets.iter_mut().zip(other.size_buckets) {
+            *total += more;
+        }
+        for (mode, files) in other.modes {
+            *self.modes.entry(mode).or_default() += files;
+        }
+        self.newest = None;
+        self.oldest = None;
+        self.accessed = None;
+        self.listing = Vec::new();
+        self.sizes = Vec::new();
+        self.too_many_sizes = true;
+    }
+
+    fn see_depth(&mut self, depth: usize) {
+        let bucket = depth.clamp(1, HISTOGRAM_DEPTHS) - 1;
+        if self.depths.len() <= bucket {
+            self.depths.resize(bucket + 1, 0);
+        }
+        self.depths[bucket] += 1;
+    }
+
+    fn see_bucket(&mut self, size: u64) {
+        let bucket = SIZE_BUCKETS
+            .iter()
+            .position(|&(_, bound)| size < bound)
+            .unwrap_or(SIZE_BUCKETS.len() - 1);
+        self.size_buckets[bucket] += 1;
+    }
+
+    fn see_size(&mut self, size: u64, limit: usize) {
+        if self.too_many_sizes {
+            return;
+        }
+        if self.sizes.len() < limit {
+            self.sizes.push(size);
+        } else {
+            self.too_many_sizes = true;
+            self.sizes = Vec::new();
+        }
+    }
+
+    /// The permission bits most of the counted files have; on a tie, the
+    /// lowest (the least permissive, more or less)
+    fn common_mode(&self) -> Option<u32> {
+        self.modes
+            .iter()
+            .max_by(|(a_mode, a), (b_mode, b)| a.cmp(b).then(b_mode.cmp(a_mode)))
+            .map(|(&mode, _)| mode)
+    }
+
+    /// The nearest-rank 95th percentile of `sizes`, which this reorders
+    fn p95(&mut self) -> Option<u64> {
+        if self.too_many_sizes || self.sizes.is_empty() {
+            return None;
+        }
+        let rank = (self.sizes.len() * 95).div_ceil(100);
+        let (_, p95, _) = self.sizes.select_nth_unstable(rank.max(1) - 1);
+        Some(*p95)
+    }
+
+    fn see_mtime(&mut self, path: &std::path::Path, mtime: std::time::SystemTime) {
+        if self
+            .newest
+            .as_ref()
+            .is_none_or(|(newest, _)| mtime > *newest)
+        {
+            self.newest = Some((mtime, path.to_owned()));
+        }
+    }
+
+    fn see_atime(&mut self, path: &std::path::Path, atime: std::time::SystemTime) {
+        if self
+            .accessed
+            .as_ref()
+            .is_none_or(|(accessed, _)| atime > *accessed)
+        {
+            self.accessed = Some((atime, path.to_owned()));
+        }
+    }
+
+    fn see_older(&mut self, path: &std::path::Path, mtime: std::time::SystemTime) {
+        if self
+            .oldest
+            .as_ref()
+            .is_none_or(|(oldest, _)| mtime < *oldest)
+        {
+            self.oldest = Some((mtime, path.to_owned()));
+        }
+    }
+}
+
+/// An entry that could not be read during the walk, and why
+#[derive(Debug)]
+struct SkippedEntry {
+    path: std::path::PathBuf,
+    reason: std::io::Error,
+}
+
+impl From<walkdir::Error> for SkippedEntry {
+    fn from(err: walkdir::Error) -> Self {
+        let path = err.path().map(|p| p.to_owned()).unwrap_or_default();
+        let reason = if err.io_error().is_some() {
+            err.into_io_error().expect("checked above")
+        } else {
+            // symlink loops are the only errors without an underlying io::Error
+            std::io::Error::other(err.to_string())
+        };
+        Self { path, reason }
+    }
+}
+
+struct Scan {
+    by_user: HashMap<UserId, UserStats>,
+    /// Usage by user and month of last modification, for `--group-by-month`
+    by_month: HashMap<(UserId, YearMonth), u64>,
+    /// Usage under each immediate child of the scan root, regardless of owner;
+    /// files directly in the root are counted under the root itself
+    by_toplevel: HashMap<std::path::PathBuf, u64>,
+    /// `(bytes, files)` by lowercased extension, regardless of owner, for
+    /// `--summarize-by-extension`; files without one are under `""`
+    by_extension: HashMap<String, (u64, u64)>,
+    /// Usage by project (if any) and user, for `--group-by-project`
+    by_project: HashMap<(Option<String>, UserId), u64>,
+    /// `(bytes, files)` by user and group, for `--show-gid-breakdown`
+    by_gid: HashMap<(UserId, GroupId), (u64, u64)>,
+    /// Usage of each of the `--cross-compare-users`, by the directory
+    /// their files are directly in
+    by_dir_pair: HashMap<std::path::PathBuf, [u64; 2]>,
+    top_files: Option<TopFiles>,
+    /// `(bytes, path)` of the `--top-dirs` directories with the most under
+    /// them, largest first
+    top_dirs: Vec<(u64, std::path::PathBuf)>,
+    /// `(mode, uid, path)` of every setuid/setgid file, for `--setuid-report`
+    setuid_files: Vec<(u32, UserId, std::path::PathBuf)>,
+    /// `(mode, uid, path)` of every world-writable file, for
+    /// `--report-world-writable`
+    world_writable: Vec<(u32, UserId, std::path::PathBuf)>,
+    /// `(uid, path)` of everything whose name isn't all printable ASCII, for
+    /// `--show-rename-hazards`
+    rename_hazards: Vec<(UserId, std::path::PathBuf)>,
+    /// `(uid, path)` of everything with a path longer than
+    /// `--report-long-paths`
+    long_paths: Vec<(UserId, std::path::PathBuf)>,
+    /// `(uid, apparent size, allocated bytes, path)` of every file with
+    /// more than a tenth of it unallocated, for `--show-sparse-files`
+    sparse_files: Vec<(UserId, u64, u64, std::path::PathBuf)>,
+    /// Usage by user under each directory with the sticky bit set, for
+    /// `--report-sticky-dirs`
+    sticky_dirs: HashMap<std::path::PathBuf, HashMap<UserId, u64>>,
+    /// `(device, inode, owner)` of every file, for `--snapshot`, so that
+    /// `--show-owner-changes` can tell when files change hands
+    inodes: Vec<(u64, u64, UserId)>,
+    /// For `--report-duplicate-content`
+    duplicates: Vec<DuplicateGroup>,
+    /// For `--report-by-link-group`, largest first
+    link_groups: Vec<LinkGroup>,
+    skipped: Vec<SkippedEntry>,
+    started: std::time::SystemTime,
+    /// How long the scan took, which is not how long ago it `started` once
+    /// it's been through `--snapshot` and `--restore`
+    elapsed: std::time::Duration,
+    /// Whether `--timeout` cut the walk short
+    timed_out: bool,
+    /// The fraction of files counted, if `--sample` left some out and the
+    /// totals are estimates
+    sampled: Option<f64>,
+    /// Every directory seen, whoever owns it
+    dirs: u64,
+    /// Whether any file's atime differed from its mtime, which they all share
+    /// when the filesystem is mounted `noatime`
+    atime_differs: bool,
+    profile: Profile,
+}
+
+impl Scan {
+    /// Everyone whose files might be shown anywhere in the report, including
+    /// the side listings that ignore the filters
+    fn owners(&self) -> impl Iterator<Item = UserId> + '_ {
+        self.by_user
+            .keys()
+            .copied()
+            .chain(self.by_month.keys().map(|&(user_id, _)| user_id))
+            .chain(self.by_project.keys().map(|&(_, user_id)| user_id))
+            .chain(self.top_files.iter().flat_map(TopFiles::owners))
+            .chain(self.setuid_files.iter().map(|&(_, user_id, _)| user_id))
+            .chain(self.world_writable.iter().map(|&(_, user_id, _)| user_id))
+            .chain(self.rename_hazards.iter().map(|&(user_id, _)| user_id))
+            .chain(self.long_paths.iter().map(|&(user_id, _)| user_id))
+            .chain(self.sparse_files.iter().map(|&(user_id, ..)| user_id))
+            .chain(
+                self.sticky_dirs
+                    .values()
+                    .flat_map(|usage| usage.keys().copied()),
+            )
+            .chain(
+                self.duplicates
+                    .iter()
+                    .flat_map(|group| group.files.iter().map(|&(user_id, _)| user_id)),
+            )
+            .chain(
+                self.link_groups
+                    .iter()
+                    .flat_map(|group| group.paths.iter().map(|&(user_id, _)| user_id)),
+            )
+    }
+}
+
+/// Where the time went, for `--profile`
+#[derive(Debug, Default)]
+struct Profile {
+    /// Reading directories (or the `--stdin` listing)
+    walking: std::time::Duration,
+    /// `stat(2)`ing what was found
+    metadata: std::time::Duration,
+    lookups: std::time::Duration,
+    output: std::time::Duration,
+}
+
+impl Profile {
+    fn add(&mut self, other: &Profile) {
+        self.walking += other.walking;
+        self.metadata += other.metadata;
+        self.lookups += other.lookups;
+        self.output += other.output;
+    }
+
+    fn print(&self, total: std::time::Duration) {
+        eprintln!("--- profile");
+        for (phase, time) in [
+            ("total", total),
+            ("walking", self.walking),
+            ("metadata", self.metadata),
+            ("username lookups", self.lookups),
+            ("output", self.output),
+        ] {
+            eprintln!("{:<18}{:.3?}", phase, time);
+        }
+    }
+}
+
+fn scan(path: &std::path::Path, options: &ScanOptions) -> Scan {
+    let started = std::time::SystemTime::now();
+    let mut by_user: HashMap<UserId, UserStats> = HashMap::new();
+    let mut by_month: HashMap<(UserId, YearMonth), u64> = HashMap::new();
+    let mut by_toplevel: HashMap<std::path::PathBuf, u64> = HashMap::new();
+    let mut by_extension: HashMap<String, (u64, u64)> = HashMap::new();
+    let mut by_project: HashMap<(Option<String>, UserId), u64> = HashMap::new();
+    let mut by_gid: HashMap<(UserId, GroupId), (u64, u64)> = HashMap::new();
+    let mut by_dir_pair: HashMap<std::path::PathBuf, [u64; 2]> = HashMap::new();
+    let mut top_files = options.top_files.map(TopFiles::new);
+    // one total per directory rather than per file, so this stays far
+    // smaller than the tree
+    let mut dir_totals: HashMap<std::path::PathBuf, u64> = HashMap::new();
+    let mut setuid_files = Vec::new();
+    let mut world_writable = Vec::new();
+    let mut rename_hazards = Vec::new();
+    let mut long_paths = Vec::new();
+    let mut sparse_files = Vec::new();
+    let mut inodes = Vec::new();
+    let mut sticky_dirs: HashMap<std::path::PathBuf, HashMap<UserId, u64>> = HashMap::new();
+    let mut candidates = options
+        .duplicate_content
+        .map(|_| duplicates::Candidates::default());
+    let mut link_groups = options.link_groups.map(|_| LinkGroups::default());
+    let mut skipped = Vec::new();
+    let mut timed_out = false;
+    // (device, inode) of every multiply-linked file counted so far
+    let mut seen_links = std::collections::HashSet::new();
+    let mut profile = Profile::default();
+    let mut dirs = 0;
+    let mut atime_differs = false;
+    let excludes = options.excludes_under(path);
+    let mut walker = walk::walk(path, options.walk, |entry| {
+        options.excluded(entry, path, &excludes)
+    });
+    let mut progress = Progress::new(options.progress);
+    let mut memory = MemoryLimit::new(options.max_memory);
+    let mut sampler = options.sample.map(|fraction| {
+        let seed = std::fs::metadata(path).map_or(0, |metadata| metadata.ino());
+        Sampler::new(fraction, seed)
+    });
+    log::debug!("scanning {}", path.display());
+    loop {
+        // folding the smallest users away in batches rather than one at a
+        // time, which would sort them all for every new user
+        if let Some(max) = options.max_entries.filter(|&max| by_user.len() > max * 2) {
+            keep_largest(&mut by_user, max);
+        }
+        let walk_started = std::time::Instant::now();
+        let Some(entry) = walker.next() else {
+            break;
+        };
+        profile.walking += walk_started.elapsed();
+        if options
+            .deadline
+            .is_some_and(|deadline| std::time::Instant::now() >= deadline)
+        {
+            timed_out = true;
+            break;
+        }
+        let metadata_started = std::time::Instant::now();
+        let entry = entry.and_then(|e| match e.metadata() {
+            Ok(metadata) => Ok((e, metadata)),
+            Err(reason) => Err(SkippedEntry {
+                path: e.path().to_owned(),
+                reason,
+            }),
+        });
+        profile.metadata += metadata_started.elapsed();
+        let (entry, metadata) = match entry {
+            Ok(pair) => pair,
+            Err(err) => {
+                skipped.push(err);
+                continue;
+            }
+        };
+        // when following links, `metadata` describes the target, but unless
+        // asked otherwise the usage still belongs to whoever made the link
+        let owner = UserId(if entry.path_is_symlink() && !options.symlink_targets {
+            std::fs::symlink_metadata(entry.path()).map_or(metadata.uid(), |link| link.uid())
+        } else {
+            metadata.uid()
+        });
+        if metadata.is_dir() {
+            log::trace!("entering {}", entry.path().display());
+            dirs += 1;
+            if options.dir_count
+                && options.on_included_device(&metadata)
+                && options.counts_owner(owner)
+            {
+                by_user.entry(owner).or_default().dirs += 1;
+            }
+            // another readdir(2), but the walk only hands out what it found
+            // in a directory, which can't tell an empty one from an excluded one
+            if options.empty_dirs
+                && options.on_included_device(&metadata)
+                && options.counts_owner(owner)
+                && std::fs::read_dir(entry.path())
+                    .is_ok_and(|mut children| children.next().is_none())
+            {
+                by_user.entry(owner).or_default().empty_dirs += 1;
+            }
+            if options.sticky_dirs && metadata.mode() & 0o1000 != 0 {
+                sticky_dirs.insert(entry.path().to_owned(), HashMap::new());
+            }
+        }
+        // this is a security report rather than a usage one, so it ignores the filters
+        if options.setuid_report && metadata.is_file() && metadata.mode() & 0o6000 != 0 {
+            setuid_files.push((
+                metadata.mode(),
+                UserId(metadata.uid()),
+                entry.path().to_owned(),
+            ));
+        }
+        if options.world_writable && metadata.is_file() && metadata.mode() & 0o002 != 0 {
+            world_writable.push((
+                metadata.mode(),
+                UserId(metadata.uid()),
+                entry.path().to_owned(),
+            ));
+        }
+        if options.rename_hazards
+            && entry.depth() > 0
+            && entry.path().file_name().is_some_and(is_rename_hazard)
+        {
+            rename_hazards.push((UserId(metadata.uid()), entry.path().to_owned()));
+        }
+        if options
+            .long_paths
+            .is_some_and(|limit| entry.path().as_os_str().len() > limit)
+        {
+            long_paths.push((UserId(metadata.uid()), entry.path().to_owned()));
+        }
+        if metadata.is_file()
+            && options.counts_owner(owner)
+            && include_file(entry.path(), &metadata, options)
+        {
+            if sampler.as_mut().is_some_and(|sampler| !sampler.keep()) {
+                continue;
+            }
+            let usage = file_usage(entry.path(), &metadata, options);
+            let collecting = memory.allows_more();
+            if options.record_inodes && collecting {
+                inodes.push((metadata.dev(), metadata.ino(), owner));
+            }
+            if let Some(link_groups) = link_groups
+                .as_mut()
+                .filter(|_| metadata.nlink() > 1 && collecting)
+            {
+                link_groups.push(entry.path(), &metadata);
+            }
+            let stats = by_user.entry(owner).or_default();
+            stats.links += 1;
+            if metadata.nlink() > 1 && !seen_links.insert((metadata.dev(), metadata.ino())) {
+                stats.linked += usage;
+                if !options.count_links {
+                    continue;
+                }
+            } else {
+                stats.inodes += 1;
+            }
+            stats.bytes += usage;
+            stats.files += 1;
+            if metadata.mode() & 0o4000 != 0 {
+                stats.setuid += 1;
+            }
+            if options.acl_count && has_acl(entry.path()) {
+                stats.acl += 1;
+            }
+            if options.cap_count && has_capabilities(entry.path()) {
+                stats.caps += 1;
+            }
+            if options.mode_count {
+                *stats.modes.entry(metadata.mode() & 0o7777).or_default() += 1;
+            }
+            if options.depth_histogram {
+                stats.see_depth(entry.depth());
+            }
+            if options.size_histogram {
+                stats.see_bucket(usage);
+            }
+            if options.gid_breakdown {
+                let (bytes, files) = by_gid.entry((owner, GroupId(metadata.gid()))).or_default();
+                *bytes += usage;
+                *files += 1;
+            }
+            progress.file(usage);
+            stats.sum_nlink += metadata.nlink();
+            if let Some(limit) = options.collect_sizes.filter(|_| collecting) {
+                stats.see_size(usage, limit);
+            }
+            let allocated = metadata.blocks() * 512;
+            if options.sparse_files && collecting && is_sparse(metadata.size(), allocated) {
+                sparse_files.push((owner, metadata.size(), allocated, entry.path().to_owned()));
+            }
+            if options.count_reflinks {
+                match reflink::shared_bytes(entry.path(), metadata.len()) {
+                    Ok(shared) => stats.reflinked += shared,
+                    Err(err) => log::debug!(
+                        "could not map extents of {}: {}",
+                        entry.path().display(),
+                        err
+                    ),
+                }
+            }
+            stats.allocated += allocated;
+            if options.newest_file || options.oldest_file {
+                // some filesystems can't tell us; those files just don't compete
+                if let Ok(mtime) = metadata.modified() {
+                    if options.newest_file {
+                        stats.see_mtime(entry.path(), mtime);
+                    }
+                    if options.oldest_file {
+                        stats.see_older(entry.path(), mtime);
+                    }
+                }
+            }
+            if options.accessed_file {
+                if let Ok(atime) = metadata.accessed() {
+                    stats.see_atime(entry.path(), atime);
+                    atime_differs |= metadata.modified().is_ok_and(|mtime| mtime != atime);
+                }
+            }
+            if options.by_month {
+                if let Ok(mtime) = metadata.modified() {
+                    *by_month.entry((owner, mtime.into())).or_default() += usage;
+                }
+            }
+            if options.by_toplevel {
+                let toplevel = match entry.path().strip_prefix(path) {
+                    Ok(relative) if entry.depth() > 1 => {
+                        path.join(relative.components().next().expect("depth > 1"))
+                    }
+                    _ => path.to_owned(),
+                };
+                *by_toplevel.entry(toplevel).or_default() += usage;
+            }
+            if options.by_extension {
+                let extension = lowercase_extension(entry.path()).unwrap_or_default();
+                let (bytes, files) = by_extension.entry(extension).or_default();
+                *bytes += usage;
+                *files += 1;
+            }
+            if let Some(projects) = &options.projects {
+                let project = projects.project(entry.path()).map(str::to_owned);
+                *by_project.entry((project, owner)).or_default() += usage;
+            }
+            if let Some(i) = options
+                .cross_users
+                .and_then(|users| users.iter().position(|&user_id| user_id == owner))
+            {
+                let dir = entry.path().parent().unwrap_or(entry.path());
+                by_dir_pair.entry(dir.to_owned()).or_default()[i] += usage;
+            }
+            // directories always come before what's in them, so every sticky
+            // ancestor is already known; nested ones each count the file
+            if !sticky_dirs.is_empty() {
+                for ancestor in entry.path().ancestors().skip(1) {
+                    if let Some(usage_by_user) = sticky_dirs.get_mut(ancestor) {
+                        *usage_by_user.entry(owner).or_default() += usage;
+                    }
+                }
+            }
+            if options.collect_files && collecting {
+                stats.listing.push((usage, entry.path().to_owned()));
+            }
+            if let Some(top_files) = top_files.as_mut() {
+                top_files.push(usage, entry.path(), owner, metadata.ino());
+            }
+            if options.top_dirs.is_some() {
+                add_to_ancestors(&mut dir_totals, path, entry.path(), usage);
+            }
+            if let Some(candidates) = candidates.as_mut().filter(|_| collecting) {
+                candidates.push(metadata.len(), owner, entry.path());
+            }
+        }
+    }
+    progress.finish();
+    if let Some(max) = options.max_entries {
+        keep_largest(&mut by_user, max);
+    }
+    if let Some(sampler) = &sampler {
+        let scale = sampler.scale();
+        by_user.values_mut().for_each(|stats| stats.scale(scale));
+        let scaled = |bytes: &mut u64| *bytes = (*bytes as f64 * scale).round() as u64;
+        by_month.values_mut().for_each(scaled);
+        by_toplevel.values_mut().for_each(scaled);
+        by_extension
+            .values_mut()
+            .flat_map(|(bytes, files)| [bytes, files])
+            .for_each(scaled);
+        dir_totals.values_mut().for_each(scaled);
+        by_project.values_mut().for_each(scaled);
+        by_gid
+            .values_mut()
+            .flat_map(|(bytes, files)| [bytes, files])
+            .for_each(scaled);
+        by_dir_pair.values_mut().flatten().for_each(scaled);
+        sticky_dirs
+            .values_mut()
+            .flat_map(|usage| usage.values_mut())
+            .for_each(scaled);
+    }
+    Scan {
+        sampled: options.sample,
+        by_user,
+        by_month,
+        by_gid,
+        by_toplevel,
+        by_extension,
+        by_project,
+        by_dir_pair,
+        top_files,
+        top_dirs: options
+            .top_dirs
+            .map_or(Vec::new(), |limit| largest_dirs(dir_totals, limit)),
+        setuid_files,
+        world_writable,
+        rename_hazards,
+        long_paths,
+        sparse_files,
+        sticky_dirs,
+        inodes,
+        duplicates: match (candidates, options.duplicate_content) {
+            (Some(candidates), Some(hash)) => candidates.find(hash),
+            _ => Vec::new(),
+        },
+        link_groups: match (link_groups, options.link_groups) {
+            (Some(link_groups), Some(limit)) => link_groups.largest(limit),
+            _ => Vec::new(),
+        },
+        skipped,
+        started,
+        elapsed: started.elapsed().unwrap_or_default(),
+        timed_out,
+        dirs,
+        atime_differs,
+        profile,
+    }
+}
+
+/// Like [`scan`], but over a `find -ls` (or `du`) listing instead of the
+/// filesystem.
+///
+/// Only what the listing records can be counted: sizes, allocation, link
+/// counts and directories. Hard links are recognized by inode alone, so a
+/// listing should not span filesystems unless `--count-links` is given.
+fn scan_listing<R: std::io::BufRead>(
+    reader: R,
+    format: StdinFormat,
+    options: &ScanOptions,
+    uid_map: &HashMap<UserId, String>,
+) -> Scan {
+    let started = std::time::SystemTime::now();
+    let mut by_user: HashMap<UserId, UserStats> = HashMap::new();
+    let mut top_files = options.top_files.map(TopFiles::new);
+    let mut dir_totals: HashMap<std::path::PathBuf, u64> = HashMap::new();
+    let mut skipped = Vec::new();
+    let mut seen_links = std::collections::HashSet::new();
+    let mut dirs = 0;
+    let mut memory = MemoryLimit::new(options.max_memory);
+    for record in listing::records(reader, format) {
+        let file = match record.and_then(|record| {
+            format
+                .parse(&record)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+        }) {
+            Ok(file) => file,
+            Err(reason) => {
+                skipped.push(SkippedEntry {
+                    path: "-".into(),
+                    reason,
+                });
+                continue;
+            }
+        };
+        let Some(owner) = resolve_user_id(&file.owner, uid_map) else {
+            skipped.push(SkippedEntry {
+                reason: std::io::Error::other(format!("unknown user {:?}", file.owner)),
+                path: file.path,
+            });
+            continue;
+        };
+        // the walk would never have gone into an excluded directory
+        if file
+            .path
+            .ancestors()
+            .any(|path| options.excluded(path, std::path::Path::new(""), &options.exclude))
+        {
+            continue;
+        }
+        match file.kind {
+            'd' => {
+                dirs += 1;
+                if options.dir_count && options.counts_owner(owner) {
+                    by_user.entry(owner).or_default().dirs += 1;
+                }
+            }
+            '-' if options.counts_owner(owner)
+                && include_listed(&file.path, file.size, file.nlink, options) =>
+            {
+                let stats = by_user.entry(owner).or_default();
+                stats.links += 1;
+                if file.nlink > 1 && !seen_links.insert(file.inode) {
+                    stats.linked += file.size;
+                    if !options.count_links {
+                        continue;
+                    }
+                } else {
+                    stats.inodes += 1;
+                }
+                stats.bytes += file.size;
+                stats.files += 1;
+                if file.setuid {
+                    stats.setuid += 1;
+                }
+                stats.sum_nlink += file.nlink;
+                let collecting = memory.allows_more();
+                if let Some(limit) = options.collect_sizes.filter(|_| collecting) {
+                    stats.see_size(file.size, limit);
+                }
+                stats.allocated += file.blocks * 1024;
+                if let Some(top_files) = top_files.as_mut() {
+                    top_files.push(file.size, &file.path, owner, file.inode);
+                }
+                if options.top_dirs.is_some() {
+                    // there's no scan root, so every directory in the listing
+                    // counts, up to `/`
+                    add_to_ancestors(
+                        &mut dir_totals,
+                        std::path::Path::new(""),
+                        &file.path,
+                        file.size,
+                    );
+                }
+                if options.collect_files && collecting {
+                    stats.listing.push((file.size, file.path));
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(max) = options.max_entries {
+        keep_largest(&mut by_user, max);
+    }
+    Scan {
+        by_user,
+        by_month: HashMap::new(),
+        by_toplevel: HashMap::new(),
+        by_extension: HashMap::new(),
+        by_project: HashMap::new(),
+        by_gid: HashMap::new(),
+        by_dir_pair: HashMap::new(),
+        top_files,
+        top_dirs: options
+            .top_dirs
+            .map_or(Vec::new(), |limit| largest_dirs(dir_totals, limit)),
+        setuid_files: Vec::new(),
+        world_writable: Vec::new(),
+        rename_hazards: Vec::new(),
+        long_paths: Vec::new(),
+        sparse_files: Vec::new(),
+        sticky_dirs: HashMap::new(),
+        inodes: Vec::new(),
+        sampled: None,
+        // the listed files may not even be on this machine
+        duplicates: Vec::new(),
+        link_groups: Vec::new(),
+        skipped,
+        started,
+        elapsed: started.elapsed().unwrap_or_default(),
+        timed_out: false,
+        dirs,
+        atime_differs: false,
+        profile: Profile {
+            walking: started.elapsed().unwrap_or_default(),
+            ..Profile::default()
+        },
+    }
+}
+
+/// SHA-256 over `uid<TAB>bytes` lines in UID order, so it only depends on the totals
+fn checksum(by_user: &HashMap<UserId, UserStats>) -> String {
+    use sha2::Digest;
+
+    let mut hasher = sha2::Sha256::new();
+    for (user_id, stats) in by_user.iter().sorted_by_key(|&(user_id, _)| user_id) {
+        hasher.update(format!("{}\t{}\n", user_id, stats.bytes));
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Everything that can be shown about one user
+struct UserRow {
+    user_id: UserId,
+    stats: UserStats,
+    /// Change since the previous `--watch` cycle
+    delta: Option<i64>,
+    /// Bytes per hour since the previous cycle, for `--show-recent-growth`
+    growth: Option<f64>,
+    relative: Option<f64>,
+    /// Total usage of all users, for percentages
+    total: u64,
+    /// Usage of this user and every larger one, for `--show-cumulative`
+    cumulative: u64,
+    p95: Option<u64>,
+}
+
+/// What the per-user table is ordered by, smallest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Size,
+    Count,
+    User,
+    Uid,
+    /// Average file size, with users who have no files first
+    BytesPerInode,
+}
+
+impl SortKey {
+    const NAMES: [&'static str; 5] = ["size", "count", "user", "uid", "bytes-per-inode"];
+
+    fn from_matches(matches: &clap::ArgMatches) -> Self {
+        // --bytes-per-inode changes the default, but not an explicit choice
+        if matches.is_present("bytes-per-inode") && matches.occurrences_of("sort-by") == 0 {
+            return Self::BytesPerInode;
+        }
+        match matches.value_of("sort-by") {
+            Some("bytes-per-inode") => Self::BytesPerInode,
+            Some("count") => Self::Count,
+            Some("user") => Self::User,
+            Some("uid") => Self::Uid,
+            _ => Self::Size,
+        }
+    }
+}
+
+/// The columns of the per-user report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UserColumn {
+    Size,
+    Allocated,
+    Efficiency,
+    User,
+    Uid,
+    Files,
+    BytesPerInode,
+    Dirs,
+    EmptyDirs,
+    Nlink,
+    Inodes,
+    Links,
+    LinksPerInode,
+    Setuid,
+    Acl,
+    Caps,
+    Mode,
+    P95,
+    SizeHistogram,
+    Compression,
+    Percent,
+    Cumulative,
+    CumulativePercent,
+    Delta,
+    Growth,
+    Saved,
+    LinkSaved,
+    Reflinked,
+    Relative,
+    NewestMtime,
+    NewestFile,
+    OldestMtime,
+    OldestFile,
+    AccessedAtime,
+    AccessedFile,
+}
+
+impl UserColumn {
+    const ALL: [Self; 35] = [
+        Self::Size,
+        Self::Allocated,
+        Self::Efficiency,
+        Self::User,
+        Self::Uid,
+        Self::Files,
+        Self::BytesPerInode,
+        Self::Dirs,
+        Self::EmptyDirs,
+        Self::Nlink,
+        Self::Inodes,
+        Self::Links,
+        Self::LinksPerInode,
+        Self::Setuid,
+        Self::Acl,
+        Self::Caps,
+        Self::Mode,
+        Self::P95,
+        Self::SizeHistogram,
+        Self::Compression,
+        Self::Percent,
+        Self::Cumulative,
+        Self::CumulativePercent,
+        Self::Delta,
+        Self::Growth,
+        Self::Saved,
+        Self::LinkSaved,
+        Self::Reflinked,
+        Self::Relative,
+        Self::NewestMtime,
+        Self::NewestFile,
+        Self::OldestMtime,
+        Self::OldestFile,
+        Self::AccessedAtime,
+        Self::AccessedFile,
+    ];
+    const NAMES: [&'static str; 35] = [
+        "size",
+        "allocated",
+        "efficiency",
+        "user",
+        "uid",
+        "files",
+        "bytes-per-inode",
+        "dirs",
+        "empty-dirs",
+        "nlink",
+        "inodes",
+        "links",
+        "links-per-inode",
+        "setuid",
+        "acl",
+        "caps",
+        "mode",
+        "p95",
+        "size-histogram",
+        "compression",
+        "percent",
+        "cumulative",
+        "cumulative-percent",
+        "delta",
+        "growth",
+        "saved",
+        "link-saved",
+        "reflinked",
+        "relative",
+        "newest-mtime",
+        "newest-file",
+        "oldest-mtime",
+        "oldest-file",
+        "accessed-atime",
+        "accessed-file",
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.column().name == name)
+    }
+
+    fn column(&self) -> Column {
+        match self {
+            Self::Size => Column::new("size", "bytes"),
+            Self::Allocated => Column::new("allocated", "allocated_bytes"),
+            Self::Efficiency => Column::new("efficiency", "allocation_efficiency_percent"),
+            Self::User => Column::new("user", "user"),
+            Self::Uid => Column::new("uid", "uid"),
+            Self::Files => Column::new("files", "files"),
+            Self::BytesPerInode => Column::new("bytes-per-inode", "bytes_per_inode"),
+            Self::Dirs => Column::new("dirs", "dirs"),
+            Self::EmptyDirs => Column::new("empty-dirs", "empty_dirs"),
+            Self::Nlink => Column::new("nlink", "average_nlink"),
+            Self::Inodes => Column::new("inodes", "unique_inodes"),
+            Self::Links => Column::new("links", "total_links"),
+            Self::LinksPerInode => Column::new("links-per-inode", "links_per_inode"),
+            Self::Setuid => Column::new("setuid", "setuid_files"),
+            Self::Acl => Column::new("acl", "acl_files"),
+            Self::Caps => Column::new("caps", "capability_files"),
+            Self::Mode => Column::new("mode", "common_mode"),
+            Self::P95 => Column::new("p95", "p95_bytes"),
+            Self::SizeHistogram => Column::new("size-histogram", "size_histogram"),
+            Self::Compression => Column::new("compression", "compression_ratio"),
+            Self::Percent => Column::new("percent", "percent"),
+            Self::Cumulative => Column::new("cumulative", "cumulative_bytes"),
+            Self::CumulativePercent => Column::new("cumulative-percent", "cumulative_percent"),
+            Self::Delta => Column::new("delta", "delta"),
+            Self::Growth => Column::new("growth", "growth_bytes_per_hour"),
+            Self::Saved => Column::new("saved", "saved"),
+            Self::LinkSaved => Column::new("link-saved", "hardlink_savings"),
+            Self::Reflinked => Column::new("reflinked", "reflinked_bytes"),
+            Self::Relative => Column::new("relative", "relative"),
+            Self::NewestMtime => Column::new("newest-mtime", "newest_mtime"),
+            Self::NewestFile => Column::path("newest-file", "newest_file"),
+            Self::OldestMtime => Column::new("oldest-mtime", "oldest_mtime"),
+            Self::OldestFile => Column::path("oldest-file", "oldest_file"),
+            Self::AccessedAtime => Column::new("accessed-atime", "accessed_atime"),
+            Self::AccessedFile => Column::path("accessed-file", "accessed_file"),
+        }
+    }
+
+    fn value(
+        &self,
+        row: &UserRow,
+        names: &UserNames,
+        report: &ReportOptions,
+        started: SystemTime,
+    ) -> Value {
+        let stats = &row.stats;
+        match self {
+            Self::Size => match report.cap_at {
+                Some(cap) if stats.bytes > cap => Value::Capped(cap),
+                _ => Value::Size(stats.bytes),
+            },
+            Self::Allocated => Value::Size(stats.allocated),
+            // over 100% is filesystem overhead, under is sparseness or compression
+            Self::Efficiency => match stats.bytes {
+                0 => Value::Missing,
+                bytes => Value::Percent(stats.allocated as f64 * 100.0 / bytes as f64),
+            },
+            Self::User => Value::Text(names.name(row.user_id)),
+            Self::Uid => names.uid(row.user_id),
+            Self::Files => Value::Count(stats.files),
+            Self::BytesPerInode => stats
+                .bytes
+                .checked_div(stats.files)
+                .map_or(Value::Missing, Value::Size),
+            Self::Dirs => Value::Count(stats.dirs),
+            Self::EmptyDirs => Value::Count(stats.empty_dirs),
+            Self::Nlink => match stats.files {
+                0 => Value::Missing,
+                files => Value::Mean(stats.sum_nlink as f64 / files as f64),
+            },
+            Self::Inodes => Value::Count(stats.inodes),
+            Self::Links => Value::Count(stats.links),
+            Self::LinksPerInode => match stats.inodes {
+                0 => Value::Missing,
+                inodes => Value::Ratio(stats.links as f64 / inodes as f64),
+            },
+            Self::Setuid => Value::Count(stats.setuid),
+            Self::Acl => Value::Count(stats.acl),
+            Self::Caps => Value::Count(stats.caps),
+            Self::Mode => stats
+                .common_mode()
+                .map_or(Value::Missing, |mode| Value::Text(format!("{:04o}", mode))),
+            Self::P95 => row.p95.map_or(Value::Missing, Value::Size),
+            Self::SizeHistogram => Value::Counts(stats.size_buckets.to_vec()),
+            // weighted by size, since it's the ratio of the totals
+            Self::Compression => match stats.allocated {
+                0 => Value::Missing,
+                allocated => Value::Ratio(stats.bytes as f64 / allocated as f64),
+            },
+            Self::Percent => match row.total {
+                0 => Value::Missing,
+                total => Value::Percent(stats.bytes as f64 * 100.0 / total as f64),
+            },
+            Self::Cumulative => Value::Size(row.cumulative),
+            Self::CumulativePercent => match row.total {
+                0 => Value::Missing,
+                total => Value::Percent(row.cumulative as f64 * 100.0 / total as f64),
+            },
+            Self::Delta => row.delta.map_or(Value::Missing, Value::SizeDelta),
+            Self::Growth => row.growth.map_or(Value::Missing, |growth| {
+                Value::SizeDelta(growth.round() as i64)
+            }),
+            Self::Saved => Value::Size(stats.bytes.saturating_sub(stats.allocated)),
+            Self::LinkSaved => Value::Size(stats.linked),
+            Self::Reflinked => Value::Size(stats.reflinked),
+            Self::Relative => row.relative.map_or(Value::Missing, Value::Ratio),
+            Self::NewestMtime => match &stats.newest {
+                Some((mtime, _)) => Value::Text(report.time_format.format(*mtime, started)),
+                None => Value::Missing,
+            },
+            Self::NewestFile => match &stats.newest {
+                Some((_, path)) => Value::Text(path.display().to_string()),
+                None => Value::Missing,
+            },
+            Self::OldestMtime => match &stats.oldest {
+                Some((mtime, _)) => Value::Text(report.time_format.format(*mtime, started)),
+                None => Value::Missing,
+            },
+            Self::OldestFile => match &stats.oldest {
+                Some((_, path)) => Value::Text(path.display().to_string()),
+                None => Value::Missing,
+            },
+            Self::AccessedAtime => match &stats.accessed {
+                Some((atime, _)) => Value::Text(report.time_format.format(*atime, started)),
+                None => Value::Missing,
+            },
+            Self::AccessedFile => match &stats.accessed {
+                Some((_, path)) => Value::Text(path.display().to_string()),
+                None => Value::Missing,
+            },
+        }
+    }
+}
+
+fn user_columns(
+    report: &ReportOptions,
+    options: &ScanOptions,
+    watch_diff: bool,
+    relative: bool,
+) -> Vec<UserColumn> {
+    if let Some(columns) = &report.columns {
+        return columns.clone();
+    }
+    if report.output_format == OutputFormat::MachineReadable {
+        return vec![UserColumn::Size, UserColumn::Uid];
+    }
+    if report.bytes_per_inode {
+        let columns = [
+            UserColumn::BytesPerInode,
+            UserColumn::Files,
+            UserColumn::Size,
+        ];
+        return if report.output_format.is_text() || report.output_format == OutputFormat::Csv {
+            [&columns[..], &[UserColumn::User]].concat()
+        } else {
+            [&[UserColumn::User, UserColumn::Uid], &columns[..]].concat()
+        };
+    }
+    let mut extra = Vec::new();
+    if report.size_and_blocks {
+        extra.push(UserColumn::Allocated);
+    }
+    if report.allocation_efficiency {
+        extra.push(UserColumn::Efficiency);
+    }
+    if report.cumulative {
+        extra.extend([UserColumn::Cumulative, UserColumn::CumulativePercent]);
+    }
+    if watch_diff {
+        extra.push(UserColumn::Delta);
+    }
+    if report.recent_growth {
+        extra.push(UserColumn::Growth);
+    }
+    if report.sparse_savings {
+        extra.push(UserColumn::Saved);
+    }
+    if report.compression_ratio {
+        extra.push(UserColumn::Compression);
+    }
+    if report.hardlink_savings {
+        extra.push(UserColumn::LinkSaved);
+    }
+    if options.count_reflinks {
+        extra.push(UserColumn::Reflinked);
+    }
+    if relative {
+        extra.push(UserColumn::Relative);
+    }
+    if options.dir_count {
+        extra.push(UserColumn::Dirs);
+    }
+    if options.empty_dirs {
+        extra.push(UserColumn::EmptyDirs);
+    }
+    // for the groups' file counts under each user
+    if options.gid_breakdown {
+        extra.push(UserColumn::Files);
+    }
+    if report.nlink {
+        extra.push(UserColumn::Nlink);
+    }
+    if report.link_count {
+        extra.extend([
+            UserColumn::Inodes,
+            UserColumn::Links,
+            UserColumn::LinksPerInode,
+        ]);
+    }
+    if report.setuid_count {
+        extra.push(UserColumn::Setuid);
+    }
+    if options.acl_count {
+        extra.push(UserColumn::Acl);
+    }
+    if options.cap_count {
+        extra.push(UserColumn::Caps);
+    }
+    if options.mode_count {
+        extra.push(UserColumn::Mode);
+    }
+    if options.collect_sizes.is_some() {
+        extra.push(UserColumn::P95);
+    }
+    // text gets a table of its own after this one
+    if options.size_histogram && report.output_format.is_json() {
+        extra.push(UserColumn::SizeHistogram);
+    }
+    let mut columns = if report.output_format.is_text() || report.output_format == OutputFormat::Csv
+    {
+        [vec![UserColumn::Size], extra, vec![UserColumn::User]].concat()
+    } else {
+        [
+            vec![UserColumn::User, UserColumn::Uid, UserColumn::Size],
+            extra,
+        ]
+        .concat()
+    };
+    if options.newest_file {
+        columns.extend([UserColumn::NewestMtime, UserColumn::NewestFile]);
+    }
+    if options.oldest_file {
+        columns.extend([UserColumn::OldestMtime, UserColumn::OldestFile]);
+    }
+    if options.accessed_file {
+        columns.extend([UserColumn::AccessedAtime, UserColumn::AccessedFile]);
+    }
+    columns
+}
+
+fn month_table(
+    by_month: HashMap<(UserId, YearMonth), u64>,
+    names: &UserNames,
+    output_format: OutputFormat,
+) -> Table {
+    let (month, user, uid, size) = (
+        Column::new("month", "month"),
+        Column::new("user", "user"),
+        Column::new("uid", "uid"),
+        Column::new("size", "bytes"),
+    );
+    let columns = match output_format {
+        OutputFormat::MachineReadable => vec![month, uid, size],
+        f if f.is_text() || f == OutputFormat::Csv => vec![month, user, size],
+        _ => vec![month, user, uid, size],
+    };
+    let rows = by_month
+        .into_iter()
+        .sorted_by_key(|&((_, month), bytes)| (month, bytes))
+        .map(|((user_id, month), bytes)| {
+            columns
+                .iter()
+                .map(|column| match column.key {
+                    "month" => Value::Text(month.to_string()),
+                    "user" => Value::Text(names.name(user_id)),
+                    "uid" => names.uid(user_id),
+                    _ => Value::Size(bytes),
+                })
+                .collect()
+        })
+        .collect();
+    Table {
+        columns,
+        rows,
+        alerts: Vec::new(),
+    }
+}
+
+fn project_table(
+    by_project: HashMap<(Option<String>, UserId), u64>,
+    names: &UserNames,
+    output_format: OutputFormat,
+) -> Table {
+    let (project, user, uid, size) = (
+        Column::new("project", "project"),
+        Column::new("user", "user"),
+        Column::new("uid", "uid"),
+        Column::new("size", "bytes"),
+    );
+    let columns = match output_format {
+        OutputFormat::MachineReadable => vec![project, uid, size],
+        f if f.is_text() || f == OutputFormat::Csv => vec![project, user, size],
+        _ => vec![project, user, uid, size],
+    };
+    // files outside every project come first, shown without a project name
+    let rows = by_project
+        .into_iter()
+        .sorted_by(|((a_project, _), a_bytes), ((b_project, _), b_bytes)| {
+            (a_project, a_bytes).cmp(&(b_project, b_bytes))
+        })
+        .map(|((project, user_id), bytes)| {
+            columns
+                .iter()
+                .map(|column| match column.key {
+                    "project" => project.clone().map_or(Value::Missing, Value::Text),
+                    "user" => Value::Text(names.name(user_id)),
+                    "uid" => names.uid(user_id),
+                    _ => Value::Size(bytes),
+                })
+                .collect()
+        })
+        .collect();
+    Table {
+        columns,
+        rows,
+        alerts: Vec::new(),
+    }
+}
+
+/// Two rows for each directory that both `users` have files in, one per
+/// user with their share of the two's bytes there, the biggest directories last
+fn dir_pair_table(
+    by_dir_pair: HashMap<std::path::PathBuf, [u64; 2]>,
+    users: [UserId; 2],
+    names: &UserNames,
+    output_format: OutputFormat,
+) -> Table {
+    let (path, user, uid, size, percent) = (
+        Column::path("path", "path"),
+        Column::new("user", "user"),
+        Column::new("uid", "uid"),
+        Column::new("size", "bytes"),
+        Column::new("percent", "percent"),
+    );
+    let columns = match output_format {
+        OutputFormat::MachineReadable => vec![path, uid, size, percent],
+        f if f.is_text() || f == OutputFormat::Csv => vec![path, user, size, percent],
+        _ => vec![path, user, uid, size, percent],
+    };
+    let rows = by_dir_pair
+        .into_iter()
+        .filter(|(_, bytes)| bytes.iter().all(|&bytes| bytes > 0))
+        .sorted_by(|(a_dir, a), (b_dir, b)| (a[0] + a[1], a_dir).cmp(&(b[0] + b[1], b_dir)))
+        .flat_map(|(dir, bytes)| {
+            let total = bytes[0] + bytes[1];
+            users.into_iter().zip(bytes).map(move |(user_id, bytes)| {
+                (
+                    dir.clone(),
+                    user_id,
+                    bytes,
+                    bytes as f64 * 100.0 / total as f64,
+                )
+            })
+        })
+        .map(|(dir, user_id, bytes, share)| {
+            columns
+                .iter()
+                .map(|column| match column.key {
+                    "path" => Value::Text(dir.display().to_string()),
+                    "user" => Value::Text(names.name(user_id)),
+                    "uid" => names.uid(user_id),
+                    "percent" => Value::Percent(share),
+                    _ => Value::Size(bytes),
+                })
+                .collect()
+        })
+        .collect();
+    Table {
+        columns,
+        rows,
+        alerts: Vec::new(),
+    }
+}
+
+/// Usage by extension, the largest last, with files without one shown as
+/// `(none)`
+fn extension_table(
+    by_extension: HashMap<String, (u64, u64)>,
+    output_format: OutputFormat,
+) -> Table {
+    let (extension, size, files) = (
+        Column::new("extension", "extension"),
+        Column::new("size", "bytes"),
+        Column::new("files", "files"),
+    );
+    let size_first = output_format.is_text() || output_format == OutputFormat::Csv;
+    let rows = by_extension
+        .into_iter()
+        .sorted_by(|(a_ext, a), (b_ext, b)| (a, a_ext).cmp(&(b, b_ext)))
+        .map(|(ext, (bytes, count))| {
+            let name = if ext.is_empty() {
+                "(none)".to_owned()
+            } else {
+                format!(".{}", ext)
+            };
+            let (name, size, count) = (Value::Text(name), Value::Size(bytes), Value::Count(count));
+            if size_first {
+                vec![size, count, name]
+            } else {
+                vec![name, size, count]
+            }
+        })
+        .collect();
+    let columns = if size_first {
+        vec![size, files, extension]
+    } else {
+        vec![extension, size, files]
+    };
+    Table {
+        columns,
+        rows,
+        alerts: Vec::new(),
+    }
+}
+
+fn toplevel_table(
+    by_toplevel: HashMap<std::path::PathBuf, u64>,
+    output_format: OutputFormat,
+) -> Table {
+    let (size, path) = (Column::new("size", "bytes"), Column::path("path", "path"));
+    let size_first = output_format.is_text() || output_format == OutputFormat::Csv;
+    let rows = by_toplevel
+        .into_iter()
+        .sorted_by(|(a_path, a_bytes), (b_path, b_bytes)| (a_bytes, a_path).cmp(&(b_bytes, b_path)))
+        .map(|(toplevel, bytes)| {
+            let (size, path) = (
+                Value::Size(bytes),
+                Value::Text(toplevel.display().to_string()),
+            );
+            if size_first {
+                vec![size, path]
+            } else {
+                vec![path, size]
+            }
+        })
+        .collect();
+    let columns = if size_first {
+        vec![size, path]
+    } else {
+        vec![path, size]
+    };
+    Table {
+        columns,
+        rows,
+        alerts: Vec::new(),
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct FileRecord<'a> {
+    path: &'a std::path::Path,
+    bytes: u64,
+}
+
+fn write_per_user_reports(
+    dir: &std::path::Path,
+    reports: HashMap<UserId, Vec<(u64, std::path::PathBuf)>>,
+    formatter: &SizeFormatter,
+    names: &UserNames,
+    json: bool,
+    path_prefix: &PathPrefix,
+) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for (user_id, files) in reports {
+        let mut files = files
+            .into_iter()
+            .map(|(bytes, path)| (bytes, path_prefix.apply(&path)))
+            .collect::<Vec<_>>();
+        files.sort_by(|a, b| b.cmp(a));
+        let extension = if json { "json" } else { "txt" };
+        let report_path = dir.join(format!(
+            "{}.{}",
+            report_stem(&names.name(user_id), user_id),
+            extension
+        ));
+        let mut out = std::io::BufWriter::new(std::fs::File::create(report_path)?);
+        if json {
+            let records = files
+                .iter()
+                .map(|(bytes, path)| FileRecord {
+                    path,
+                    bytes: *bytes,
+                })
+                .collect::<Vec<_>>();
+            serde_json::to_writer(&mut out, &records)?;
+            writeln!(out)?;
+        } else {
+            for (bytes, path) in files {
+                writeln!(out, "{}\t{}", formatter.wrap(bytes), path.display())?;
+            }
+        }
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// What to call `user_id`'s `--per-user-report` file: their name, unless it
+/// could be taken for a path (as a `--uid-map` name like `../x` could), in
+/// which case their UID
+fn report_stem(name: &str, user_id: UserId) -> String {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\0']) {
+        user_id.to_string()
+    } else {
+        name.to_owned()
+    }
+}
+
+/// Whether `user_id` belongs to an account, either locally or in `--uid-map`;
+/// `OTHER_USERS` is several UIDs rather than one that could be missing
+fn has_account(user_id: UserId, uid_map: &HashMap<UserId, String>) -> bool {
+    user_id == OTHER_USERS
+        || uid_map.contains_key(&user_id)
+        || users::get_user_by_uid(user_id.0).is_some()
+}
+
+/// Quote `path` for a POSIX shell, byte for byte, so that odd names survive.
+/// Names with control characters use `$'...'` escapes instead, so a newline
+/// can't end a commented-out line and start a live command.
+fn shell_quote(path: &std::path::Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let bytes = path.as_os_str().as_bytes();
+    if bytes.iter().any(u8::is_ascii_control) {
+        let mut quoted = b"$'".to_vec();
+        for &byte in bytes {
+            match byte {
+                b'\'' | b'\\' => quoted.extend_from_slice(&[b'\\', byte]),
+                b'\n' => quoted.extend_from_slice(b"\\n"),
+                b'\t' => quoted.extend_from_slice(b"\\t"),
+                _ if byte.is_ascii_control() => {
+                    quoted.extend_from_slice(format!("\\{:03o}", byte).as_bytes())
+                }
+                _ => quoted.push(byte),
+            }
+        }
+        quoted.push(b'\'');
+        return quoted;
+    }
+    let mut quoted = vec![b'\''];
+    for &byte in bytes {
+        if byte == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
+        } else {
+            quoted.push(byte);
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+/// Write `--emit-shell-script`: a command per file owned by a UID without an
+/// account, grouped by UID. The commands are commented out unless `execute`,
+/// so the script can be reviewed (and edited) before anything happens.
+fn write_cleanup_script(
+    script: &std::path::Path,
+    reports: &HashMap<UserId, Vec<(u64, std::path::PathBuf)>>,
+    uid_map: &HashMap<UserId, String>,
+    chown_to: Option<&str>,
+    execute: bool,
+) -> std::io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o755)
+        .open(script)?;
+    let mut out = std::io::BufWriter::new(file);
+    writeln!(out, "#!/bin/sh")?;
+    writeln!(
+        out,
+        "# Files owned by UIDs without accounts, found by du-by-user."
+    )?;
+    if !execute {
+        writeln!(out, "# Review, then uncomment the commands to run.")?;
+    }
+    let command = match chown_to {
+        Some(user) => format!(
+            "chown {} --",
+            String::from_utf8_lossy(&shell_quote(user.as_ref()))
+        ),
+        None => "rm -f --".to_owned(),
+    };
+    let prefix = if execute { "" } else { "# " };
+    for (user_id, files) in reports
+        .iter()
+        .filter(|&(&user_id, _)| !has_account(user_id, uid_map))
+        .sorted_by_key(|&(user_id, _)| user_id)
+    {
+        writeln!(out, "\n# uid {}", user_id)?;
+        for (_, path) in files {
+            write!(out, "{}{} ", prefix, command)?;
+            out.write_all(&shell_quote(path))?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()
+}
+
+/// Output settings shared by every report in a run
+#[derive(Debug)]
+struct ReportOptions {
+    formatter: SizeFormatter,
+    output_format: OutputFormat,
+    output_encoding: OutputEncoding,
+    time_format: TimeFormat,
+    numeric: bool,
+    sparse_savings: bool,
+    size_and_blocks: bool,
+    total_and_free: bool,
+    compression_ratio: bool,
+    hardlink_savings: bool,
+    nlink: bool,
+    link_count: bool,
+    setuid_count: bool,
+    allocation_efficiency: bool,
+    cumulative: bool,
+    print_inode: bool,
+    relative_to: Option<String>,
+    anonymize: bool,
+    checksum: bool,
+    #[cfg(feature = "tokio")]
+    async_lookup: bool,
+    print_headers: bool,
+    scan_metadata: bool,
+    /// Print only a one-line JSON summary, for `--summarize`
+    summarize: bool,
+    /// Leave out users who own directories but no files
+    omit_only_dirs: bool,
+    /// Capacity of the buffer in front of stdout
+    buffer_size: usize,
+    missing_users: bool,
+    /// Limit on the width of `table` output
+    output_width: Option<usize>,
+    watch_diff: bool,
+    recent_growth: bool,
+    /// Columns picked with `--columns`, if any
+    columns: Option<Vec<UserColumn>>,
+    /// Names from `--uid-map`
+    uid_map: HashMap<UserId, String>,
+    influxdb_measurement: String,
+    color: bool,
+    /// Users over this many bytes are shown in red; by default, anyone over
+    /// 80% of the largest user's total
+    color_threshold: Option<u64>,
+    sql_table: String,
+    sql_create_table: bool,
+    comment_header: bool,
+    /// Stick to ASCII in tables, for `--no-unicode`
+    ascii: bool,
+    path_prefix: PathPrefix,
+    /// Sizes above this are shown as `>SIZE` in formats meant for people;
+    /// percentages and machine-readable formats still use the real sizes
+    cap_at: Option<u64>,
+    /// `None` for `--no-sort`
+    sort_by: Option<SortKey>,
+    reverse: bool,
+    /// Leave out users with less than this share of the total
+    min_ratio: Option<f64>,
+    /// Leave out users with more than this share of the total
+    max_ratio: Option<f64>,
+    /// Show average file sizes instead of the usual columns, for
+    /// `--bytes-per-inode`
+    bytes_per_inode: bool,
+}
+
+impl ReportOptions {
+    fn from_matches(matches: &clap::ArgMatches, path_count: usize) -> Self {
+        let output_format = OutputFormat::from_matches(matches);
+        Self {
+            formatter: SizeFormatter::from_matches(matches),
+            output_format,
+            output_encoding: OutputEncoding::from_matches(matches),
+            time_format: TimeFormat::from_matches(matches),
+            numeric: matches.is_present("numeric-uid")
+                || output_format == OutputFormat::MachineReadable,
+            sparse_savings: matches.is_present("sparse-savings"),
+            size_and_blocks: matches.is_present("show-size-and-blocks"),
+            total_and_free: matches.is_present("show-total-and-free"),
+            compression_ratio: cfg!(target_os = "linux")
+                && matches.is_present("show-compression-ratio"),
+            hardlink_savings: matches.is_present("show-hardlink-savings"),
+            nlink: matches.is_present("show-nlink"),
+            link_count: matches.is_present("show-link-count"),
+            setuid_count: matches.is_present("show-setuid-count"),
+            allocation_efficiency: matches.is_present("show-allocation-efficiency"),
+            cumulative: matches.is_present("show-cumulative"),
+            print_inode: matches.is_present("print-inode"),
+            relative_to: matches.value_of("relative-to").map(str::to_owned),
+            anonymize: matches.is_present("anonymize"),
+            checksum: matches.is_present("checksum"),
+            #[cfg(feature = "tokio")]
+            async_lookup: matches.is_present("async-lookup"),
+            print_headers: path_count > 1 && output_format.is_text(),
+            scan_metadata: matches.is_present("report-scan-metadata"),
+            summarize: matches.is_present("summarize"),
+            omit_only_dirs: matches.is_present("omit-if-only-dirs"),
+            buffer_size: matches
+                .value_of_t_or_exit::<ParsedSize>("ndjson-buffer-size")
+                .bytes() as usize,
+            missing_users: matches.is_present("report-missing-users"),
+            output_width: if matches.is_present("output-width") {
+                Some(matches.value_of_t_or_exit::<usize>("output-width"))
+            } else {
+                terminal_size::terminal_size().map(|(terminal_size::Width(w), _)| w.into())
+            },
+            watch_diff: matches.is_present("watch-diff"),
+            recent_growth: matches.is_present("show-recent-growth"),
+            columns: matches.values_of("columns").map(|names| {
+                names
+                    .map(|name| UserColumn::from_name(name).expect("validated by clap"))
+                    .collect()
+            }),
+            uid_map: match matches.value_of("uid-map") {
+                Some(path) => load_uid_map(path.as_ref()).unwrap_or_else(|err| {
+                    log::error!("could not read {:?}: {}", path, err);
+                    std::process::exit(1);
+                }),
+                None => HashMap::new(),
+            },
+            influxdb_measurement: matches
+                .value_of("influxdb-measurement")
+                .unwrap_or("du_by_user")
+                .to_owned(),
+            color: match matches.value_of("color") {
+                Some("always") => true,
+                Some("never") => false,
+                _ => {
+                    use std::io::IsTerminal;
+                    std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+                }
+            },
+            color_threshold: if matches.is_present("color-threshold") {
+                Some(
+                    matches
+                        .value_of_t_or_exit::<ParsedSize>("color-threshold")
+                        .bytes(),
+                )
+            } else {
+                None
+            },
+            sql_table: matches
+                .value_of("sql-table")
+                .unwrap_or("disk_usage")
+                .to_owned(),
+            sql_create_table: matches.is_present("sql-create-table"),
+            comment_header: matches.is_present("format-header"),
+            ascii: matches.is_present("no-unicode"),
+            path_prefix: PathPrefix::from_matches(matches),
+            cap_at: matches
+                .is_present("cap-at")
+                .then(|| matches.value_of_t_or_exit::<ParsedSize>("cap-at").bytes())
+                .filter(|_| {
+                    output_format == OutputFormat::Csv
+                        || (output_format.is_text()
+                            && output_format != OutputFormat::MachineReadable)
+                }),
+            sort_by: (!matches.is_present("no-sort")).then(|| SortKey::from_matches(matches)),
+            reverse: matches.is_present("reverse"),
+            min_ratio: matches
+                .is_present("min-ratio")
+                .then(|| matches.value_of_t_or_exit("min-ratio")),
+            max_ratio: matches
+                .is_present("max-ratio")
+                .then(|| matches.value_of_t_or_exit("max-ratio")),
+            bytes_per_inode: matches.is_present("bytes-per-inode"),
+        }
+    }
+
+    fn text_output(&self) -> bool {
+        self.output_format.is_text()
+    }
+}
+
+/// State carried from one report to the next
+#[derive(Default)]
+struct Run {
+    anonymize_map: Option<std::fs::File>,
+    /// Files collected for `--per-user-report`, across all paths
+    reports: HashMap<UserId, Vec<(u64, std::path::PathBuf)>>,
+    /// Per-user totals from the previous `--watch` cycle, for `--watch-diff`
+    previous: HashMap<std::path::PathBuf, HashMap<UserId, u64>>,
+    /// When the previous cycle (or the `--snapshot` it started from) was
+    /// scanned, and its per-user totals, for `--show-recent-growth`
+    growth_base: HashMap<std::path::PathBuf, (SystemTime, HashMap<UserId, u64>)>,
+    had_errors: bool,
+    timed_out: bool,
+    profile: Profile,
+}
+
+impl Run {
+    /// 2 if `--timeout` cut a scan short, 1 if anything couldn't be read
+    /// (unless `--ignore-errors`), otherwise 0
+    fn exit_status(&self, ignore_errors: bool) -> u8 {
+        if self.timed_out {
+            2
+        } else if self.had_errors && !ignore_errors {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+fn report(
+    path: &std::path::Path,
+    scan: Scan,
+    options: &ScanOptions,
+    report: &ReportOptions,
+    run: &mut Run,
+) -> std::io::Result<()> {
+    // before --watch-diff and --omit-if-only-dirs leave some of them out, so
+    // that --anonymize has an alias for everyone named anywhere
+    let mut owners = scan
+        .owners()
+        .chain(options.cross_users.into_iter().flatten())
+        .collect::<Vec<_>>();
+    let Scan {
+        mut by_user,
+        by_month,
+        by_toplevel,
+        by_extension,
+        by_project,
+        by_gid,
+        by_dir_pair,
+        top_files,
+        top_dirs,
+        setuid_files,
+        world_writable,
+        rename_hazards,
+        long_paths,
+        sparse_files,
+        sticky_dirs,
+        inodes: _,
+        duplicates,
+        link_groups,
+        skipped,
+        started,
+        elapsed,
+        timed_out,
+        sampled,
+        dirs,
+        atime_differs,
+        profile,
+    } = scan;
+    run.profile.add(&profile);
+    let formatter = &report.formatter;
+    let output_format = report.output_format;
+    let text_output = report.text_output();
+    run.had_errors |= !skipped.is_empty();
+    if options.accessed_file && !atime_differs && by_user.values().any(|stats| stats.files > 0) {
+        log::warn!(
+            "every access time under {} matches its modification time; the filesystem is probably mounted noatime",
+            path.display()
+        );
+    }
+    let total_used: u64 = by_user.values().map(|stats| stats.bytes).sum();
+    let summary = report.scan_metadata.then(|| ScanSummary {
+        total_bytes: total_used,
+        total_files: by_user.values().map(|stats| stats.files).sum(),
+        total_dirs: dirs,
+        elapsed,
+        errors: skipped.len(),
+    });
+    if timed_out {
+        log::warn!(
+            "timed out while scanning {}; these results are incomplete",
+            path.display()
+        );
+        run.timed_out = true;
+    }
+    if let Some(fraction) = sampled {
+        log::warn!(
+            "only {}% of the files under {} were counted; these results are estimates",
+            fraction * 100.0,
+            path.display()
+        );
+    }
+    if !skipped.is_empty() && !options.ignore_errors {
+        if log::log_enabled!(log::Level::Debug) {
+            for entry in &skipped {
+                log::debug!("skipped {}: {}", entry.path.display(), entry.reason);
+            }
+        } else {
+            log::warn!(
+                "{} entries skipped; re-run with --verbose for details",
+                skipped.len()
+            );
+        }
+    }
+    if options.collect_files {
+        for (&user_id, stats) in by_user.iter_mut() {
+            run.reports
+                .entry(user_id)
+                .or_default()
+                .append(&mut stats.listing);
+        }
+    }
+    let reference = report.relative_to.as_deref().and_then(|user| {
+        let Some(uid) = resolve_user_id(user, &report.uid_map) else {
+            log::warn!("unknown user {}; showing absolute sizes only", user);
+            return None;
+        };
+        match by_user.get(&uid) {
+            Some(stats) if stats.bytes > 0 => Some(stats.bytes),
+            _ => {
+                log::warn!("{} has no usage here; showing absolute sizes only", user);
+                None
+            }
+        }
+    });
+    let digest = report.checksum.then(|| checksum(&by_user));
+    let previous = if report.watch_diff {
+        let totals = by_user
+            .iter()
+            .map(|(&user_id, stats)| (user_id, stats.bytes))
+            .collect();
+        run.previous.insert(path.to_owned(), totals)
+    } else {
+        None
+    };
+    let growth = if report.recent_growth {
+        let totals = by_user
+            .iter()
+            .map(|(&user_id, stats)| (user_id, stats.bytes))
+            .collect();
+        run.growth_base
+            .insert(path.to_owned(), (started, totals))
+            .and_then(|(then, before)| {
+                let hours = started.duration_since(then).ok()?.as_secs_f64() / 3600.0;
+                (hours > 0.0).then_some((hours, before))
+            })
+    } else {
+        None
+    };
+    if let Some(previous) = &previous {
+        owners.extend(previous.keys().copied());
+        // users who no longer own anything changed too
+        for &user_id in previous.keys() {
+            by_user.entry(user_id).or_default();
+        }
+        by_user.retain(|user_id, stats| previous.get(user_id).copied().unwrap_or(0) != stats.bytes);
+    }
+    if report.omit_only_dirs {
+        by_user.retain(|_, stats| stats.files > 0 || (stats.dirs == 0 && stats.empty_dirs == 0));
+    }
+    let lookups_started = std::time::Instant::now();
+    let mut names = if report.anonymize {
+        let names = UserNames::anonymized(report.numeric, owners.iter().copied())
+            .with_uid_map(&report.uid_map)
+            .with_encoding(report.output_encoding);
+        match run.anonymize_map.as_mut() {
+            Some(f) => {
+                if report.print_headers {
+                    writeln!(f, "--- {:?}", path)?;
+                }
+                names.write_mapping(f)?
+            }
+            None => names.write_mapping(std::io::stderr())?,
+        };
+        names
+    } else {
+        #[allow(unused_mut)]
+        let mut names = UserNames::new(report.numeric)
+            .with_uid_map(&report.uid_map)
+            .with_encoding(report.output_encoding);
+        #[cfg(feature = "tokio")]
+        if report.async_lookup {
+            names.prefetch(by_user.keys().copied());
+        }
+        names
+    };
+    names.resolve(owners.into_iter());
+    run.profile.lookups += lookups_started.elapsed();
+    if report.summarize {
+        let top = by_user
+            .iter()
+            .max_by_key(|&(&user_id, stats)| (stats.bytes, Reverse(user_id)));
+        let summary = serde_json::json!({
+            "total_bytes": total_used,
+            "total_files": by_user.values().map(|stats| stats.files).sum::<u64>(),
+            "unique_users": by_user.len(),
+            "top_user": top.map(|(&user_id, _)| names.name(user_id)),
+            "top_user_bytes": top.map(|(_, stats)| stats.bytes),
+            "scan_seconds": elapsed.as_secs_f64(),
+        });
+        println!("{}", summary);
+        return Ok(());
+    }
+    let output_started = std::time::Instant::now();
+    let missing_users = report.missing_users.then(|| {
+        by_user
+            .iter()
+            .filter(|&(&user_id, _)| !has_account(user_id, &report.uid_map))
+            .map(|(&user_id, stats)| (stats.bytes, user_id))
+            .sorted()
+            .collect::<Vec<_>>()
+    });
+    let depths = options.depth_histogram.then(|| {
+        by_user
+            .iter()
+            .map(|(&user_id, stats)| (user_id, stats.depths.clone()))
+            .sorted()
+            .collect::<Vec<_>>()
+    });
+    let size_buckets = options.size_histogram.then(|| {
+        by_user
+            .iter()
+            .map(|(&user_id, stats)| (user_id, stats.size_buckets))
+            .sorted()
+            .collect::<Vec<_>>()
+    });
+    let columns = user_columns(report, options, previous.is_some(), reference.is_some());
+    let writer = Writer {
+        format: output_format,
+        formatter,
+        scan_root: path,
+        width: report.output_width,
+        measurement: &report.influxdb_measurement,
+        timestamp: started,
+        color: report.color,
+        sql_table: &report.sql_table,
+        sql_create_table: report.sql_create_table,
+        comment_header: report.comment_header,
+        ascii: report.ascii,
+        path_prefix: report.path_prefix.clone(),
+    };
+    let (key, table) = if options.by_month {
+        ("months", month_table(by_month, &names, output_format))
+    } else if options.by_toplevel {
+        ("toplevel", toplevel_table(by_toplevel, output_format))
+    } else if options.by_extension {
+        ("extensions", extension_table(by_extension, output_format))
+    } else if options.projects.is_some() {
+        ("projects", project_table(by_project, &names, output_format))
+    } else if let Some(users) = options.cross_users {
+        (
+            "directories",
+            dir_pair_table(by_dir_pair, users, &names, output_format),
+        )
+    } else {
+        // from before --watch-diff and --omit-if-only-dirs left anyone out
+        let total = total_used;
+        let largest = by_user.values().map(|stats| stats.bytes).max();
+        let threshold = report
+            .color_threshold
+            .unwrap_or_else(|| largest.unwrap_or(0) / 5 * 4);
+        // running totals from the largest user down, whatever order the
+        // table ends up in
+        let cumulative = by_user
+            .iter()
+            .map(|(&user_id, stats)| (Reverse(stats.bytes), user_id))
+            .sorted()
+            .scan(0, |sum, (Reverse(bytes), user_id)| {
+                *sum += bytes;
+                Some((user_id, *sum))
+            })
+            .collect::<HashMap<_, _>>();
+        let mut users = by_user.into_iter().collect::<Vec<_>>();
+        // shares of everyone's total, as the percentages still are
+        if let Some(min) = report.min_ratio {
+            users.retain(|(_, stats)| stats.bytes as f64 >= min * total as f64);
+        }
+        if let Some(max) = report.max_ratio {
+            users.retain(|(_, stats)| stats.bytes as f64 <= max * total as f64);
+        }
+        match report.sort_by {
+            Some(SortKey::Size) => users.sort_by_key(|(user_id, stats)| (stats.bytes, *user_id)),
+            Some(SortKey::Count) => users.sort_by_key(|(user_id, stats)| (stats.files, *user_id)),
+            Some(SortKey::User) => users.sort_by_cached_key(|(user_id, _)| names.name(*user_id)),
+            Some(SortKey::Uid) => users.sort_by_key(|(user_id, _)| *user_id),
+            Some(SortKey::BytesPerInode) => users
+                .sort_by_key(|(user_id, stats)| (stats.bytes.checked_div(stats.files), *user_id)),
+            None => {}
+        }
+        if report.reverse {
+            users.reverse();
+        }
+        // each user's groups, largest first, for sub-rows
+        let mut groups = HashMap::<UserId, Vec<(GroupId, u64, u64)>>::new();
+        if text_output {
+            for ((user_id, group_id), (bytes, files)) in by_gid {
+                groups
+                    .entry(user_id)
+                    .or_default()
+                    .push((group_id, bytes, files));
+            }
+            for usage in groups.values_mut() {
+                usage.sort_by_key(|&(group_id, bytes, _)| (Reverse(bytes), group_id));
+            }
+        }
+        let (alerts, rows): (Vec<_>, Vec<_>) = users
+            .into_iter()
+            .flat_map(|(user_id, mut stats)| {
+                // a non-root user with setuid files is worth a look whatever
+                // their usage
+                let alert = stats.bytes > threshold
+                    || (report.setuid_count && user_id != UserId(0) && stats.setuid > 0);
+                let row = UserRow {
+                    p95: stats.p95(),
+                    user_id,
+                    delta: previous.as_ref().map(|previous| {
+                        stats.bytes as i64 - previous.get(&user_id).copied().unwrap_or(0) as i64
+                    }),
+                    growth: growth.as_ref().map(|(hours, before)| {
+                        let before = before.get(&user_id).copied().unwrap_or(0);
+                        (stats.bytes as f64 - before as f64) / hours
+                    }),
+                    relative: reference.map(|r| stats.bytes as f64 / r as f64),
+                    total,
+                    cumulative: cumulative[&user_id],
+                    stats,
+                };
+                let values = columns
+                    .iter()
+                    .map(|column| column.value(&row, &names, report, started))
+                    .collect::<Vec<_>>();
+                let group_rows = groups.remove(&user_id).unwrap_or_default().into_iter().map(
+                    |(group_id, bytes, files)| {
+                        let values = columns
+                            .iter()
+                            .map(|column| match column {
+                                UserColumn::Size => Value::Size(bytes),
+                                UserColumn::Files => Value::Count(files),
+                                UserColumn::User => Value::Text(format!(
+                                    "  {}",
+                                    group_name(group_id, report.numeric)
+                                )),
+                                _ => Value::Missing,
+                            })
+                            .collect();
+                        (false, values)
+                    },
+                );
+                std::iter::once((alert, values)).chain(group_rows)
+            })
+            .unzip();
+        (
+            "users",
+            Table {
+                columns: columns.iter().map(UserColumn::column).collect(),
+                rows,
+                alerts,
+            },
+        )
+    };
+    // stdout would otherwise be flushed at every newline, which is a write(2)
+    // per jsonlines record
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::with_capacity(report.buffer_size, stdout.lock());
+    if sampled.is_some() && text_output {
+        writeln!(out, "(APPROXIMATE)")?;
+    }
+    writer.write_report(&mut out, &table, key, summary.as_ref())?;
+    if let Some(digest) = digest {
+        if text_output {
+            writeln!(out, "sha256\t{}", digest)?;
+        } else {
+            eprintln!("sha256\t{}", digest);
+        }
+    }
+    if report.total_and_free && text_output {
+        writeln!(out, "TOTAL_USED\t{}", formatter.wrap(total_used))?;
+        match freespace::available(path) {
+            Ok(free) => writeln!(out, "TOTAL_FREE\t{}", formatter.wrap(free))?,
+            Err(err) => log::warn!(
+                "could not find the free space on {}: {}",
+                path.display(),
+                err
+            ),
+        }
+    }
+    let owner = |user_id| {
+        if output_format == OutputFormat::MachineReadable {
+            names.uid(user_id)
+        } else {
+            Value::Text(names.name(user_id))
+        }
+    };
+    // the extra listings are only part of the plain-text output
+    if let Some(top_files) = top_files.filter(|_| text_output) {
+        writeln!(out, "--- top {} files", top_files.limit)?;
+        let rows = top_files
+            .into_sorted_vec()
+            .into_iter()
+            .map(|(size, path, user_id, inode)| {
+                let mut row = vec![Value::Size(size), owner(user_id)];
+                if report.print_inode {
+                    row.push(match inode {
+                        0 => Value::Missing,
+                        inode => Value::Count(inode),
+                    });
+                }
+                row.push(Value::Text(path.display().to_string()));
+                row
+            })
+            .collect();
+        let mut columns = vec![Column::new("size", "bytes"), Column::new("user", "user")];
+        if report.print_inode {
+            columns.push(Column::new("inode", "inode"));
+        }
+        columns.push(Column::path("path", "path"));
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts: Vec::new(),
+            },
+        )?;
+    }
+    if let Some(limit) = options.top_dirs.filter(|_| text_output) {
+        writeln!(out, "--- top {} dirs", limit)?;
+        let rows = top_dirs
+            .into_iter()
+            .map(|(size, path)| vec![Value::Size(size), Value::Text(path.display().to_string())])
+            .collect();
+        let columns = vec![Column::new("size", "bytes"), Column::path("path", "path")];
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts: Vec::new(),
+            },
+        )?;
+    }
+    if options.setuid_report && text_output {
+        writeln!(out, "--- setuid/setgid files")?;
+        let rows = setuid_files
+            .into_iter()
+            .map(|(mode, user_id, path)| {
+                vec![
+                    Value::Text(format!("{:04o}", mode & 0o7777)),
+                    owner(user_id),
+                    Value::Text(path.display().to_string()),
+                ]
+            })
+            .collect();
+        let columns = vec![
+            Column::new("mode", "mode"),
+            Column::new("user", "user"),
+            Column::path("path", "path"),
+        ];
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts: Vec::new(),
+            },
+        )?;
+    }
+    if options.world_writable && text_output {
+        writeln!(out, "--- world-writable files")?;
+        // anyone can change what root's are relied on for, so those come
+        // first and are shown like the largest users
+        let (rows, alerts) = world_writable
+            .into_iter()
+            .sorted_by_key(|&(_, user_id, _)| user_id != UserId(0))
+            .map(|(mode, user_id, path)| {
+                let row = vec![
+                    Value::Text(format!("{:04o}", mode & 0o7777)),
+                    owner(user_id),
+                    Value::Text(path.display().to_string()),
+                ];
+                (row, user_id == UserId(0))
+            })
+            .unzip();
+        let columns = vec![
+            Column::new("mode", "mode"),
+            Column::new("user", "user"),
+            Column::path("path", "path"),
+        ];
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts,
+            },
+        )?;
+    }
+    if options.rename_hazards && text_output {
+        writeln!(out, "--- rename hazards")?;
+        let rows = rename_hazards
+            .into_iter()
+            .sorted()
+            .map(|(user_id, path)| vec![owner(user_id), Value::Text(escape_path(&path))])
+            .collect();
+        let columns = vec![Column::new("user", "user"), Column::path("path", "path")];
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts: Vec::new(),
+            },
+        )?;
+    }
+    if let Some(limit) = options.long_paths.filter(|_| text_output) {
+        writeln!(out, "--- paths over {} bytes", limit)?;
+        // the longest last, like the largest users
+        let rows = long_paths
+            .into_iter()
+            .map(|(user_id, path)| (path.as_os_str().len(), user_id, path))
+            .sorted()
+            .map(|(length, user_id, path)| {
+                vec![
+                    Value::Count(length as u64),
+                    owner(user_id),
+                    Value::Text(path.display().to_string()),
+                ]
+            })
+            .collect();
+        let columns = vec![
+            Column::new("length", "length"),
+            Column::new("user", "user"),
+            Column::path("path", "path"),
+        ];
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts: Vec::new(),
+            },
+        )?;
+    }
+    if options.sparse_files && text_output {
+        writeln!(out, "--- sparse files")?;
+        // grouped by user, each user's smallest first
+        let rows = sparse_files
+            .into_iter()
+            .sorted_by(|(a, a_size, ..), (b, b_size, ..)| (a, a_size).cmp(&(b, b_size)))
+            .map(|(user_id, size, allocated, path)| {
+                vec![
+                    owner(user_id),
+                    Value::Size(size),
+                    Value::Size(allocated),
+                    Value::Text(path.display().to_string()),
+                ]
+            })
+            .collect();
+        let columns = vec![
+            Column::new("user", "user"),
+            Column::new("size", "bytes"),
+            Column::new("allocated", "allocated_bytes"),
+            Column::path("path", "path"),
+        ];
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts: Vec::new(),
+            },
+        )?;
+    }
+    if let Some(size_buckets) = size_buckets.filter(|_| text_output) {
+        writeln!(out, "--- file sizes")?;
+        let rows = size_buckets
+            .into_iter()
+            .map(|(user_id, buckets)| {
+                std::iter::once(owner(user_id))
+                    .chain(buckets.into_iter().map(Value::Count))
+                    .collect()
+            })
+            .collect();
+        let columns = std::iter::once(Column::new("user", "user"))
+            .chain(
+                SIZE_BUCKETS
+                    .iter()
+                    .map(|&(label, _)| Column::new(label, label)),
+            )
+            .collect();
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts: Vec::new(),
+            },
+        )?;
+    }
+    if let Some(depths) = depths.filter(|_| text_output) {
+        writeln!(out, "--- file depths")?;
+        // each user's depths shallowest first, empty ones included
+        let rows = depths
+            .into_iter()
+            .flat_map(|(user_id, depths)| {
+                depths.into_iter().enumerate().map(move |(bucket, files)| {
+                    let depth = if bucket + 1 == HISTOGRAM_DEPTHS {
+                        format!("{}+", HISTOGRAM_DEPTHS)
+                    } else {
+                        (bucket + 1).to_string()
+                    };
+                    (user_id, depth, files)
+                })
+            })
+            .map(|(user_id, depth, files)| {
+                vec![owner(user_id), Value::Text(depth), Value::Count(files)]
+            })
+            .collect();
+        let columns = vec![
+            Column::new("user", "user"),
+            Column::new("depth", "depth"),
+            Column::new("files", "files"),
+        ];
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts: Vec::new(),
+            },
+        )?;
+    }
+    if options.sticky_dirs && text_output {
+        writeln!(out, "--- sticky directories")?;
+        // each directory's users smallest first; one with nothing counted
+        // under it still gets a row
+        let rows = sticky_dirs
+            .into_iter()
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
+            .flat_map(|(dir, usage)| {
+                let path = Value::Text(dir.display().to_string());
+                if usage.is_empty() {
+                    return vec![vec![path, Value::Missing, Value::Size(0)]];
+                }
+                usage
+                    .into_iter()
+                    .sorted_by_key(|&(user_id, bytes)| (bytes, user_id))
+                    .map(|(user_id, bytes)| vec![path.clone(), owner(user_id), Value::Size(bytes)])
+                    .collect()
+            })
+            .collect();
+        let columns = vec![
+            Column::path("path", "path"),
+            Column::new("user", "user"),
+            Column::new("size", "bytes"),
+        ];
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts: Vec::new(),
+            },
+        )?;
+    }
+    if options.duplicate_content.is_some() && text_output {
+        writeln!(out, "--- duplicate content")?;
+        // one numbered group after another, the most wasteful last
+        let rows = duplicates
+            .into_iter()
+            .sorted_by_key(|group| (group.wasted(), group.size))
+            .enumerate()
+            .flat_map(|(i, group)| {
+                let wasted = group.wasted();
+                group.files.into_iter().map(move |(user_id, path)| {
+                    vec![
+                        Value::Count(i as u64 + 1),
+                        Value::Size(wasted),
+                        owner(user_id),
+                        Value::Text(path.display().to_string()),
+                    ]
+                })
+            })
+            .collect();
+        let columns = vec![
+            Column::new("group", "group"),
+            Column::new("wasted", "wasted_bytes"),
+            Column::new("user", "user"),
+            Column::path("path", "path"),
+        ];
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts: Vec::new(),
+            },
+        )?;
+    }
+    if let Some(limit) = options.link_groups.filter(|_| text_output) {
+        writeln!(out, "--- top {} link groups", limit)?;
+        // like the other listings, smallest first
+        let rows = link_groups
+            .into_iter()
+            .rev()
+            .enumerate()
+            .flat_map(|(i, group)| {
+                let size = group.size;
+                group.paths.into_iter().map(move |(user_id, path)| {
+                    vec![
+                        Value::Count(i as u64 + 1),
+                        Value::Size(size),
+                        owner(user_id),
+                        Value::Text(path.display().to_string()),
+                    ]
+                })
+            })
+            .collect();
+        let columns = vec![
+            Column::new("group", "group"),
+            Column::new("size", "bytes"),
+            Column::new("user", "user"),
+            Column::path("path", "path"),
+        ];
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts: Vec::new(),
+            },
+        )?;
+    }
+    if let Some(missing_users) = missing_users.filter(|_| text_output) {
+        writeln!(out, "--- users without accounts")?;
+        let rows = missing_users
+            .into_iter()
+            .map(|(size, user_id)| vec![Value::Size(size), Value::Id(user_id.0)])
+            .collect();
+        let columns = vec![Column::new("size", "bytes"), Column::new("uid", "uid")];
+        writer.write(
+            &mut out,
+            &Table {
+                columns,
+                rows,
+                alerts: Vec::new(),
+            },
+        )?;
+    }
+    out.flush()?;
+    run.profile.output += output_started.elapsed();
+    Ok(())
+}
+
+/// Log to stderr; `RUST_LOG` takes precedence over `--verbose`
+fn init_logging(verbose: bool) {
+    let default = if verbose {
+        "du_by_user=debug"
+    } else {
+        "du_by_user=info"
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default))
+        .format(|buf, record| match record.level() {
+            log::Level::Info => writeln!(buf, "{}", record.args()),
+            log::Level::Warn => writeln!(buf, "warning: {}", record.args()),
+            level => writeln!(buf, "{}: {}", level.as_str().to_lowercase(), record.args()),
+        })
+        .init();
+}
+
+fn main() -> std::process::ExitCode {
+    let run_started = std::time::Instant::now();
+    let matches = cli().get_matches();
+    init_logging(matches.is_present("verbose"));
+    if matches.is_present("compare") {
+        let reports = matches.values_of_t_or_exit::<std::path::PathBuf>("compare");
+        let (before, after) = (&reports[0], &reports[1]);
+        let numeric = matches.is_present("numeric-uid");
+        let compared = if matches.is_present("show-owner-changes") {
+            compare::owner_changes(
+                &mut std::io::stdout().lock(),
+                before,
+                after,
+                DiffFormat::from_matches(&matches),
+                |user_id| user_name(user_id, numeric),
+            )
+        } else {
+            compare::compare(
+                &mut std::io::stdout().lock(),
+                before,
+                after,
+                DiffFormat::from_matches(&matches),
+                &SizeFormatter::from_matches(&matches),
+                matches.is_present("report-unchanged"),
+            )
+        };
+        if let Err(err) = compared {
+            log::error!("could not compare {:?} and {:?}: {}", before, after, err);
+            return std::process::ExitCode::FAILURE;
+        }
+        return std::process::ExitCode::SUCCESS;
+    }
+    // held until main returns, which is also how every error after this exits
+    let _pid_file = match matches.value_of("pid-file") {
+        Some(path) => match pidfile::PidFile::create(std::path::Path::new(path)) {
+            Ok(pid_file) => Some(pid_file),
+            Err(err) => {
+                log::error!("could not lock pid file {}: {}", path, err);
+                return std::process::ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    let options = ScanOptions::from_matches(&matches);
+    let mut paths = matches.values_of_t_or_exit::<std::path::PathBuf>("path");
+    let mut restored = match matches.value_of("restore") {
+        Some(file) => match snapshot::load(std::path::Path::new(file), options.top_files) {
+            Ok(scans) => {
+                let (saved_paths, scans): (Vec<_>, Vec<_>) = scans.into_iter().unzip();
+                paths = saved_paths;
+                Some(scans.into_iter())
+            }
+            Err(err) => {
+                log::error!("could not restore {}: {}", file, err);
+                return std::process::ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    if matches.is_present("realpath") {
+        for path in paths.iter_mut() {
+            match std::fs::canonicalize(&*path) {
+                Ok(real) => *path = real,
+                Err(err) => {
+                    log::error!("could not resolve {:?}: {}", path, err);
+                    return std::process::ExitCode::FAILURE;
+                }
+            }
+        }
+    }
+    let report_options = ReportOptions::from_matches(&matches, paths.len());
+    let watch = if matches.is_present("rescan-interval") {
+        duration(&matches, "rescan-interval")
+    } else if matches.is_present("watch") {
+        Some(std::time::Duration::from_secs(
+            matches.value_of_t_or_exit::<u64>("watch"),
+        ))
+    } else {
+        None
+    };
+    for path in paths.iter().filter(|_| restored.is_none()) {
+        if options.auto_exclude(path) {
+            log::info!(
+                "not scanning {} under {:?} (use --no-auto-exclude to include them)",
+                PSEUDO_FILESYSTEMS.join(", "),
+                path
+            );
+        }
+    }
+    let mut run = Run::default();
+    // the last snapshot, if there is one, is where growth is measured from
+    // until the first cycle is done
+    if let Some(file) = matches
+        .value_of("snapshot")
+        .filter(|_| report_options.recent_growth)
+        .filter(|file| std::path::Path::new(file).exists())
+    {
+        match snapshot::load(file.as_ref(), None) {
+            Ok(scans) => {
+                for (root, scan) in scans {
+                    let totals = scan
+                        .by_user
+                        .iter()
+                        .map(|(&user_id, stats)| (user_id, stats.bytes))
+                        .collect();
+                    run.growth_base.insert(root, (scan.started, totals));
+                }
+            }
+            Err(err) => log::warn!("could not read {}: {}", file, err),
+        }
+    }
+    if let Some(map_path) = matches.value_of("anonymize-map") {
+        match std::fs::File::create(map_path) {
+            Ok(f) => run.anonymize_map = Some(f),
+            Err(err) => {
+                log::error!("could not create {}: {}", map_path, err);
+                return std::process::ExitCode::FAILURE;
+            }
+        }
+    }
+    if matches.is_present("stdin") {
+        let scan = scan_listing(
+            std::io::stdin().lock(),
+            StdinFormat::from_matches(&matches),
+            &options,
+            &report_options.uid_map,
+        );
+        report(
+            std::path::Path::new("-"),
+            scan,
+            &options,
+            &report_options,
+            &mut run,
+        )
+        .expect("failed to write output");
+    } else {
+        loop {
+            if watch.is_some() && report_options.text_output() {
+                let now = std::time::SystemTime::now();
+                println!("--- {}", TimeFormat::Iso8601.format(now, now));
+            }
+            let mut snapshot = Vec::new();
+            for path in &paths {
+                if report_options.print_headers {
+                    println!("--- {:?}", path);
+                }
+                let scan = match restored.as_mut() {
+                    Some(scans) => scans.next().expect("a saved scan for every path"),
+                    None => scan(path, &options),
+                };
+                if matches.is_present("snapshot") {
+                    snapshot.push(snapshot::SavedScan::new(path, &scan));
+                }
+                report(path, scan, &options, &report_options, &mut run)
+                    .expect("failed to write output");
+                if run.timed_out {
+                    break;
+                }
+            }
+            if let Some(file) = matches.value_of("snapshot") {
+                if let Err(err) = snapshot::save(std::path::Path::new(file), snapshot) {
+                    log::error!("could not write snapshot {}: {}", file, err);
+                    return std::process::ExitCode::FAILURE;
+                }
+            }
+            let reports = std::mem::take(&mut run.reports);
+            if let Some(script) = matches.value_of("emit-shell-script") {
+                if let Err(err) = write_cleanup_script(
+                    std::path::Path::new(script),
+                    &reports,
+                    &report_options.uid_map,
+                    matches.value_of("chown-to"),
+                    matches.is_present("execute"),
+                ) {
+                    log::error!("could not write {}: {}", script, err);
+                    return std::process::ExitCode::FAILURE;
+                }
+            }
+            if let Some(dir) = matches.value_of("per-user-report") {
+                let json = report_options.output_format.is_json();
+                let mut names = UserNames::new(report_options.numeric)
+                    .with_uid_map(&report_options.uid_map)
+                    .with_encoding(report_options.output_encoding);
+                names.resolve(reports.keys().copied());
+                if let Err(err) = write_per_user_reports(
+                    std::path::Path::new(dir),
+                    reports,
+                    &report_options.formatter,
+                    &names,
+                    json,
+                    &report_options.path_prefix,
+                ) {
+                    log::error!("could not write per-user reports to {}: {}", dir, err);
+                    return std::process::ExitCode::FAILURE;
+                }
+            }
+            match watch {
+                Some(interval) if !run.timed_out => std::thread::sleep(interval),
+                _ => break,
+            }
+        }
+    }
+    if matches.is_present("profile") {
+        run.profile.print(run_started.elapsed());
+    }
+    std::process::ExitCode::from(run.exit_status(options.ignore_errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add_to_ancestors, age_in_range, checksum, cli, dir_pair_table, escape_path,
+        is_rename_hazard, is_sparse, keep_largest, largest_dirs, load_project_map, load_uid_filter,
+        parse_ratio, report_stem, resolve_uid, scan, scan_listing, write_cleanup_script,
+        OutputFormat, Run, Scan, ScanOptions, StdinFormat, TopFiles, UserId, UserNames, UserStats,
+        Value, HISTOGRAM_DEPTHS, OTHER_USERS,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_debug_assert_cli() {
+        cli().debug_assert()
+    }
+
+    #[test]
+    fn test_top_files_keeps_largest() {
+        let mut top = TopFiles::new(2);
+        for (size, name) in [(5, "a"), (1, "b"), (9, "c"), (7, "d")] {
+            top.push(size, std::path::Path::new(name), UserId(0), 0);
+        }
+        let sizes: Vec<u64> = top.into_sorted_vec().into_iter().map(|e| e.0).collect();
+        assert_eq!(sizes, vec![7, 9]);
+    }
+
+    #[test]
+    fn test_top_dirs_add_up_below_root() {
+        let mut totals = HashMap::new();
+        let root = std::path::Path::new("/data");
+        for (path, size) in [("/data/a/x", 5), ("/data/a/b/y", 3), ("/data/c/z", 7)] {
+            add_to_ancestors(&mut totals, root, std::path::Path::new(path), size);
+        }
+        assert_eq!(totals.len(), 4);
+        let largest = largest_dirs(totals, 3)
+            .into_iter()
+            .map(|(size, dir)| (size, dir.display().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            largest,
+            [
+                (15, "/data".to_owned()),
+                (8, "/data/a".to_owned()),
+                (7, "/data/c".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rename_hazards() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = |bytes: &[u8]| std::ffi::OsStr::from_bytes(bytes).to_owned();
+        assert!(!is_rename_hazard(&name(b"report-2024 (final).txt")));
+        assert!(is_rename_hazard(&name(b"a\tb")));
+        assert!(is_rename_hazard(&name("caf\u{e9}".as_bytes())));
+        assert!(is_rename_hazard(&name(b"\xff")));
+        let path = std::path::PathBuf::from(name(b"/data/a\tb\nc\\d\xff"));
+        assert_eq!(escape_path(&path), "/data/a\\tb\\nc\\\\d\\xff");
+    }
+
+    #[test]
+    fn test_resolve_uid_uses_cache() {
+        let mut cache = HashMap::from([(UserId(0), "cached".to_owned())]);
+        assert_eq!(resolve_uid(UserId(0), &mut cache), "cached");
+        // nobody has this UID, so it falls back to the number, once
+        assert_eq!(resolve_uid(UserId(3_999_999_999), &mut cache), "3999999999");
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_project_map_longest_prefix() {
+        let path = std::env::temp_dir().join(format!("du-by-user-projects-{}", std::process::id()));
+        std::fs::write(&path, "# comment\n/data/a\talpha\n/data/a/shared\tshared\n").unwrap();
+        let projects = load_project_map(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let project = |path: &str| projects.project(std::path::Path::new(path));
+        assert_eq!(project("/data/a/x"), Some("alpha"));
+        assert_eq!(project("/data/a/shared/x"), Some("shared"));
+        assert_eq!(project("/data/ab/x"), None);
+    }
+
+    #[test]
+    fn test_dir_pair_table_needs_both_users() {
+        let by_dir_pair = HashMap::from([
+            ("/data/shared".into(), [300, 100]),
+            ("/data/alone".into(), [50, 0]),
+        ]);
+        let users = [UserId(1000), UserId(1001)];
+        let table = dir_pair_table(by_dir_pair, users, &UserNames::new(true), OutputFormat::Tsv);
+        assert_eq!(table.rows.len(), 2);
+        assert_eq!(table.rows[0][0], Value::Text("/data/shared".to_owned()));
+        assert_eq!(table.rows[0][3], Value::Percent(75.0));
+        assert_eq!(table.rows[1][1], Value::Text("1001".to_owned()));
+    }
+
+    #[test]
+    fn test_uid_filter() {
+        let path = std::env::temp_dir().join(format!("du-by-user-uids-{}", std::process::id()));
+        std::fs::write(&path, "# staff\n1000\n+1001\n-1001\n\n+émile\n").unwrap();
+        let uid_map = HashMap::from([(UserId(1003), "émile".to_owned())]);
+        let filter = load_uid_filter(&path, &uid_map).unwrap();
+        assert!(filter.counts(UserId(1000)));
+        // excluding wins over including
+        assert!(!filter.counts(UserId(1001)));
+        assert!(!filter.counts(UserId(1002)));
+        assert!(filter.counts(UserId(1003)));
+        // a multi-byte first character isn't mistaken for a prefix
+        std::fs::write(&path, "émile\n").unwrap();
+        let filter = load_uid_filter(&path, &uid_map).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(filter.counts(UserId(1003)));
+    }
+
+    #[test]
+    fn test_p95_nearest_rank() {
+        let mut stats = UserStats::default();
+        for size in (1..=40).rev() {
+            stats.see_size(size, 50);
+        }
+        assert_eq!(stats.p95(), Some(38));
+        for size in 41..=60 {
+            stats.see_size(size, 50);
+        }
+        assert_eq!(stats.p95(), None);
+    }
+
+    #[test]
+    fn test_depth_histogram_buckets() {
+        let mut stats = UserStats::default();
+        for depth in [0, 1, 3, HISTOGRAM_DEPTHS, HISTOGRAM_DEPTHS + 5] {
+            stats.see_depth(depth);
+        }
+        let mut expected = vec![0; HISTOGRAM_DEPTHS];
+        expected[0] = 2;
+        expected[2] = 1;
+        expected[HISTOGRAM_DEPTHS - 1] = 2;
+        assert_eq!(stats.depths, expected);
+    }
+
+    #[test]
+    fn test_size_buckets() {
+        let mut stats = UserStats::default();
+        for size in [0, 1023, 1024, 5 << 20, 1 << 30, u64::MAX] {
+            stats.see_bucket(size);
+        }
+        assert_eq!(stats.size_buckets, [2, 1, 1, 2]);
+    }
+
+    #[test]
+    fn test_parse_ratio() {
+        assert_eq!(parse_ratio("0"), Ok(0.0));
+        assert_eq!(parse_ratio("0.01"), Ok(0.01));
+        assert_eq!(parse_ratio("1.0"), Ok(1.0));
+        assert!(parse_ratio("1.5").is_err());
+        assert!(parse_ratio("-0.1").is_err());
+        assert!(parse_ratio("lots").is_err());
+    }
+
+    #[test]
+    fn test_common_mode() {
+        let mut stats = UserStats::default();
+        assert_eq!(stats.common_mode(), None);
+        stats.modes = HashMap::from([(0o644, 3), (0o755, 3), (0o600, 1)]);
+        assert_eq!(stats.common_mode(), Some(0o644));
+        stats.modes.insert(0o600, 4);
+        assert_eq!(stats.common_mode(), Some(0o600));
+    }
+
+    #[test]
+    fn test_keep_largest() {
+        let mut by_user = (1..=4)
+            .map(|uid| {
+                let stats = UserStats {
+                    bytes: uid as u64 * 100,
+                    files: 1,
+                    ..UserStats::default()
+                };
+                (UserId(uid), stats)
+            })
+            .collect::<HashMap<_, _>>();
+        keep_largest(&mut by_user, 2);
+        keep_largest(&mut by_user, 1);
+        assert_eq!(by_user.len(), 2);
+        assert_eq!(by_user[&UserId(4)].bytes, 400);
+        let other = &by_user[&OTHER_USERS];
+        assert_eq!((other.bytes, other.files), (600, 3));
+    }
+
+    #[test]
+    fn test_excludes_under_root() {
+        let cwd = std::env::current_dir().unwrap();
+        let matches = cli().get_matches_from([
+            "du-by-user",
+            "--exclude",
+            "cache",
+            "--exclude",
+            cwd.join("x/tmp").to_str().unwrap(),
+        ]);
+        let options = ScanOptions::from_matches(&matches);
+        let root = std::path::Path::new(".");
+        let excludes = options.excludes_under(root);
+        assert!(options.excluded(std::path::Path::new("./cache"), root, &excludes));
+        assert!(options.excluded(std::path::Path::new("./x/tmp"), root, &excludes));
+        assert!(!options.excluded(std::path::Path::new("./x/cache"), root, &excludes));
+        let root = std::path::Path::new("/data");
+        let excludes = options.excludes_under(root);
+        assert!(options.excluded(std::path::Path::new("/data/cache"), root, &excludes));
+    }
+
+    #[test]
+    fn test_timeout_keeps_partial_results() {
+        let root = std::env::temp_dir().join(format!("du-by-user-timeout-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("a"), [0; 10]).unwrap();
+        std::fs::write(root.join("sub/b"), [0; 20]).unwrap();
+        let scan_with = |args: &[&str]| {
+            let matches = cli().get_matches_from(
+                ["du-by-user"]
+                    .iter()
+                    .chain(args)
+                    .chain([&root.to_str().unwrap()]),
+            );
+            scan(&root, &ScanOptions::from_matches(&matches))
+        };
+        let (full, cut) = (scan_with(&[]), scan_with(&["--timeout", "0"]));
+        std::fs::remove_dir_all(&root).unwrap();
+        assert!(!full.timed_out);
+        assert!(cut.timed_out);
+        // whatever was counted before the deadline is kept, and is no more
+        // than the whole tree
+        let total = |scan: &Scan| scan.by_user.values().map(|s| s.bytes).sum::<u64>();
+        assert_eq!(total(&full), 30);
+        assert!(total(&cut) <= total(&full));
+        assert!(cut.dirs <= full.dirs);
+
+        let mut run = Run::default();
+        assert_eq!(run.exit_status(false), 0);
+        run.had_errors = true;
+        assert_eq!((run.exit_status(false), run.exit_status(true)), (1, 0));
+        run.timed_out = true;
+        assert_eq!((run.exit_status(false), run.exit_status(true)), (2, 2));
+    }
+
+    #[test]
+    fn test_age_bounds() {
+        let day = std::time::Duration::from_secs(86400);
+        let root = std::env::temp_dir().join(format!("du-by-user-ages-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let now = std::time::SystemTime::now();
+        // a day inside each side of 7d..30d, a day outside each, and a file
+        // from the future
+        let ages = [(6, false), (8, true), (29, true), (31, false)];
+        let aged = |days: u64| now - day * days as u32;
+        let mut files = ages
+            .iter()
+            .map(|&(days, inside)| (aged(days), inside))
+            .collect::<Vec<_>>();
+        files.push((now + day, false));
+        let options = ScanOptions::from_matches(&cli().get_matches_from([
+            "du-by-user",
+            "--min-age",
+            "7d",
+            "--max-age",
+            "30d",
+        ]));
+        for (i, &(mtime, inside)) in files.iter().enumerate() {
+            let path = root.join(i.to_string());
+            let file = std::fs::File::create(&path).unwrap();
+            file.set_modified(mtime).unwrap();
+            let mtime = std::fs::metadata(&path).unwrap().modified().ok();
+            assert_eq!(age_in_range(mtime, &options), inside, "{:?}", mtime);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+        // the future counts as brand new, so it's in with only --max-age
+        let max_only =
+            ScanOptions::from_matches(&cli().get_matches_from(["du-by-user", "--max-age", "30d"]));
+        assert!(age_in_range(Some(now + day), &max_only));
+        assert!(!age_in_range(None, &max_only));
+        // an empty range counts nothing
+        let backwards = ScanOptions::from_matches(&cli().get_matches_from([
+            "du-by-user",
+            "--min-age",
+            "30d",
+            "--max-age",
+            "7d",
+        ]));
+        for &(days, _) in &ages {
+            assert!(!age_in_range(Some(aged(days)), &backwards));
+        }
+    }
+
+    #[test]
+    fn test_checksum_only_depends_on_totals() {
+        let usage = [(1000, 100), (7, 70), (1001, 200)];
+        let by_user = |order: &[usize]| {
+            let mut by_user = HashMap::with_capacity(order.len() * 8);
+            for &i in order {
+                let (uid, bytes) = usage[i];
+                let stats = UserStats {
+                    bytes,
+                    files: i as u64,
+                    ..UserStats::default()
+                };
+                by_user.insert(UserId(uid), stats);
+            }
+            by_user
+        };
+        // the same in every run, whatever order the map iterates in
+        let expected = "9148e3b5e372a45b1daef5743745936018f7ba523817fa76a66c41d28ad9599d";
+        for order in [[0, 1, 2], [2, 1, 0], [1, 2, 0]] {
+            assert_eq!(checksum(&by_user(&order)), expected);
+        }
+        let mut changed = by_user(&[0, 1, 2]);
+        changed.get_mut(&UserId(7)).unwrap().bytes += 1;
+        assert_ne!(checksum(&changed), expected);
+    }
+
+    #[test]
+    fn test_is_sparse() {
+        assert!(!is_sparse(0, 0));
+        assert!(!is_sparse(1000, 900));
+        assert!(is_sparse(1000, 899));
+        assert!(is_sparse(4 << 60, 0));
+        assert!(!is_sparse(u64::MAX, u64::MAX));
+    }
+
+    #[test]
+    fn test_other_users_has_no_uid() {
+        let names = UserNames::anonymized(false, [UserId(1000), OTHER_USERS].into_iter());
+        assert_eq!(names.name(UserId(1000)), "user_1");
+        assert_eq!(names.name(OTHER_USERS), "(other)");
+        assert_eq!(names.uid(OTHER_USERS), Value::Missing);
+        let mut mapping = Vec::new();
+        names.write_mapping(&mut mapping).unwrap();
+        assert_eq!(String::from_utf8(mapping).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_anonymize_covers_side_listings() {
+        let root = std::env::temp_dir().join(format!("du-by-user-anon-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("na\u{ef}ve"), "x").unwrap();
+        let uid = users::get_current_uid();
+        let range = format!("{}..={}", uid, uid);
+        let matches = cli().get_matches_from([
+            "du-by-user",
+            "--show-rename-hazards",
+            "--exclude-uid-range",
+            &range,
+            root.to_str().unwrap(),
+        ]);
+        let scan = scan(&root, &ScanOptions::from_matches(&matches));
+        std::fs::remove_dir_all(&root).unwrap();
+        // the filters leave the owner out of the totals but not the listing
+        assert!(scan.by_user.is_empty());
+        let names = UserNames::anonymized(false, scan.owners());
+        assert_eq!(names.name(UserId(uid)), "user_1");
+    }
+
+    #[test]
+    fn test_stdin_listing_filters() {
+        let input = concat!(
+            "  1234      8 -rw-r--r--   1 1001     1001         5000 Oct 14 18:47 /data/a.iso\n",
+            "  1235      8 -rw-r--r--   1 1001     1001           10 Oct 14 18:47 /data/cache/b.txt\n",
+            "  1236      8 -rw-r--r--   1 1001     1001           20 Oct 14 18:47 /data/c.txt\n",
+        );
+        let matches = cli().get_matches_from([
+            "du-by-user",
+            "--stdin",
+            "--include-extension",
+            "txt",
+            "--exclude",
+            "/data/cache",
+        ]);
+        let options = ScanOptions::from_matches(&matches);
+        let scan = scan_listing(
+            input.as_bytes(),
+            StdinFormat::FindLs,
+            &options,
+            &HashMap::new(),
+        );
+        let stats = &scan.by_user[&UserId(1001)];
+        assert_eq!((stats.bytes, stats.files), (20, 1));
+    }
+
+    #[test]
+    fn test_hard_links_counted_once() {
+        let root = std::env::temp_dir().join(format!("du-by-user-links-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a"), [0; 10]).unwrap();
+        std::fs::hard_link(root.join("a"), root.join("b")).unwrap();
+        let totals = |args: &[&str]| {
+            let matches = cli().get_matches_from(
+                ["du-by-user"]
+                    .iter()
+                    .chain(args)
+                    .chain([&root.to_str().unwrap()]),
+            );
+            let scan = scan(&root, &ScanOptions::from_matches(&matches));
+            let stats = &scan.by_user[&UserId(users::get_current_uid())];
+            (stats.bytes, stats.files)
+        };
+        let (once, every) = (totals(&[]), totals(&["--count-links"]));
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(once, (10, 1));
+        assert_eq!(every, (20, 2));
+    }
+
+    #[test]
+    fn test_report_stem_stays_in_dir() {
+        assert_eq!(report_stem("alice", UserId(1000)), "alice");
+        assert_eq!(report_stem("../../etc/x", UserId(1000)), "1000");
+        assert_eq!(report_stem("..", UserId(1000)), "1000");
+        assert_eq!(report_stem("a/b", UserId(1000)), "1000");
+        assert_eq!(report_stem("", UserId(1000)), "1000");
+    }
+
+    #[test]
+    fn test_cleanup_script_keeps_newlines_commented() {
+        let script =
+            std::env::temp_dir().join(format!("du-by-user-cleanup-{}.sh", std::process::id()));
+        let path = std::path::PathBuf::from("/tmp/x\nrm -rf ~ #'\x01");
+        let reports = HashMap::from([(UserId(3_999_999_999), vec![(1, path)])]);
+        write_cleanup_script(&script, &reports, &HashMap::new(), None, false).unwrap();
+        let contents = std::fs::read_to_string(&script).unwrap();
+        std::fs::remove_file(&script).unwrap();
+        assert!(contents
+            .lines()
+            .skip(1)
+            .all(|line| line.is_empty() || line.starts_with('#')));
+        assert!(contents.ends_with("# rm -f -- $'/tmp/x\\nrm -rf ~ #\\'\\001'\n"));
     }
 }
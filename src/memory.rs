@@ -0,0 +1,99 @@
+//! A rough count of the heap in use, for `--max-memory`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The system allocator, keeping a running total of what's allocated
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+// SAFETY: every call is passed straight through to `System`
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// Bytes currently allocated on the heap, not counting the allocator's own
+/// overhead
+pub fn allocated() -> u64 {
+    ALLOCATED.load(Ordering::Relaxed) as u64
+}
+
+/// Whether the per-file details that grow with the tree (file sizes for
+/// percentiles, `--per-user-report` listings, `--report-duplicate-content`
+/// candidates and so on) may still be collected
+#[derive(Debug)]
+pub struct MemoryLimit {
+    limit: Option<u64>,
+    exceeded: bool,
+}
+
+impl MemoryLimit {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            exceeded: false,
+        }
+    }
+
+    /// False from the first time the heap is over the limit on, which is
+    /// warned about once; whatever was collected until then is still used
+    pub fn allows_more(&mut self) -> bool {
+        if !self.exceeded && self.limit.is_some_and(|limit| allocated() > limit) {
+            log::warn!(
+                "over --max-memory with {} bytes allocated; percentiles, per-user reports, sparse files, duplicate content, link groups and snapshot inodes only cover the files seen so far",
+                allocated()
+            );
+            self.exceeded = true;
+        }
+        !self.exceeded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{allocated, MemoryLimit};
+
+    #[test]
+    fn test_memory_limit() {
+        let kept = vec![0_u8; 4096];
+        assert!(allocated() >= kept.len() as u64);
+        assert!(MemoryLimit::new(None).allows_more());
+        let mut limit = MemoryLimit::new(Some(1));
+        assert!(!limit.allows_more());
+        // once over, the limit stays exceeded
+        assert!(!limit.allows_more());
+        drop(kept);
+    }
+}
@@ -0,0 +1,70 @@
+//! Finding bind mounts in `/proc/self/mountinfo`, for `--include-bind-mounts`.
+
+/// Every device that holds a mount of the same source as `device` (`device`
+/// itself included), so that bind mounts of it count as the same filesystem
+#[cfg(target_os = "linux")]
+pub fn same_source_devices(device: u64) -> std::io::Result<Vec<u64>> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+    Ok(same_source(&mountinfo, device))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn same_source_devices(device: u64) -> std::io::Result<Vec<u64>> {
+    Ok(vec![device])
+}
+
+/// One line of mountinfo: the device, and the filesystem type and source that
+/// come after the `-` separator
+#[cfg(target_os = "linux")]
+fn parse_line(line: &str) -> Option<(u64, &str, &str)> {
+    let mut fields = line.split(' ');
+    let (major, minor) = fields.nth(2)?.split_once(':')?;
+    let device = libc::makedev(major.parse().ok()?, minor.parse().ok()?);
+    let mut fields = fields.skip_while(|&field| field != "-").skip(1);
+    Some((device, fields.next()?, fields.next()?))
+}
+
+#[cfg(target_os = "linux")]
+fn same_source(mountinfo: &str, device: u64) -> Vec<u64> {
+    let mounts = mountinfo.lines().filter_map(parse_line).collect::<Vec<_>>();
+    let sources = mounts
+        .iter()
+        .filter(|(dev, _, _)| *dev == device)
+        .map(|&(_, fstype, source)| (fstype, source))
+        .collect::<Vec<_>>();
+    let mut devices = vec![device];
+    for &(dev, fstype, source) in &mounts {
+        if !devices.contains(&dev) && sources.contains(&(fstype, source)) {
+            devices.push(dev);
+        }
+    }
+    devices
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+23 22 0:21 / /proc rw,nosuid shared:2 - proc proc rw
+30 22 0:45 /srv /mnt/srv rw,relatime shared:1 - ext4 /dev/sda1 rw
+31 22 8:17 / /home rw,relatime - ext4 /dev/sdb1 rw
+";
+
+    #[test]
+    fn test_same_source() {
+        let root = libc::makedev(8, 1);
+        assert_eq!(
+            same_source(MOUNTINFO, root),
+            vec![root, libc::makedev(0, 45)]
+        );
+        let home = libc::makedev(8, 17);
+        assert_eq!(same_source(MOUNTINFO, home), vec![home]);
+    }
+
+    #[test]
+    fn test_unknown_device() {
+        assert_eq!(same_source(MOUNTINFO, 12345), vec![12345]);
+    }
+}
@@ -0,0 +1,1222 @@
+use std::io::Write;
+
+use du_by_user::size::SizeFormatter;
+use du_by_user::time::TimeFormat;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Tsv,
+    /// Plain bytes and UIDs, without going through the `SizeFormatter`
+    MachineReadable,
+    Csv,
+    Json,
+    JsonLines,
+    Table,
+    Markdown,
+    /// Prometheus text exposition format
+    Metrics,
+    /// InfluxDB line protocol
+    InfluxDb,
+    /// Graphite plaintext protocol
+    Graphite,
+    /// SQL `INSERT` statements
+    Sql,
+    Xml,
+    Toml,
+    /// Text like `sar -r` prints
+    Sysstat,
+}
+
+impl OutputFormat {
+    pub const NAMES: [&'static str; 14] = [
+        "tsv",
+        "csv",
+        "json",
+        "jsonlines",
+        "ndjson",
+        "table",
+        "markdown",
+        "metrics",
+        "influxdb",
+        "graphite",
+        "sql",
+        "xml",
+        "toml",
+        "sysstat",
+    ];
+
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        if matches.is_present("ndjson") {
+            return Self::JsonLines;
+        } else if matches.is_present("machine-readable") {
+            return Self::MachineReadable;
+        }
+        match matches.value_of("output-format") {
+            Some("csv") => Self::Csv,
+            Some("json") => Self::Json,
+            Some("jsonlines" | "ndjson") => Self::JsonLines,
+            Some("table") => Self::Table,
+            Some("markdown") => Self::Markdown,
+            Some("metrics") => Self::Metrics,
+            Some("influxdb") => Self::InfluxDb,
+            Some("graphite") => Self::Graphite,
+            Some("sql") => Self::Sql,
+            Some("xml") => Self::Xml,
+            Some("toml") => Self::Toml,
+            Some("sysstat") => Self::Sysstat,
+            _ => Self::Tsv,
+        }
+    }
+
+    /// Whether free-form lines (section headers, extra listings) can be mixed
+    /// into the output without breaking it
+    pub fn is_text(&self) -> bool {
+        matches!(
+            self,
+            Self::Tsv | Self::MachineReadable | Self::Table | Self::Markdown
+        )
+    }
+
+    pub fn is_json(&self) -> bool {
+        matches!(self, Self::Json | Self::JsonLines)
+    }
+}
+
+/// How text that isn't ASCII is written, for `--output-encoding`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputEncoding {
+    /// As it is
+    Utf8,
+    /// With everything outside ASCII written as `\uXXXX` (UTF-16, like JSON)
+    Ascii,
+}
+
+impl OutputEncoding {
+    pub const NAMES: [&'static str; 2] = ["utf8", "ascii"];
+
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        match matches.value_of("output-encoding") {
+            Some("ascii") => Self::Ascii,
+            _ => Self::Utf8,
+        }
+    }
+
+    pub fn encode(&self, text: String) -> String {
+        if *self == Self::Utf8 || text.is_ascii() {
+            return text;
+        }
+        let mut encoded = String::with_capacity(text.len());
+        for c in text.chars() {
+            if c.is_ascii() {
+                encoded.push(c);
+            } else {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    encoded.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+        encoded
+    }
+}
+
+/// How reported paths are rewritten, for `--strip-path-prefix` and
+/// `--add-path-prefix`: a scan of a disk image mounted at `/mnt/disk` can
+/// report paths as they are on the system the image came from
+#[derive(Debug, Clone, Default)]
+pub struct PathPrefix {
+    /// Taken off the front of every path; paths outside it are left alone
+    pub strip: Option<std::path::PathBuf>,
+    /// Put in front of every path (that `strip` was taken off, if given)
+    pub add: Option<std::path::PathBuf>,
+}
+
+impl PathPrefix {
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        Self {
+            strip: matches.value_of("strip-path-prefix").map(Into::into),
+            add: matches.value_of("add-path-prefix").map(Into::into),
+        }
+    }
+
+    pub fn apply(&self, path: &std::path::Path) -> std::path::PathBuf {
+        let rest = match &self.strip {
+            Some(strip) => match path.strip_prefix(strip) {
+                Ok(rest) => rest,
+                Err(_) => return path.to_owned(),
+            },
+            None => path,
+        };
+        match &self.add {
+            // joining an absolute path would replace `add` rather than
+            // extend it
+            Some(add) => add.join(rest.strip_prefix("/").unwrap_or(rest)),
+            None if rest.as_os_str().is_empty() => ".".into(),
+            None => rest.to_owned(),
+        }
+    }
+
+    /// `table` with the paths in its path columns rewritten
+    fn rewrite<'t>(&self, table: &'t Table) -> std::borrow::Cow<'t, Table> {
+        if self.strip.is_none() && self.add.is_none() {
+            return std::borrow::Cow::Borrowed(table);
+        }
+        let mut table = table.clone();
+        for row in &mut table.rows {
+            for (value, column) in row.iter_mut().zip(&table.columns) {
+                if let (true, Value::Text(path)) = (column.path, &*value) {
+                    let path = self.apply(std::path::Path::new(path));
+                    *value = Value::Text(path.display().to_string());
+                }
+            }
+        }
+        std::borrow::Cow::Owned(table)
+    }
+}
+
+/// A single cell of a report
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Size(u64),
+    /// A size that was clamped to this maximum, shown like `>10G`
+    Capped(u64),
+    /// A change in size, shown with an explicit sign
+    SizeDelta(i64),
+    Count(u64),
+    Id(u32),
+    /// A multiple of something, shown like `4.0×`
+    Ratio(f64),
+    Percent(f64),
+    /// An average of some per-file count, shown to two decimal places
+    Mean(f64),
+    Text(String),
+    /// Several counts in one cell, like a histogram's buckets; an array in
+    /// JSON and TOML, and joined with `/` elsewhere
+    Counts(Vec<u64>),
+    Missing,
+}
+
+impl Value {
+    fn is_numeric(&self) -> bool {
+        matches!(
+            self,
+            Self::Size(_)
+                | Self::Capped(_)
+                | Self::SizeDelta(_)
+                | Self::Count(_)
+                | Self::Ratio(_)
+                | Self::Percent(_)
+                | Self::Mean(_)
+        )
+    }
+
+    fn text(&self, formatter: &SizeFormatter) -> String {
+        match self {
+            Self::Size(size) => formatter.wrap(*size).to_string(),
+            Self::Capped(size) => format!(">{}", formatter.wrap(*size)),
+            Self::SizeDelta(0) => formatter.wrap(0).to_string(),
+            Self::SizeDelta(delta) => {
+                let sign = if *delta < 0 { '-' } else { '+' };
+                format!("{}{}", sign, formatter.wrap(delta.unsigned_abs()))
+            }
+            Self::Count(count) => count.to_string(),
+            Self::Id(id) => id.to_string(),
+            Self::Ratio(ratio) => format!("{:.1}\u{d7}", ratio),
+            Self::Percent(percent) => format!("{:.1}%", percent),
+            Self::Mean(mean) => format!("{:.2}", mean),
+            Self::Text(text) => text.clone(),
+            Self::Counts(_) => self.raw(),
+            Self::Missing => "-".to_owned(),
+        }
+    }
+
+    /// Like `text`, but with sizes as plain byte counts
+    fn raw(&self) -> String {
+        match self {
+            Self::Size(n) | Self::Capped(n) | Self::Count(n) => n.to_string(),
+            Self::SizeDelta(delta) => delta.to_string(),
+            Self::Id(id) => id.to_string(),
+            Self::Ratio(ratio) | Self::Percent(ratio) | Self::Mean(ratio) => ratio.to_string(),
+            Self::Text(text) => text.clone(),
+            Self::Counts(counts) => counts
+                .iter()
+                .map(u64::to_string)
+                .collect::<Vec<_>>()
+                .join("/"),
+            Self::Missing => "-".to_owned(),
+        }
+    }
+
+    fn toml(&self) -> Option<toml::Value> {
+        match self {
+            Self::Size(n) | Self::Capped(n) | Self::Count(n) => {
+                Some(toml::Value::Integer(*n as i64))
+            }
+            Self::SizeDelta(n) => Some(toml::Value::Integer(*n)),
+            Self::Id(n) => Some(toml::Value::Integer((*n).into())),
+            Self::Ratio(n) | Self::Percent(n) | Self::Mean(n) => Some(toml::Value::Float(*n)),
+            Self::Text(s) => Some(toml::Value::String(s.clone())),
+            Self::Counts(counts) => Some(toml::Value::Array(
+                counts
+                    .iter()
+                    .map(|&n| toml::Value::Integer(n as i64))
+                    .collect(),
+            )),
+            Self::Missing => None,
+        }
+    }
+
+    fn json(&self) -> serde_json::Value {
+        match self {
+            Self::Size(n) | Self::Capped(n) | Self::Count(n) => (*n).into(),
+            Self::SizeDelta(n) => (*n).into(),
+            Self::Id(n) => (*n).into(),
+            Self::Ratio(n) | Self::Percent(n) | Self::Mean(n) => (*n).into(),
+            Self::Text(s) => s.as_str().into(),
+            Self::Counts(counts) => counts.as_slice().into(),
+            Self::Missing => serde_json::Value::Null,
+        }
+    }
+}
+
+/// A report column; `key` names it in JSON and metrics output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Column {
+    pub name: &'static str,
+    pub key: &'static str,
+    /// Whether this column holds paths, which `table` output may shorten to
+    /// fit `--output-width` and which `--strip-path-prefix` rewrites
+    pub path: bool,
+}
+
+impl Column {
+    pub const fn new(name: &'static str, key: &'static str) -> Self {
+        Self {
+            name,
+            key,
+            path: false,
+        }
+    }
+
+    /// A column of paths, which give way first when the table is too wide
+    pub const fn path(name: &'static str, key: &'static str) -> Self {
+        Self {
+            name,
+            key,
+            path: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<Value>>,
+    /// Rows to show in red when colors are on; may be shorter than `rows`
+    pub alerts: Vec<bool>,
+}
+
+impl Table {
+    fn alert(&self, row: usize) -> bool {
+        self.alerts.get(row).copied().unwrap_or(false)
+    }
+
+    /// Whether each column holds numbers (and so should be right-aligned)
+    fn numeric_columns(&self) -> Vec<bool> {
+        (0..self.columns.len())
+            .map(|i| self.rows.iter().any(|row| row[i].is_numeric()))
+            .collect()
+    }
+}
+
+/// One JSON object per row, keeping the column order
+struct JsonRow<'t> {
+    columns: &'t [Column],
+    row: &'t [Value],
+}
+
+impl serde::Serialize for JsonRow<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(self.columns.len()))?;
+        for (column, value) in self.columns.iter().zip(self.row) {
+            map.serialize_entry(column.key, &value.json())?;
+        }
+        map.end()
+    }
+}
+
+/// Cut `text` down to `width` characters, ending in `…` (or `...` if
+/// `ascii`) if anything was lost
+fn truncate(text: String, width: usize, ascii: bool) -> String {
+    if text.chars().count() <= width {
+        return text;
+    }
+    let ellipsis = if ascii { "..." } else { "\u{2026}" };
+    let kept = width.saturating_sub(ellipsis.chars().count());
+    let mut short = text.chars().take(kept).collect::<String>();
+    short.push_str(ellipsis);
+    short
+}
+
+fn csv_quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Escape an InfluxDB measurement name, tag key or tag value
+fn influxdb_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+        .replace('\n', "\\n")
+}
+
+/// Make `text` usable as one node of a Graphite metric path
+fn graphite_node(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c == '.' || c.is_whitespace() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+fn sql_literal(value: &Value) -> String {
+    match value {
+        Value::Text(text) => format!("'{}'", text.replace('\'', "''")),
+        Value::Missing => "NULL".to_owned(),
+        value => value.raw(),
+    }
+}
+
+fn prometheus_escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Renders tables in the chosen output format
+pub struct Writer<'f> {
+    pub format: OutputFormat,
+    pub formatter: &'f SizeFormatter,
+    /// The scanned path, which metrics carry as a label
+    pub scan_root: &'f std::path::Path,
+    /// How wide `table` output may be, if limited
+    pub width: Option<usize>,
+    /// The InfluxDB measurement each row is written to
+    pub measurement: &'f str,
+    /// When the scan started, which InfluxDB points are timestamped with
+    pub timestamp: std::time::SystemTime,
+    /// Whether `tsv` and `table` output may use ANSI colors
+    pub color: bool,
+    /// The table that `sql` output inserts into
+    pub sql_table: &'f str,
+    /// Whether `sql` output starts with a `CREATE TABLE IF NOT EXISTS`
+    pub sql_create_table: bool,
+    /// Whether `tsv` and `machine-readable` output start with a `#` line
+    /// naming the columns (`csv` always has its header row)
+    pub comment_header: bool,
+    /// Whether to stick to ASCII for table borders, `×` and `…`
+    pub ascii: bool,
+    pub path_prefix: PathPrefix,
+}
+
+/// Totals for a whole scan, for `--report-scan-metadata`
+#[derive(Debug, Clone, Default)]
+pub struct ScanSummary {
+    pub total_bytes: u64,
+    pub total_files: u64,
+    pub total_dirs: u64,
+    pub elapsed: std::time::Duration,
+    /// Entries that could not be read
+    pub errors: usize,
+}
+
+/// The version of the `json` report layout, bumped whenever it changes in a
+/// way that older readers (such as `--compare`) would misread
+pub const SCHEMA_VERSION: u64 = 1;
+
+impl Writer<'_> {
+    /// Write a scan's main table. In `json` format the rows are wrapped in an
+    /// object that describes the scan, with the rows under `key`, and in `xml`
+    /// each row is an element named for what `key` lists (`users` become
+    /// `<user>`), and in `toml` the rows are a `[[key]]` array of tables;
+    /// every other format is the same as [`Writer::write`].
+    ///
+    /// `summary` is added to the `json` envelope, or as `# name: value`
+    /// comment lines before the `csv` header; other formats ignore it.
+    pub fn write_report<W: Write>(
+        &self,
+        out: &mut W,
+        table: &Table,
+        key: &str,
+        summary: Option<&ScanSummary>,
+    ) -> std::io::Result<()> {
+        let table = &*self.path_prefix.rewrite(table);
+        match self.format {
+            OutputFormat::Json => {}
+            OutputFormat::Csv if summary.is_some() => {
+                for (name, value) in self.metadata(summary) {
+                    writeln!(
+                        out,
+                        "# {}: {}",
+                        name,
+                        value.as_str().map_or(value.to_string(), str::to_owned)
+                    )?;
+                }
+                return self.write_table(out, table);
+            }
+            OutputFormat::Xml => {
+                return self.write_xml(out, table, key.strip_suffix('s').unwrap_or(key))
+            }
+            OutputFormat::Toml => return self.write_toml(out, table, key),
+            _ => return self.write_table(out, table),
+        }
+        let rows = table
+            .rows
+            .iter()
+            .map(|row| JsonRow {
+                columns: &table.columns,
+                row,
+            })
+            .collect::<Vec<_>>();
+        let mut envelope = self.metadata(summary);
+        envelope.insert(key.to_owned(), serde_json::json!(rows));
+        serde_json::to_writer(&mut *out, &envelope)?;
+        writeln!(out)
+    }
+
+    /// What is known about the scan itself, for self-describing output
+    fn metadata(
+        &self,
+        summary: Option<&ScanSummary>,
+    ) -> serde_json::Map<String, serde_json::Value> {
+        let serde_json::Value::Object(mut metadata) = serde_json::json!({
+            "schema_version": SCHEMA_VERSION,
+            "scan_root": self.scan_root.to_string_lossy(),
+            "scanned_at": TimeFormat::Iso8601.format(self.timestamp, self.timestamp),
+        }) else {
+            unreachable!("built from an object literal")
+        };
+        if let Some(summary) = summary {
+            metadata.extend([
+                ("total_bytes".to_owned(), summary.total_bytes.into()),
+                ("total_files".to_owned(), summary.total_files.into()),
+                ("total_dirs".to_owned(), summary.total_dirs.into()),
+                (
+                    "elapsed_seconds".to_owned(),
+                    summary.elapsed.as_secs_f64().into(),
+                ),
+                ("errors_count".to_owned(), summary.errors.into()),
+            ]);
+        }
+        metadata
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W, table: &Table) -> std::io::Result<()> {
+        self.write_table(out, &self.path_prefix.rewrite(table))
+    }
+
+    fn write_table<W: Write>(&self, out: &mut W, table: &Table) -> std::io::Result<()> {
+        let columns = &table.columns;
+        let rows = &table.rows;
+        if self.comment_header
+            && matches!(
+                self.format,
+                OutputFormat::Tsv | OutputFormat::MachineReadable
+            )
+        {
+            let names = columns.iter().map(|c| c.name).collect::<Vec<_>>();
+            writeln!(out, "# {}", names.join("\t"))?;
+        }
+        match self.format {
+            OutputFormat::Tsv => {
+                for (i, row) in rows.iter().enumerate() {
+                    let cells = row.iter().map(|v| self.text(v));
+                    let line = cells.collect::<Vec<_>>().join("\t");
+                    writeln!(out, "{}", self.paint(table, i, line))?;
+                }
+            }
+            OutputFormat::MachineReadable => {
+                for row in rows {
+                    let cells = row.iter().map(Value::raw);
+                    writeln!(out, "{}", cells.collect::<Vec<_>>().join("\t"))?;
+                }
+            }
+            OutputFormat::Csv => {
+                let header = columns.iter().map(|c| csv_quote(c.name));
+                writeln!(out, "{}", header.collect::<Vec<_>>().join(","))?;
+                for row in rows {
+                    let cells = row.iter().map(|v| csv_quote(&self.text(v)));
+                    writeln!(out, "{}", cells.collect::<Vec<_>>().join(","))?;
+                }
+            }
+            OutputFormat::Json => {
+                let objects = rows
+                    .iter()
+                    .map(|row| JsonRow { columns, row })
+                    .collect::<Vec<_>>();
+                serde_json::to_writer(&mut *out, &objects)?;
+                writeln!(out)?;
+            }
+            OutputFormat::JsonLines => {
+                for row in rows {
+                    serde_json::to_writer(&mut *out, &JsonRow { columns, row })?;
+                    writeln!(out)?;
+                }
+            }
+            OutputFormat::Table => self.write_boxed(out, table)?,
+            OutputFormat::Markdown => {
+                let header = columns.iter().map(|c| c.name).collect::<Vec<_>>();
+                writeln!(out, "| {} |", header.join(" | "))?;
+                let numeric = table.numeric_columns();
+                let rule = numeric.iter().map(|&n| if n { "---:" } else { "---" });
+                writeln!(out, "|{}|", rule.collect::<Vec<_>>().join("|"))?;
+                for row in rows {
+                    let cells = row
+                        .iter()
+                        .map(|v| self.text(v).replace('|', "\\|"))
+                        .collect::<Vec<_>>();
+                    writeln!(out, "| {} |", cells.join(" | "))?;
+                }
+            }
+            OutputFormat::Metrics => self.write_metrics(out, table)?,
+            OutputFormat::InfluxDb => self.write_influxdb(out, table)?,
+            OutputFormat::Graphite => self.write_graphite(out, table)?,
+            OutputFormat::Sql => self.write_sql(out, table)?,
+            OutputFormat::Xml => self.write_xml(out, table, "row")?,
+            OutputFormat::Toml => self.write_toml(out, table, "rows")?,
+            OutputFormat::Sysstat => self.write_sysstat(out, table)?,
+        }
+        Ok(())
+    }
+
+    fn write_boxed<W: Write>(&self, out: &mut W, table: &Table) -> std::io::Result<()> {
+        let cells = table
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|v| self.text(v)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let widths = table
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain(std::iter::once(column.name.chars().count()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+        let widths = self.fit(table, widths);
+        let cells = cells
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .zip(&widths)
+                    .map(|(text, &width)| truncate(text, width, self.ascii))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let (horizontal, vertical) = if self.ascii {
+            ("-", "|")
+        } else {
+            ("\u{2500}", "\u{2502}")
+        };
+        let rule = |left: &str, middle: &str, right: &str| {
+            let segments = widths.iter().map(|w| horizontal.repeat(w + 2));
+            format!(
+                "{}{}{}",
+                left,
+                segments.collect::<Vec<_>>().join(middle),
+                right
+            )
+        };
+        let line = |texts: &[String], numeric: &[bool]| {
+            let padded = texts
+                .iter()
+                .zip(&widths)
+                .zip(numeric)
+                .map(|((text, width), numeric)| {
+                    let pad = " ".repeat(width - text.chars().count());
+                    if *numeric {
+                        format!(" {}{} ", pad, text)
+                    } else {
+                        format!(" {}{} ", text, pad)
+                    }
+                });
+            format!(
+                "{}{}{}",
+                vertical,
+                padded.collect::<Vec<_>>().join(vertical),
+                vertical
+            )
+        };
+        let headers = table
+            .columns
+            .iter()
+            .map(|c| c.name.to_owned())
+            .collect::<Vec<_>>();
+        let corners = |unicode: [&'static str; 3]| if self.ascii { ["+"; 3] } else { unicode };
+        let [left, middle, right] = corners(["\u{250c}", "\u{252c}", "\u{2510}"]);
+        writeln!(out, "{}", rule(left, middle, right))?;
+        writeln!(out, "{}", line(&headers, &vec![false; widths.len()]))?;
+        let [left, middle, right] = corners(["\u{251c}", "\u{253c}", "\u{2524}"]);
+        writeln!(out, "{}", rule(left, middle, right))?;
+        let numeric = table.numeric_columns();
+        for (i, texts) in cells.iter().enumerate() {
+            writeln!(out, "{}", self.paint(table, i, line(texts, &numeric)))?;
+        }
+        let [left, middle, right] = corners(["\u{2514}", "\u{2534}", "\u{2518}"]);
+        writeln!(out, "{}", rule(left, middle, right))
+    }
+
+    /// How `value` is shown in the text formats
+    fn text(&self, value: &Value) -> String {
+        match value {
+            Value::Ratio(ratio) if self.ascii => format!("{:.1}x", ratio),
+            value => value.text(self.formatter),
+        }
+    }
+
+    /// Wrap the text of row `i` in red if it is an alert and colors are on
+    fn paint(&self, table: &Table, i: usize, text: String) -> String {
+        if self.color && table.alert(i) {
+            format!("\x1b[31m{}\x1b[0m", text)
+        } else {
+            text
+        }
+    }
+
+    /// Shrink the truncatable columns, widest first, until the table fits in
+    /// `self.width` (or they can't get any narrower)
+    fn fit(&self, table: &Table, mut widths: Vec<usize>) -> Vec<usize> {
+        let Some(limit) = self.width else {
+            return widths;
+        };
+        // each column is padded by a space on both sides and followed by a border
+        let total = |widths: &[usize]| widths.iter().map(|w| w + 3).sum::<usize>() + 1;
+        while total(&widths) > limit {
+            let widest = (0..widths.len())
+                .filter(|&i| {
+                    let column = &table.columns[i];
+                    column.path && widths[i] > column.name.chars().count().max(2)
+                })
+                .max_by_key(|&i| widths[i]);
+            match widest {
+                Some(i) => widths[i] -= 1,
+                None => break,
+            }
+        }
+        widths
+    }
+
+    /// Each numeric column becomes a gauge, labelled by the non-numeric ones
+    fn write_metrics<W: Write>(&self, out: &mut W, table: &Table) -> std::io::Result<()> {
+        let scan_root = self.scan_root.to_string_lossy();
+        for (i, column) in table.columns.iter().enumerate() {
+            let samples = table
+                .rows
+                .iter()
+                .filter(|row| row[i].is_numeric())
+                .collect::<Vec<_>>();
+            if samples.is_empty() {
+                continue;
+            }
+            let name = format!("du_by_user_{}", column.key);
+            writeln!(out, "# TYPE {} gauge", name)?;
+            for row in samples {
+                let mut labels = vec![format!("path=\"{}\"", prometheus_escape(&scan_root))];
+                for (label, value) in table.columns.iter().zip(row) {
+                    match value {
+                        Value::Text(_) | Value::Id(_) => labels.push(format!(
+                            "{}=\"{}\"",
+                            label.key,
+                            prometheus_escape(&value.raw())
+                        )),
+                        _ => {}
+                    }
+                }
+                writeln!(out, "{}{{{}}} {}", name, labels.join(","), row[i].raw())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `du_by_user.<text columns>.<numeric column> <value> <timestamp>` for
+    /// every numeric cell
+    fn write_graphite<W: Write>(&self, out: &mut W, table: &Table) -> std::io::Result<()> {
+        let timestamp = self
+            .timestamp
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        for row in &table.rows {
+            let mut prefix = vec!["du_by_user".to_owned()];
+            for value in row {
+                if let Value::Text(text) = value {
+                    prefix.push(graphite_node(text));
+                }
+            }
+            let prefix = prefix.join(".");
+            for (column, value) in table.columns.iter().zip(row) {
+                if value.is_numeric() {
+                    let name = graphite_node(column.key);
+                    writeln!(out, "{}.{} {} {}", prefix, name, value.raw(), timestamp)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// In the style of `sar -r`: a `Linux <release> (<host>)` banner, then
+    /// one line per row prefixed with the local time of the scan. As with
+    /// sysstat, sizes are in kilobytes, in `kb` columns.
+    fn write_sysstat<W: Write>(&self, out: &mut W, table: &Table) -> std::io::Result<()> {
+        let kernel = |name: &str| {
+            std::fs::read_to_string(format!("/proc/sys/kernel/{}", name))
+                .map_or_else(|_| "unknown".to_owned(), |value| value.trim().to_owned())
+        };
+        let time: chrono::DateTime<chrono::Local> = self.timestamp.into();
+        writeln!(
+            out,
+            "Linux {} ({}) \t{} \t_{}_\n",
+            kernel("osrelease"),
+            kernel("hostname"),
+            time.format("%m/%d/%Y"),
+            std::env::consts::ARCH
+        )?;
+        let headers = table.columns.iter().enumerate().map(|(i, column)| {
+            match table.rows.first().map(|row| &row[i]) {
+                Some(Value::Size(_) | Value::Capped(_) | Value::SizeDelta(_)) => {
+                    format!("kb{}", column.name)
+                }
+                Some(Value::Percent(_)) if column.name == "percent" => "%used".to_owned(),
+                Some(Value::Percent(_)) => format!("%{}", column.name),
+                _ => column.name.to_owned(),
+            }
+        });
+        let cells = table.rows.iter().map(|row| {
+            row.iter()
+                .map(|value| match value {
+                    Value::Size(n) | Value::Capped(n) => (n / 1024).to_string(),
+                    Value::SizeDelta(n) => (n / 1024).to_string(),
+                    Value::Ratio(n) | Value::Percent(n) | Value::Mean(n) => format!("{:.2}", n),
+                    value => value.raw(),
+                })
+                .collect::<Vec<_>>()
+        });
+        let lines = std::iter::once(headers.collect::<Vec<_>>())
+            .chain(cells)
+            .collect::<Vec<_>>();
+        let widths = (0..table.columns.len()).map(|i| {
+            let widest = lines.iter().map(|line| line[i].len()).max().unwrap_or(0);
+            widest.max(9)
+        });
+        let widths = widths.collect::<Vec<_>>();
+        let time = time.format("%H:%M:%S").to_string();
+        for line in lines {
+            let cells = line
+                .iter()
+                .zip(&widths)
+                .map(|(cell, &width)| format!("{:>width$}", cell));
+            writeln!(out, "{} {}", time, cells.collect::<Vec<_>>().join(" "))?;
+        }
+        Ok(())
+    }
+
+    /// A `<disk-usage>` document with one empty `element` per row, whose
+    /// attributes are the column keys (dashed rather than underscored). The
+    /// user column of a `<user>` is its `name`.
+    fn write_xml<W: Write>(
+        &self,
+        out: &mut W,
+        table: &Table,
+        element: &str,
+    ) -> std::io::Result<()> {
+        use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, Event};
+
+        let mut xml = quick_xml::Writer::new_with_indent(&mut *out, b' ', 2);
+        let scan_root = self.scan_root.to_string_lossy();
+        let scanned_at = TimeFormat::Iso8601.format(self.timestamp, self.timestamp);
+        xml.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+        xml.write_event(Event::Start(BytesStart::new("disk-usage").with_attributes(
+            [
+                ("scan-root", scan_root.as_ref()),
+                ("scanned-at", scanned_at.as_str()),
+            ],
+        )))?;
+        for row in &table.rows {
+            let attributes = table
+                .columns
+                .iter()
+                .zip(row)
+                .filter(|(_, value)| **value != Value::Missing)
+                .map(|(column, value)| {
+                    let name = match column.key {
+                        "user" if element == "user" => "name".to_owned(),
+                        key => key.replace('_', "-"),
+                    };
+                    (name, value.raw())
+                })
+                .collect::<Vec<_>>();
+            xml.write_event(Event::Empty(
+                BytesStart::new(element).with_attributes(
+                    attributes
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), value.as_str())),
+                ),
+            ))?;
+        }
+        xml.write_event(Event::End(BytesEnd::new("disk-usage")))?;
+        writeln!(out)
+    }
+
+    /// A TOML document describing the scan, with the rows as an array of tables
+    /// under `key`. TOML has no null, so missing values are left out, and the
+    /// user column of a `[[users]]` entry is its `name`.
+    fn write_toml<W: Write>(&self, out: &mut W, table: &Table, key: &str) -> std::io::Result<()> {
+        let rows = table
+            .rows
+            .iter()
+            .map(|row| {
+                let entry = table
+                    .columns
+                    .iter()
+                    .zip(row)
+                    .filter_map(|(column, value)| {
+                        let name = match column.key {
+                            "user" if key == "users" => "name",
+                            key => key,
+                        };
+                        Some((name.to_owned(), value.toml()?))
+                    })
+                    .collect::<toml::Table>();
+                toml::Value::Table(entry)
+            })
+            .collect();
+        let mut document = toml::Table::new();
+        document.insert(
+            "scan_root".to_owned(),
+            self.scan_root.to_string_lossy().into_owned().into(),
+        );
+        document.insert(
+            "scanned_at".to_owned(),
+            TimeFormat::Iso8601
+                .format(self.timestamp, self.timestamp)
+                .into(),
+        );
+        document.insert(key.to_owned(), toml::Value::Array(rows));
+        let text = toml::to_string(&document).map_err(std::io::Error::other)?;
+        write!(out, "{}", text)
+    }
+
+    /// One `INSERT` per row, with the scan time as an extra `scanned_at` column
+    fn write_sql<W: Write>(&self, out: &mut W, table: &Table) -> std::io::Result<()> {
+        let scanned_at = TimeFormat::Iso8601.format(self.timestamp, self.timestamp);
+        let names = std::iter::once("scanned_at")
+            .chain(table.columns.iter().map(|c| c.key))
+            .map(|name| format!("\"{}\"", name))
+            .collect::<Vec<_>>();
+        if self.sql_create_table {
+            let types = (0..table.columns.len()).map(|i| {
+                let sample = table.rows.iter().map(|row| &row[i]);
+                match sample.into_iter().find(|v| **v != Value::Missing) {
+                    Some(Value::Ratio(_) | Value::Percent(_) | Value::Mean(_)) => {
+                        "DOUBLE PRECISION"
+                    }
+                    Some(Value::Text(_) | Value::Counts(_)) | None => "TEXT",
+                    Some(_) => "BIGINT",
+                }
+            });
+            let definitions = names
+                .iter()
+                .zip(std::iter::once("TEXT").chain(types))
+                .map(|(name, kind)| format!("{} {}", name, kind))
+                .collect::<Vec<_>>();
+            writeln!(
+                out,
+                "CREATE TABLE IF NOT EXISTS {} ({});",
+                self.sql_table,
+                definitions.join(", ")
+            )?;
+        }
+        for row in &table.rows {
+            let values = std::iter::once(format!("'{}'", scanned_at))
+                .chain(row.iter().map(sql_literal))
+                .collect::<Vec<_>>();
+            writeln!(
+                out,
+                "INSERT INTO {} ({}) VALUES ({});",
+                self.sql_table,
+                names.join(", "),
+                values.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+
+    /// One point per row: the non-numeric columns (and the scanned path) are
+    /// tags, and the numeric ones are fields
+    fn write_influxdb<W: Write>(&self, out: &mut W, table: &Table) -> std::io::Result<()> {
+        let timestamp = self
+            .timestamp
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos());
+        let path = influxdb_escape(&self.scan_root.to_string_lossy());
+        for row in &table.rows {
+            let mut tags = vec![influxdb_escape(self.measurement)];
+            let mut fields = Vec::new();
+            for (column, value) in table.columns.iter().zip(row) {
+                let key = influxdb_escape(column.key);
+                match value {
+                    Value::Text(_) | Value::Id(_) | Value::Counts(_) => {
+                        tags.push(format!("{}={}", key, influxdb_escape(&value.raw())))
+                    }
+                    Value::Size(n) | Value::Capped(n) | Value::Count(n) => {
+                        fields.push(format!("{}={}i", key, n))
+                    }
+                    Value::SizeDelta(n) => fields.push(format!("{}={}i", key, n)),
+                    Value::Ratio(n) | Value::Percent(n) | Value::Mean(n) => {
+                        fields.push(format!("{}={}", key, n))
+                    }
+                    Value::Missing => {}
+                }
+            }
+            if fields.is_empty() {
+                continue;
+            }
+            if !path.is_empty() {
+                tags.push(format!("path={}", path));
+            }
+            writeln!(out, "{} {} {}", tags.join(","), fields.join(","), timestamp)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Column, OutputEncoding, OutputFormat, PathPrefix, Table, Value, Writer};
+    use du_by_user::size::{SizeFormatter, SizeMode};
+
+    fn render(format: OutputFormat) -> String {
+        render_with_header(format, false)
+    }
+
+    fn render_with_header(format: OutputFormat, comment_header: bool) -> String {
+        let table = Table {
+            columns: vec![Column::new("size", "bytes"), Column::new("user", "user")],
+            rows: vec![
+                vec![Value::Size(2048), Value::Text("a,\"b\"".to_owned())],
+                vec![Value::Size(10), Value::Text("root".to_owned())],
+            ],
+            alerts: vec![false, true],
+        };
+        let formatter = SizeFormatter::new(SizeMode::Kilobytes, false);
+        let writer = Writer {
+            format,
+            formatter: &formatter,
+            scan_root: std::path::Path::new("/data"),
+            width: None,
+            measurement: "du_by_user",
+            timestamp: std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_699_900_000),
+            color: false,
+            sql_table: "disk_usage",
+            sql_create_table: true,
+            comment_header,
+            ascii: false,
+            path_prefix: PathPrefix::default(),
+        };
+        let mut out = Vec::new();
+        writer.write(&mut out, &table).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_comment_header() {
+        assert_eq!(
+            render_with_header(OutputFormat::Tsv, true),
+            "# size\tuser\n2\ta,\"b\"\n0\troot\n"
+        );
+        // csv already has a header row, and the flag doesn't add another
+        assert_eq!(
+            render_with_header(OutputFormat::Csv, true),
+            render(OutputFormat::Csv)
+        );
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(render(OutputFormat::Tsv), "2\ta,\"b\"\n0\troot\n");
+        assert_eq!(
+            render(OutputFormat::Csv),
+            "size,user\n2,\"a,\"\"b\"\"\"\n0,root\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Json),
+            "[{\"bytes\":2048,\"user\":\"a,\\\"b\\\"\"},{\"bytes\":10,\"user\":\"root\"}]\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Metrics),
+            "# TYPE du_by_user_bytes gauge\n\
+             du_by_user_bytes{path=\"/data\",user=\"a,\\\"b\\\"\"} 2048\n\
+             du_by_user_bytes{path=\"/data\",user=\"root\"} 10\n"
+        );
+        assert_eq!(
+            render(OutputFormat::InfluxDb),
+            "du_by_user,user=a\\,\"b\",path=/data bytes=2048i 1699900000000000000\n\
+             du_by_user,user=root,path=/data bytes=10i 1699900000000000000\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Graphite),
+            "du_by_user.a,\"b\".bytes 2048 1699900000\n\
+             du_by_user.root.bytes 10 1699900000\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Sql).lines().collect::<Vec<_>>(),
+            [
+                "CREATE TABLE IF NOT EXISTS disk_usage (\"scanned_at\" TEXT, \"bytes\" BIGINT, \"user\" TEXT);",
+                "INSERT INTO disk_usage (\"scanned_at\", \"bytes\", \"user\") VALUES ('2023-11-13T18:26:40Z', 2048, 'a,\"b\"');",
+                "INSERT INTO disk_usage (\"scanned_at\", \"bytes\", \"user\") VALUES ('2023-11-13T18:26:40Z', 10, 'root');",
+            ]
+        );
+        assert_eq!(
+            render(OutputFormat::Xml).lines().nth(2),
+            Some("  <row bytes=\"2048\" user=\"a,&quot;b&quot;\"/>")
+        );
+        assert_eq!(
+            render(OutputFormat::Toml),
+            "scan_root = \"/data\"\n\
+             scanned_at = \"2023-11-13T18:26:40Z\"\n\
+             \n\
+             [[rows]]\n\
+             bytes = 2048\n\
+             user = 'a,\"b\"'\n\
+             \n\
+             [[rows]]\n\
+             bytes = 10\n\
+             user = \"root\"\n"
+        );
+        // the banner and times depend on the machine running the tests
+        assert_eq!(
+            render(OutputFormat::Sysstat)
+                .lines()
+                .skip(2)
+                .map(|line| &line[9..])
+                .collect::<Vec<_>>(),
+            [
+                "   kbsize      user",
+                "        2     a,\"b\"",
+                "        0      root"
+            ]
+        );
+        assert_eq!(
+            render(OutputFormat::Table),
+            "┌──────┬───────┐\n\
+             │ size │ user  │\n\
+             ├──────┼───────┤\n\
+             │    2 │ a,\"b\" │\n\
+             │    0 │ root  │\n\
+             └──────┴───────┘\n"
+        );
+    }
+
+    #[test]
+    fn test_output_width() {
+        let table = Table {
+            columns: vec![Column::new("user", "user"), Column::path("path", "path")],
+            rows: vec![vec![
+                Value::Text("root".to_owned()),
+                Value::Text("/var/lib/something".to_owned()),
+            ]],
+            alerts: Vec::new(),
+        };
+        let formatter = SizeFormatter::new(SizeMode::Bytes, false);
+        let mut writer = Writer {
+            format: OutputFormat::Table,
+            formatter: &formatter,
+            scan_root: std::path::Path::new("/"),
+            width: Some(18),
+            measurement: "du_by_user",
+            timestamp: std::time::UNIX_EPOCH,
+            color: false,
+            sql_table: "disk_usage",
+            sql_create_table: true,
+            comment_header: false,
+            ascii: false,
+            path_prefix: PathPrefix::default(),
+        };
+        let mut out = Vec::new();
+        writer.write(&mut out, &table).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "┌──────┬─────────┐\n\
+             │ user │ path    │\n\
+             ├──────┼─────────┤\n\
+             │ root │ /var/l… │\n\
+             └──────┴─────────┘\n"
+        );
+
+        writer.ascii = true;
+        let mut out = Vec::new();
+        writer.write(&mut out, &table).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "+------+---------+\n\
+             | user | path    |\n\
+             +------+---------+\n\
+             | root | /var... |\n\
+             +------+---------+\n"
+        );
+    }
+
+    #[test]
+    fn test_path_prefix() {
+        let path = std::path::Path::new("/mnt/disk/etc/passwd");
+        let prefix = |strip: Option<&str>, add: Option<&str>| PathPrefix {
+            strip: strip.map(Into::into),
+            add: add.map(Into::into),
+        };
+        let moved = prefix(Some("/mnt/disk"), Some("/"));
+        assert_eq!(moved.apply(path), std::path::Path::new("/etc/passwd"));
+        assert_eq!(
+            moved.apply(std::path::Path::new("/mnt/disk")),
+            std::path::Path::new("/")
+        );
+        // outside the stripped prefix, nothing is added either
+        assert_eq!(
+            moved.apply(std::path::Path::new("/home/x")),
+            std::path::Path::new("/home/x")
+        );
+        assert_eq!(
+            prefix(Some("/mnt/disk"), None).apply(path),
+            std::path::Path::new("etc/passwd")
+        );
+        assert_eq!(
+            prefix(None, Some("/host")).apply(path),
+            std::path::Path::new("/host/mnt/disk/etc/passwd")
+        );
+    }
+
+    #[test]
+    fn test_ascii_encoding() {
+        let name = "jos\u{e9}\u{1f600}".to_owned();
+        assert_eq!(OutputEncoding::Utf8.encode(name.clone()), name);
+        assert_eq!(
+            OutputEncoding::Ascii.encode(name),
+            "jos\\u00e9\\ud83d\\ude00"
+        );
+        assert_eq!(OutputEncoding::Ascii.encode("root".to_owned()), "root");
+    }
+}
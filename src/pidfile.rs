@@ -0,0 +1,116 @@
+//! The `--pid-file` lock, which keeps two copies of a long-running
+//! `du-by-user` from writing to the same place.
+
+use std::io::{Read, Seek, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// The pid file to remove if SIGTERM or SIGINT ends the process, or null once
+/// it's been dropped; a C string, so the handler needn't allocate
+static SIGNAL_PATH: AtomicPtr<libc::c_char> = AtomicPtr::new(std::ptr::null_mut());
+
+/// A file holding our PID under an exclusive `flock(2)`, removed on drop or
+/// when SIGTERM or SIGINT ends the process.
+///
+/// A file left behind by a killed process is still safe to reuse: the kernel
+/// releases the lock when the process exits, so only a live holder blocks us.
+#[derive(Debug)]
+pub struct PidFile {
+    path: std::path::PathBuf,
+    file: std::fs::File,
+}
+
+impl PidFile {
+    pub fn create(path: &std::path::Path) -> std::io::Result<Self> {
+        let mut file = loop {
+            let file = Self::lock(path)?;
+            // the holder before us may have removed the file between our
+            // open(2) and its unlock, leaving us with a lock on nothing
+            let locked = file.metadata()?;
+            match std::fs::metadata(path) {
+                Ok(current) if (current.dev(), current.ino()) == (locked.dev(), locked.ino()) => {
+                    break file;
+                }
+                Ok(_) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        };
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())?;
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+        // leaked rather than freed on drop, since a handler could be reading it
+        SIGNAL_PATH.store(c_path.into_raw(), Ordering::SeqCst);
+        for signal in [libc::SIGTERM, libc::SIGINT] {
+            unsafe { libc::signal(signal, remove_on_signal as *const () as libc::sighandler_t) };
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+        })
+    }
+
+    fn lock(path: &std::path::Path) -> std::io::Result<std::fs::File> {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(file),
+            Err(std::fs::TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                file.read_to_string(&mut holder)?;
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::WouldBlock,
+                    format!("already locked by process {}", holder.trim()),
+                ))
+            }
+            Err(std::fs::TryLockError::Error(err)) => Err(err),
+        }
+    }
+}
+
+/// Remove the pid file, then die of `signal` as if we'd never caught it
+extern "C" fn remove_on_signal(signal: libc::c_int) {
+    let path = SIGNAL_PATH.swap(std::ptr::null_mut(), Ordering::SeqCst);
+    unsafe {
+        if !path.is_null() {
+            libc::unlink(path);
+        }
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        SIGNAL_PATH.store(std::ptr::null_mut(), Ordering::SeqCst);
+        // remove it while we still hold the lock, so a new instance can't
+        // have locked the file we're deleting
+        let _ = std::fs::remove_file(&self.path);
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PidFile;
+
+    #[test]
+    fn test_pid_file_excludes_second_instance() {
+        let path = std::env::temp_dir().join(format!("du-by-user-pidfile-{}", std::process::id()));
+        let first = PidFile::create(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        assert!(PidFile::create(&path).is_err());
+        drop(first);
+        assert!(!path.exists());
+        drop(PidFile::create(&path).unwrap());
+    }
+}
@@ -0,0 +1,125 @@
+//! Progress while scanning, always on stderr so that it never ends up mixed
+//! into the report, for `--progress-format`.
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use du_by_user::size::{SizeFormatter, SizeMode};
+
+/// How often the bar and the count are redrawn
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+/// How many files each dot stands for
+const FILES_PER_DOT: u64 = 1000;
+/// Width of the bar's track, which its marker sweeps back and forth across
+/// since there's no telling in advance how much is left
+const BAR_WIDTH: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    Bar,
+    /// One dot per [`FILES_PER_DOT`] files, for logs
+    Dots,
+    /// A running count of files and bytes
+    Count,
+    None,
+}
+
+impl ProgressFormat {
+    pub const NAMES: [&'static str; 4] = ["bar", "dots", "count", "none"];
+
+    /// The bar when stderr is a terminal, and nothing otherwise, unless asked
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        match matches.value_of("progress-format") {
+            Some("bar") => Self::Bar,
+            Some("dots") => Self::Dots,
+            Some("count") => Self::Count,
+            Some(_) => Self::None,
+            None => {
+                use std::io::IsTerminal;
+                if std::io::stderr().is_terminal() {
+                    Self::Bar
+                } else {
+                    Self::None
+                }
+            }
+        }
+    }
+}
+
+/// Files and bytes counted so far in one scan
+#[derive(Debug)]
+pub struct Progress {
+    format: ProgressFormat,
+    files: u64,
+    bytes: u64,
+    frame: usize,
+    drawn: Option<Instant>,
+    formatter: SizeFormatter,
+}
+
+impl Progress {
+    pub fn new(format: ProgressFormat) -> Self {
+        Self {
+            format,
+            files: 0,
+            bytes: 0,
+            frame: 0,
+            drawn: None,
+            formatter: SizeFormatter::new(SizeMode::Human, false),
+        }
+    }
+
+    pub fn file(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+        match self.format {
+            ProgressFormat::None => {}
+            ProgressFormat::Dots => {
+                if self.files.is_multiple_of(FILES_PER_DOT) {
+                    eprint!(".");
+                    self.drawn = Some(Instant::now());
+                }
+            }
+            ProgressFormat::Bar | ProgressFormat::Count => {
+                if self
+                    .drawn
+                    .is_none_or(|drawn| drawn.elapsed() >= REDRAW_INTERVAL)
+                {
+                    self.draw();
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self) {
+        let counts = format!("{} files, {}", self.files, self.formatter.wrap(self.bytes));
+        let line = match self.format {
+            ProgressFormat::Bar => {
+                // 0, 1, ... BAR_WIDTH - 1, then back down again
+                let sweep = 2 * (BAR_WIDTH - 1);
+                let position = self.frame % sweep;
+                let position = position.min(sweep - position);
+                self.frame += 1;
+                let mut track = vec![' '; BAR_WIDTH];
+                track[position] = '=';
+                format!("[{}] {}", track.into_iter().collect::<String>(), counts)
+            }
+            _ => counts,
+        };
+        eprint!("\r\x1b[K{}", line);
+        let _ = std::io::stderr().flush();
+        self.drawn = Some(Instant::now());
+    }
+
+    /// Clear the bar or the count (or end the line of dots), so that what's
+    /// printed next starts on a clean line
+    pub fn finish(&mut self) {
+        if self.drawn.take().is_none() {
+            return;
+        }
+        match self.format {
+            ProgressFormat::Dots => eprintln!(),
+            _ => eprint!("\r\x1b[K"),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Finding out how much of a file shares its data extents with other files
+//! (reflinks, deduplicated extents and snapshots), for `--count-reflinks`.
+
+/// Bytes of `file` that live in extents the filesystem reports as shared
+#[cfg(all(target_os = "linux", feature = "btrfs"))]
+pub fn shared_bytes(file: &std::path::Path, size: u64) -> std::io::Result<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    const FS_IOC_FIEMAP: libc::c_ulong = 0xc020_660b;
+    const FIEMAP_FLAG_SYNC: u32 = 0x1;
+    const FIEMAP_EXTENT_LAST: u32 = 0x1;
+    const FIEMAP_EXTENT_SHARED: u32 = 0x2000;
+    const BATCH: usize = 64;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct Extent {
+        logical: u64,
+        physical: u64,
+        length: u64,
+        reserved64: [u64; 2],
+        flags: u32,
+        reserved: [u32; 3],
+    }
+
+    #[repr(C)]
+    struct Fiemap {
+        start: u64,
+        length: u64,
+        flags: u32,
+        mapped_extents: u32,
+        extent_count: u32,
+        reserved: u32,
+        extents: [Extent; BATCH],
+    }
+
+    let handle = std::fs::File::open(file)?;
+    let mut shared = 0;
+    let mut start = 0;
+    loop {
+        let mut request = Fiemap {
+            start,
+            length: u64::MAX - start,
+            flags: FIEMAP_FLAG_SYNC,
+            mapped_extents: 0,
+            extent_count: BATCH as u32,
+            reserved: 0,
+            extents: [Extent::default(); BATCH],
+        };
+        // SAFETY: `request` is a correctly laid out `struct fiemap` with room
+        // for `extent_count` extents, and outlives the call
+        let rc = unsafe { libc::ioctl(handle.as_raw_fd(), FS_IOC_FIEMAP, &mut request) };
+        if rc < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let extents = &request.extents[..request.mapped_extents as usize];
+        for extent in extents {
+            if extent.flags & FIEMAP_EXTENT_SHARED != 0 {
+                shared += extent.length;
+            }
+        }
+        match extents.last() {
+            Some(last) if last.flags & FIEMAP_EXTENT_LAST == 0 => {
+                start = last.logical + last.length;
+            }
+            _ => break,
+        }
+    }
+    // extents are whole blocks, so the last one can run past the end of the file
+    Ok(shared.min(size))
+}
+
+#[cfg(not(all(target_os = "linux", feature = "btrfs")))]
+pub fn shared_bytes(_file: &std::path::Path, _size: u64) -> std::io::Result<u64> {
+    Ok(0)
+}
@@ -0,0 +1,68 @@
+//! Counting only a random fraction of files, for `--sample`.
+
+/// Decides which files are counted, the same way every time for the same
+/// seed, so that two sampled scans of an unchanged tree agree
+#[derive(Debug)]
+pub struct Sampler {
+    fraction: f64,
+    state: u64,
+}
+
+impl Sampler {
+    /// Keep about `fraction` of files; the scan root's inode makes a good
+    /// `seed`
+    pub fn new(fraction: f64, seed: u64) -> Self {
+        Self {
+            fraction,
+            state: seed,
+        }
+    }
+
+    /// What every counted file stands for, to scale the results back up by
+    pub fn scale(&self) -> f64 {
+        1.0 / self.fraction
+    }
+
+    /// Whether the next file is in the sample
+    pub fn keep(&mut self) -> bool {
+        // the top 53 bits, as a uniform float in [0, 1)
+        let uniform = (self.next() >> 11) as f64 / (1_u64 << 53) as f64;
+        uniform < self.fraction
+    }
+
+    /// splitmix64, which is plenty random for this and needs no dependency
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Parse the `--sample` fraction, which has to be in `(0, 1]`
+pub fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        _ => Err(format!("{:?} is not a fraction between 0 and 1", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_fraction, Sampler};
+
+    #[test]
+    fn test_sample_is_reproducible() {
+        let sample = |seed| {
+            let mut sampler = Sampler::new(0.1, seed);
+            (0..10_000).filter(|_| sampler.keep()).count()
+        };
+        let kept = sample(42);
+        assert_eq!(kept, sample(42));
+        assert!((900..1100).contains(&kept), "{}", kept);
+        assert_eq!(Sampler::new(0.25, 1).scale(), 4.0);
+        assert!(parse_fraction("0").is_err() && parse_fraction("1.5").is_err());
+        assert_eq!(parse_fraction("1"), Ok(1.0));
+    }
+}
@@ -0,0 +1,386 @@
+use num_format::ToFormattedString;
+
+const DIVISORS_SI: [(u64, &str); 4] = [
+    (1_000_000_000_000, "T"),
+    (1_000_000_000, "G"),
+    (1_000_000, "M"),
+    (1_000, "K"),
+];
+const DIVISORS_NON_SI: [(u64, &str); 4] = [
+    (1_099_511_627_776, "T"),
+    (1_073_741_824, "G"),
+    (1_048_576, "M"),
+    (1_024, "K"),
+];
+
+/// The most decimal places [`SizeMode::Human`] shows; more would be below a
+/// byte for every unit, and overflow the arithmetic long before that matters
+pub const MAX_PRECISION: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeMode {
+    Bytes,
+    Kilobytes,
+    Megabytes,
+    Gigabytes,
+    Human,
+    /// Whole blocks of this many bytes, rounded up like `du --block-size`
+    Blocks(u64),
+}
+
+impl SizeMode {
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        if matches.is_present("block-size") {
+            Self::Blocks(
+                matches
+                    .value_of_t_or_exit::<ParsedSize>("block-size")
+                    .bytes(),
+            )
+        } else if matches.is_present("gigabytes") {
+            Self::Gigabytes
+        } else if matches.is_present("megabytes") {
+            Self::Megabytes
+        } else if matches.is_present("kilobytes") {
+            Self::Kilobytes
+        } else if matches.is_present("human") {
+            Self::Human
+        } else {
+            Self::Bytes
+        }
+    }
+}
+
+/// A size in bytes, shown according to the options of a [`SizeFormatter`]
+pub struct FormattedSize<'s> {
+    size: u64,
+    formatter: &'s SizeFormatter,
+}
+
+impl<'s> std::fmt::Display for FormattedSize<'s> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let (base, fraction, unit) = self.formatter.get_parts(self.size);
+        match &self.formatter.locale {
+            Some(locale) => write!(f, "{}", base.to_formatted_string(locale))?,
+            None => write!(f, "{}", base)?,
+        }
+        if let Some(fraction) = fraction {
+            let separator = self.formatter.locale.as_ref().map_or(".", |l| l.decimal());
+            let precision = self.formatter.precision;
+            write!(f, "{}{:0precision$}", separator, fraction)?;
+        }
+        if let Some(unit) = unit {
+            write!(f, "{}", unit)?;
+        }
+        Ok(())
+    }
+}
+
+/// The whole part of a size, the digits after the decimal point (if any), and the unit
+type Parts = (u64, Option<u64>, Option<&'static str>);
+
+#[derive(Debug)]
+pub struct SizeFormatter {
+    mode: SizeMode,
+    si: bool,
+    locale: Option<num_format::Locale>,
+    /// Decimal places shown in [`SizeMode::Human`]
+    precision: usize,
+    /// How many of a unit a size must be before [`SizeMode::Human`] uses it
+    threshold: u64,
+}
+
+/// Pick the number formatting locale from the environment, using the same
+/// precedence as setlocale(3); anything we don't recognize falls back to `en`.
+fn detect_locale() -> num_format::Locale {
+    let name = ["LC_ALL", "LC_NUMERIC", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    // strip the encoding and modifier, as in "de_DE.UTF-8@euro"
+    let name = name.split(['.', '@']).next().unwrap_or_default();
+    let language = name.split('_').next().unwrap_or_default();
+    num_format::Locale::from_name(name)
+        .or_else(|_| num_format::Locale::from_name(language))
+        .unwrap_or(num_format::Locale::en)
+}
+
+impl SizeFormatter {
+    pub fn new(mode: SizeMode, si: bool) -> Self {
+        Self {
+            mode,
+            si,
+            locale: None,
+            precision: 1,
+            threshold: 1,
+        }
+    }
+
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        Self {
+            si: matches.is_present("si"),
+            mode: SizeMode::from_matches(matches),
+            locale: if matches.is_present("locale") {
+                Some(detect_locale())
+            } else {
+                None
+            },
+            precision: matches
+                .value_of_t("output-precision")
+                .unwrap_or(1)
+                .min(MAX_PRECISION),
+            threshold: matches
+                .value_of_t("human-threshold-multiplier")
+                .unwrap_or(1),
+        }
+    }
+
+    fn get_parts_divisor(&self, size: u64, divisor: u64) -> Parts {
+        (size / divisor, None, None)
+    }
+
+    fn get_parts_human(&self, size: u64) -> Parts {
+        let divisors = if self.si {
+            DIVISORS_SI
+        } else {
+            DIVISORS_NON_SI
+        };
+        for (divisor, unit) in divisors {
+            if size >= divisor.saturating_mul(self.threshold) {
+                if self.precision == 0 {
+                    return (size / divisor, None, Some(unit));
+                }
+                // truncated rather than rounded, like the integer output
+                let scale = 10_u128.pow(self.precision as u32);
+                let scaled = size as u128 * scale / divisor as u128;
+                let fraction = (scaled % scale) as u64;
+                return (size / divisor, Some(fraction), Some(unit));
+            }
+        }
+        (size, None, Some("B"))
+    }
+
+    fn get_parts(&self, size: u64) -> Parts {
+        match (&self.mode, self.si) {
+            (SizeMode::Bytes, _) => (size, None, None),
+            (SizeMode::Kilobytes, false) => self.get_parts_divisor(size, 1024),
+            (SizeMode::Kilobytes, true) => self.get_parts_divisor(size, 1000),
+            (SizeMode::Megabytes, false) => self.get_parts_divisor(size, 1048576),
+            (SizeMode::Megabytes, true) => self.get_parts_divisor(size, 1000000),
+            (SizeMode::Gigabytes, false) => self.get_parts_divisor(size, 1073741824),
+            (SizeMode::Gigabytes, true) => self.get_parts_divisor(size, 1000000000),
+            (SizeMode::Human, _) => self.get_parts_human(size),
+            (SizeMode::Blocks(block_size), _) => (size.div_ceil(*block_size), None, None),
+        }
+    }
+
+    pub fn wrap(&self, size: u64) -> FormattedSize<'_> {
+        FormattedSize {
+            size,
+            formatter: self,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseSizeError {
+    Empty,
+    InvalidNumber(String),
+    UnknownSuffix(String),
+    Overflow,
+    /// A size range without the `..` between its bounds
+    NotARange(String),
+}
+
+impl std::fmt::Display for ParseSizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Self::Empty => write!(f, "empty size"),
+            Self::InvalidNumber(n) => write!(f, "invalid number {:?}", n),
+            Self::UnknownSuffix(s) => write!(f, "unknown size suffix {:?}", s),
+            Self::Overflow => write!(f, "size is too large"),
+            Self::NotARange(s) => write!(f, "{:?} is not a range like 1M..100M", s),
+        }
+    }
+}
+
+impl std::error::Error for ParseSizeError {}
+
+/// A byte count parsed from a human-written size such as `4G`, `500M` or `1.5T`.
+///
+/// Bare suffixes (`K`, `M`, `G`, `T`, optionally followed by `iB`) are powers of
+/// 2, matching the default output; suffixes ending in `B` (`KB`, `MB`, ...) are
+/// powers of 10. A bare number (or a `B` suffix) is bytes.
+///
+/// `--si` output uses bare suffixes too, as `du --si` does, so it reads back as
+/// powers of 2 unless a `B` is added to each suffix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ParsedSize(pub u64);
+
+impl ParsedSize {
+    pub fn bytes(&self) -> u64 {
+        self.0
+    }
+}
+
+impl std::str::FromStr for ParsedSize {
+    type Err = ParseSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(ParseSizeError::Empty);
+        }
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (number, suffix) = s.split_at(split);
+        let multiplier: u64 = match suffix.trim_start().to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KIB" => 1 << 10,
+            "M" | "MIB" => 1 << 20,
+            "G" | "GIB" => 1 << 30,
+            "T" | "TIB" => 1 << 40,
+            "KB" => 1_000,
+            "MB" => 1_000_000,
+            "GB" => 1_000_000_000,
+            "TB" => 1_000_000_000_000,
+            _ => return Err(ParseSizeError::UnknownSuffix(suffix.to_owned())),
+        };
+        if let Ok(whole) = number.parse::<u64>() {
+            return whole
+                .checked_mul(multiplier)
+                .map(ParsedSize)
+                .ok_or(ParseSizeError::Overflow);
+        }
+        let fractional = number
+            .parse::<f64>()
+            .map_err(|_| ParseSizeError::InvalidNumber(number.to_owned()))?;
+        let bytes = (fractional * multiplier as f64).round();
+        if bytes >= u64::MAX as f64 {
+            return Err(ParseSizeError::Overflow);
+        }
+        Ok(ParsedSize(bytes as u64))
+    }
+}
+
+/// An inclusive range of sizes written as `MIN..MAX`, where either bound may
+/// be left out (`..100M`, `1M..`); the bounds are parsed like [`ParsedSize`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeRange {
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+impl SizeRange {
+    pub fn contains(&self, size: u64) -> bool {
+        self.min.is_none_or(|min| size >= min) && self.max.is_none_or(|max| size <= max)
+    }
+}
+
+impl std::str::FromStr for SizeRange {
+    type Err = ParseSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min, max) = s
+            .split_once("..")
+            .ok_or_else(|| ParseSizeError::NotARange(s.to_owned()))?;
+        let bound = |bound: &str| match bound.trim() {
+            "" => Ok(None),
+            bound => bound.parse::<ParsedSize>().map(|size| Some(size.bytes())),
+        };
+        Ok(Self {
+            min: bound(min)?,
+            max: bound(max)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ParseSizeError, ParsedSize, SizeFormatter, SizeMode, SizeRange, MAX_PRECISION};
+
+    #[test]
+    fn test_parse_sizes() {
+        assert_eq!("1024".parse(), Ok(ParsedSize(1024)));
+        assert_eq!("4G".parse(), Ok(ParsedSize(4 << 30)));
+        assert_eq!("500MiB".parse(), Ok(ParsedSize(500 << 20)));
+        assert_eq!("500MB".parse(), Ok(ParsedSize(500_000_000)));
+        assert_eq!("1.5T".parse(), Ok(ParsedSize(3 << 39)));
+        assert_eq!("12 k".parse(), Ok(ParsedSize(12 << 10)));
+        assert_eq!(
+            "3X".parse::<ParsedSize>(),
+            Err(ParseSizeError::UnknownSuffix("X".to_owned()))
+        );
+        assert_eq!("".parse::<ParsedSize>(), Err(ParseSizeError::Empty));
+        let range = "1M..100M".parse::<SizeRange>().unwrap();
+        assert_eq!((range.min, range.max), (Some(1 << 20), Some(100 << 20)));
+        assert!(range.contains(1 << 20) && !range.contains((100 << 20) + 1));
+        assert_eq!(
+            "..4K".parse(),
+            Ok(SizeRange {
+                min: None,
+                max: Some(4096)
+            })
+        );
+        assert_eq!(
+            "4K".parse::<SizeRange>(),
+            Err(ParseSizeError::NotARange("4K".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_block_size_rounds_up() {
+        let formatter = SizeFormatter::new(SizeMode::Blocks(4096), false);
+        assert_eq!(formatter.wrap(0).to_string(), "0");
+        assert_eq!(formatter.wrap(1).to_string(), "1");
+        assert_eq!(formatter.wrap(4096).to_string(), "1");
+        assert_eq!(formatter.wrap(4097).to_string(), "2");
+    }
+
+    #[test]
+    fn test_human_round_trip() {
+        let formatter = SizeFormatter::new(SizeMode::Human, false);
+        for size in [5_u64 << 20, 42 << 30, 7 << 40] {
+            let formatted = formatter.wrap(size).to_string();
+            assert_eq!(formatted.parse(), Ok(ParsedSize(size)));
+        }
+        let si = SizeFormatter::new(SizeMode::Human, true);
+        let formatted = si.wrap(5_000_000).to_string();
+        assert_eq!(formatted, "5.0M");
+        assert_eq!(formatted.parse(), Ok(ParsedSize(5 << 20)));
+        assert_eq!(format!("{}B", formatted).parse(), Ok(ParsedSize(5_000_000)));
+    }
+
+    #[test]
+    fn test_human_precision() {
+        let size = 45_850_000_000;
+        let formatter = SizeFormatter::new(SizeMode::Human, false);
+        assert_eq!(formatter.wrap(size).to_string(), "42.7G");
+        assert_eq!(formatter.wrap(5009).to_string(), "4.8K");
+        assert_eq!(formatter.wrap(1024).to_string(), "1.0K");
+        assert_eq!(formatter.wrap(1023).to_string(), "1023B");
+        let old = SizeFormatter {
+            threshold: 10,
+            ..SizeFormatter::new(SizeMode::Human, false)
+        };
+        assert_eq!(old.wrap(5009).to_string(), "5009B");
+        assert_eq!(old.wrap(10240).to_string(), "10.0K");
+        let formatter = SizeFormatter {
+            precision: 2,
+            ..formatter
+        };
+        assert_eq!(formatter.wrap(size).to_string(), "42.70G");
+        let formatter = SizeFormatter {
+            precision: 0,
+            ..formatter
+        };
+        assert_eq!(formatter.wrap(size).to_string(), "42G");
+        let formatter = SizeFormatter {
+            precision: MAX_PRECISION,
+            ..formatter
+        };
+        assert_eq!(formatter.wrap(size).to_string(), "42.701140046G");
+        assert_eq!(formatter.wrap(u64::MAX).to_string(), "16777215.999999999T");
+    }
+}
@@ -0,0 +1,343 @@
+//! `--snapshot` and `--restore`: saving everything a scan found, so it can be
+//! reported on again (in any format) without walking the tree a second time.
+
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use du_by_user::id::{GroupId, UserId};
+use du_by_user::time::YearMonth;
+
+use crate::duplicates::DuplicateGroup;
+use crate::linkgroups::LinkGroup;
+use crate::{Profile, Scan, SkippedEntry, TopFiles, UserStats};
+
+/// Bumped whenever a snapshot written by one version couldn't be restored by
+/// another
+const SNAPSHOT_VERSION: u64 = 1;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Snapshot {
+    snapshot_version: u64,
+    scans: Vec<SavedScan>,
+}
+
+/// One scan root's results; maps are stored as lists, since JSON object keys
+/// have to be strings
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct SavedScan {
+    path: PathBuf,
+    started: SystemTime,
+    elapsed: Duration,
+    timed_out: bool,
+    #[serde(default)]
+    sampled: Option<f64>,
+    dirs: u64,
+    atime_differs: bool,
+    by_user: Vec<(UserId, UserStats)>,
+    by_month: Vec<(UserId, YearMonth, u64)>,
+    by_toplevel: Vec<(PathBuf, u64)>,
+    #[serde(default)]
+    by_extension: Vec<(String, u64, u64)>,
+    by_project: Vec<(Option<String>, UserId, u64)>,
+    #[serde(default)]
+    by_gid: Vec<(UserId, GroupId, u64, u64)>,
+    #[serde(default)]
+    by_dir_pair: Vec<(PathBuf, [u64; 2])>,
+    top_files: Option<Vec<(u64, PathBuf, UserId)>>,
+    /// The inode of each of `top_files`, in the same order; snapshots from
+    /// before this was recorded have none
+    #[serde(default)]
+    top_file_inodes: Vec<u64>,
+    #[serde(default)]
+    top_dirs: Vec<(u64, PathBuf)>,
+    setuid_files: Vec<(u32, UserId, PathBuf)>,
+    #[serde(default)]
+    world_writable: Vec<(u32, UserId, PathBuf)>,
+    #[serde(default)]
+    rename_hazards: Vec<(UserId, PathBuf)>,
+    #[serde(default)]
+    long_paths: Vec<(UserId, PathBuf)>,
+    #[serde(default)]
+    sparse_files: Vec<(UserId, u64, u64, PathBuf)>,
+    #[serde(default)]
+    sticky_dirs: Vec<(PathBuf, Vec<(UserId, u64)>)>,
+    duplicates: Vec<DuplicateGroup>,
+    link_groups: Vec<LinkGroup>,
+    /// `(device, inode, owner)` of every file, for `--show-owner-changes`;
+    /// snapshots from before this was recorded have none
+    #[serde(default)]
+    inodes: Vec<(u64, u64, UserId)>,
+    /// What was skipped, and why
+    skipped: Vec<(PathBuf, String)>,
+}
+
+impl SavedScan {
+    /// A copy of `scan`, which is still needed for its own report
+    pub fn new(path: &Path, scan: &Scan) -> Self {
+        Self {
+            path: path.to_owned(),
+            started: scan.started,
+            elapsed: scan.elapsed,
+            timed_out: scan.timed_out,
+            sampled: scan.sampled,
+            dirs: scan.dirs,
+            atime_differs: scan.atime_differs,
+            by_user: scan
+                .by_user
+                .iter()
+                .map(|(&user_id, stats)| (user_id, stats.clone()))
+                .collect(),
+            by_month: scan
+                .by_month
+                .iter()
+                .map(|(&(user_id, month), &bytes)| (user_id, month, bytes))
+                .collect(),
+            by_toplevel: scan
+                .by_toplevel
+                .iter()
+                .map(|(path, &bytes)| (path.clone(), bytes))
+                .collect(),
+            by_extension: scan
+                .by_extension
+                .iter()
+                .map(|(extension, &(bytes, files))| (extension.clone(), bytes, files))
+                .collect(),
+            by_project: scan
+                .by_project
+                .iter()
+                .map(|((project, user_id), &bytes)| (project.clone(), *user_id, bytes))
+                .collect(),
+            by_gid: scan
+                .by_gid
+                .iter()
+                .map(|(&(user_id, group_id), &(bytes, files))| (user_id, group_id, bytes, files))
+                .collect(),
+            by_dir_pair: scan
+                .by_dir_pair
+                .iter()
+                .map(|(dir, &bytes)| (dir.clone(), bytes))
+                .collect(),
+            top_files: scan.top_files.as_ref().map(|top| {
+                top.heap
+                    .iter()
+                    .map(|std::cmp::Reverse((size, path, user_id, _))| {
+                        (*size, path.clone(), *user_id)
+                    })
+                    .collect()
+            }),
+            top_file_inodes: scan.top_files.as_ref().map_or(Vec::new(), |top| {
+                top.heap
+                    .iter()
+                    .map(|std::cmp::Reverse((.., inode))| *inode)
+                    .collect()
+            }),
+            top_dirs: scan.top_dirs.clone(),
+            setuid_files: scan.setuid_files.clone(),
+            world_writable: scan.world_writable.clone(),
+            rename_hazards: scan.rename_hazards.clone(),
+            long_paths: scan.long_paths.clone(),
+            sparse_files: scan.sparse_files.clone(),
+            sticky_dirs: scan
+                .sticky_dirs
+                .iter()
+                .map(|(dir, usage)| {
+                    let usage = usage.iter().map(|(&user_id, &bytes)| (user_id, bytes));
+                    (dir.clone(), usage.collect())
+                })
+                .collect(),
+            duplicates: scan.duplicates.clone(),
+            link_groups: scan.link_groups.clone(),
+            inodes: scan.inodes.clone(),
+            skipped: scan
+                .skipped
+                .iter()
+                .map(|entry| (entry.path.clone(), entry.reason.to_string()))
+                .collect(),
+        }
+    }
+
+    /// The scan root, and what was found there. `top_files` is how many of
+    /// the saved largest files to keep, if they should be shown at all.
+    fn into_scan(self, top_files: Option<usize>) -> (PathBuf, Scan) {
+        let top_files = top_files.map(|limit| {
+            let mut top = TopFiles::new(limit);
+            let inodes = self.top_file_inodes.into_iter().chain(std::iter::repeat(0));
+            for ((size, path, user_id), inode) in self.top_files.into_iter().flatten().zip(inodes) {
+                top.push(size, &path, user_id, inode);
+            }
+            top
+        });
+        let scan = Scan {
+            by_user: self.by_user.into_iter().collect(),
+            by_month: self
+                .by_month
+                .into_iter()
+                .map(|(user_id, month, bytes)| ((user_id, month), bytes))
+                .collect(),
+            by_toplevel: self.by_toplevel.into_iter().collect(),
+            by_extension: self
+                .by_extension
+                .into_iter()
+                .map(|(extension, bytes, files)| (extension, (bytes, files)))
+                .collect(),
+            by_project: self
+                .by_project
+                .into_iter()
+                .map(|(project, user_id, bytes)| ((project, user_id), bytes))
+                .collect(),
+            by_gid: self
+                .by_gid
+                .into_iter()
+                .map(|(user_id, group_id, bytes, files)| ((user_id, group_id), (bytes, files)))
+                .collect(),
+            by_dir_pair: self.by_dir_pair.into_iter().collect(),
+            top_files,
+            top_dirs: self.top_dirs,
+            setuid_files: self.setuid_files,
+            world_writable: self.world_writable,
+            rename_hazards: self.rename_hazards,
+            long_paths: self.long_paths,
+            sparse_files: self.sparse_files,
+            sticky_dirs: self
+                .sticky_dirs
+                .into_iter()
+                .map(|(dir, usage)| (dir, usage.into_iter().collect()))
+                .collect(),
+            duplicates: self.duplicates,
+            link_groups: self.link_groups,
+            inodes: self.inodes,
+            skipped: self
+                .skipped
+                .into_iter()
+                .map(|(path, reason)| SkippedEntry {
+                    path,
+                    reason: std::io::Error::other(reason),
+                })
+                .collect(),
+            started: self.started,
+            elapsed: self.elapsed,
+            timed_out: self.timed_out,
+            sampled: self.sampled,
+            dirs: self.dirs,
+            atime_differs: self.atime_differs,
+            profile: Profile::default(),
+        };
+        (self.path, scan)
+    }
+}
+
+/// Write `scans` to `path`, replacing whatever was there
+pub fn save(path: &Path, scans: Vec<SavedScan>) -> std::io::Result<()> {
+    let snapshot = Snapshot {
+        snapshot_version: SNAPSHOT_VERSION,
+        scans,
+    };
+    let mut out = BufWriter::new(std::fs::File::create(path)?);
+    serde_json::to_writer(&mut out, &snapshot)?;
+    writeln!(out)?;
+    out.flush()
+}
+
+fn read(path: &Path) -> std::io::Result<Snapshot> {
+    let reader = BufReader::new(std::fs::File::open(path)?);
+    let snapshot: Snapshot = serde_json::from_reader(reader)?;
+    if snapshot.snapshot_version != SNAPSHOT_VERSION {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "{} has snapshot_version {}, but only version {} can be restored",
+                path.display(),
+                snapshot.snapshot_version,
+                SNAPSHOT_VERSION
+            ),
+        ));
+    }
+    Ok(snapshot)
+}
+
+/// Every scan root saved in the snapshot at `path`, in the order they were
+/// scanned; see [`SavedScan::into_scan`] for `top_files`
+pub fn load(path: &Path, top_files: Option<usize>) -> std::io::Result<Vec<(PathBuf, Scan)>> {
+    Ok(read(path)?
+        .scans
+        .into_iter()
+        .map(|scan| scan.into_scan(top_files))
+        .collect())
+}
+
+/// The owner of every file in the snapshot at `path`, by `(device, inode)`,
+/// across all of its scan roots
+pub fn load_inodes(path: &Path) -> std::io::Result<HashMap<(u64, u64), UserId>> {
+    let snapshot = read(path)?;
+    if snapshot.scans.iter().all(|scan| scan.inodes.is_empty()) {
+        log::warn!("{} has no inodes recorded", path.display());
+    }
+    Ok(snapshot
+        .scans
+        .into_iter()
+        .flat_map(|scan| scan.inodes)
+        .map(|(dev, ino, user_id)| ((dev, ino), user_id))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, load_inodes, save, SavedScan};
+    use crate::{Profile, Scan, UserStats};
+    use du_by_user::id::UserId;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_round_trip() {
+        let stats = UserStats {
+            bytes: 100,
+            files: 2,
+            sizes: vec![40, 60],
+            ..UserStats::default()
+        };
+        let scan = Scan {
+            by_user: HashMap::from([(UserId(1000), stats)]),
+            by_month: HashMap::new(),
+            by_toplevel: HashMap::from([("/srv/a".into(), 100)]),
+            by_extension: HashMap::from([("iso".to_owned(), (100, 1))]),
+            by_project: HashMap::new(),
+            by_gid: HashMap::new(),
+            by_dir_pair: HashMap::new(),
+            top_files: None,
+            top_dirs: Vec::new(),
+            setuid_files: Vec::new(),
+            world_writable: Vec::new(),
+            rename_hazards: Vec::new(),
+            long_paths: Vec::new(),
+            sparse_files: Vec::new(),
+            sticky_dirs: HashMap::new(),
+            duplicates: Vec::new(),
+            link_groups: Vec::new(),
+            inodes: vec![(1, 42, UserId(1000))],
+            skipped: Vec::new(),
+            started: std::time::SystemTime::UNIX_EPOCH,
+            elapsed: std::time::Duration::from_secs(1),
+            timed_out: false,
+            sampled: None,
+            dirs: 3,
+            atime_differs: true,
+            profile: Profile::default(),
+        };
+        let path = std::env::temp_dir().join(format!("du-by-user-snapshot-{}", std::process::id()));
+        save(&path, vec![SavedScan::new("/srv".as_ref(), &scan)]).unwrap();
+        let mut restored = load(&path, None).unwrap();
+        let inodes = load_inodes(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.len(), 1);
+        let (root, restored) = restored.remove(0);
+        assert_eq!(root, std::path::Path::new("/srv"));
+        assert_eq!(restored.by_user[&UserId(1000)].bytes, 100);
+        assert_eq!(restored.by_user[&UserId(1000)].sizes, vec![40, 60]);
+        assert_eq!(restored.by_toplevel, scan.by_toplevel);
+        assert_eq!(restored.by_extension, scan.by_extension);
+        assert_eq!((restored.dirs, restored.atime_differs), (3, true));
+        assert_eq!(inodes, HashMap::from([((1, 42), UserId(1000))]));
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Datelike, SecondsFormat, Utc};
+
+/// A calendar month (in UTC), ordered chronologically
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub struct YearMonth {
+    pub year: i32,
+    pub month: u32,
+}
+
+impl From<SystemTime> for YearMonth {
+    fn from(time: SystemTime) -> Self {
+        let datetime: DateTime<Utc> = time.into();
+        Self {
+            year: datetime.year(),
+            month: datetime.month(),
+        }
+    }
+}
+
+impl std::fmt::Display for YearMonth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{:04}-{:02}", self.year, self.month)
+    }
+}
+
+/// How timestamps are shown in reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    Iso8601,
+    Unix,
+    Rfc2822,
+    /// Relative to the start of the scan, like `3 days ago`
+    Relative,
+}
+
+impl TimeFormat {
+    pub const NAMES: [&'static str; 4] = ["iso8601", "unix", "rfc2822", "relative"];
+
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        match matches.value_of("time-format") {
+            Some("unix") => Self::Unix,
+            Some("rfc2822") => Self::Rfc2822,
+            Some("relative") => Self::Relative,
+            _ => Self::Iso8601,
+        }
+    }
+
+    /// Format `time`; `now` is only used by [`TimeFormat::Relative`]
+    pub fn format(&self, time: SystemTime, now: SystemTime) -> String {
+        let datetime: DateTime<Utc> = time.into();
+        match self {
+            Self::Iso8601 => datetime.to_rfc3339_opts(SecondsFormat::Secs, true),
+            Self::Unix => datetime.timestamp().to_string(),
+            Self::Rfc2822 => datetime.to_rfc2822(),
+            Self::Relative => match now.duration_since(time) {
+                Ok(ago) => format!("{} ago", describe_duration(ago)),
+                Err(e) => format!("in {}", describe_duration(e.duration())),
+            },
+        }
+    }
+}
+
+fn describe_duration(duration: Duration) -> String {
+    const UNITS: [(u64, &str); 5] = [
+        (365 * 86400, "year"),
+        (7 * 86400, "week"),
+        (86400, "day"),
+        (3600, "hour"),
+        (60, "minute"),
+    ];
+    let seconds = duration.as_secs();
+    let (count, unit) = UNITS
+        .iter()
+        .find(|(length, _)| seconds >= *length)
+        .map(|(length, unit)| (seconds / length, *unit))
+        .unwrap_or((seconds, "second"));
+    if count == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", count, unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TimeFormat, YearMonth};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_formats() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let now = time + Duration::from_secs(3 * 86400 + 5);
+        assert_eq!(
+            TimeFormat::Iso8601.format(time, now),
+            "2023-11-14T22:13:20Z"
+        );
+        assert_eq!(TimeFormat::Unix.format(time, now), "1700000000");
+        assert_eq!(
+            TimeFormat::Rfc2822.format(time, now),
+            "Tue, 14 Nov 2023 22:13:20 +0000"
+        );
+        assert_eq!(TimeFormat::Relative.format(time, now), "3 days ago");
+        assert_eq!(TimeFormat::Relative.format(now, time), "in 3 days");
+        assert_eq!(YearMonth::from(time).to_string(), "2023-11");
+    }
+}
@@ -0,0 +1,336 @@
+//! Walking the tree under a scan root, depth-first with `walkdir` or
+//! breadth-first, for `--walk-order`.
+
+use std::collections::VecDeque;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::SkippedEntry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkOrder {
+    /// Each directory's whole subtree before its next sibling
+    DepthFirst,
+    /// Everything at one depth before anything deeper
+    BreadthFirst,
+}
+
+impl WalkOrder {
+    pub const NAMES: [&'static str; 2] = ["dfs", "bfs"];
+
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        match matches.value_of("walk-order") {
+            Some("bfs") => Self::BreadthFirst,
+            _ => Self::DepthFirst,
+        }
+    }
+}
+
+/// Something found during a walk; the same information either order has
+#[derive(Debug)]
+pub struct Entry {
+    path: PathBuf,
+    depth: usize,
+    is_symlink: bool,
+    follow_links: bool,
+}
+
+impl Entry {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// How far below the scan root this is; the root itself is 0
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn path_is_symlink(&self) -> bool {
+        self.is_symlink
+    }
+
+    /// What this is, or when following links, what it points to
+    pub fn metadata(&self) -> std::io::Result<std::fs::Metadata> {
+        if self.follow_links {
+            std::fs::metadata(&self.path)
+        } else {
+            std::fs::symlink_metadata(&self.path)
+        }
+    }
+}
+
+/// Options shared by both walk orders
+#[derive(Debug, Clone, Copy)]
+pub struct WalkOptions {
+    pub order: WalkOrder,
+    pub follow_links: bool,
+    pub same_file_system: bool,
+    /// With `same_file_system`, also descend into bind mounts of the root's
+    /// filesystem
+    pub bind_mounts: bool,
+}
+
+/// The devices that count as the root's filesystem for `same_file_system`,
+/// or `None` when every device does
+fn root_devices(device: u64, options: WalkOptions) -> Option<Vec<u64>> {
+    if !options.same_file_system {
+        return None;
+    }
+    if options.bind_mounts {
+        match crate::mounts::same_source_devices(device) {
+            Ok(devices) => return Some(devices),
+            Err(err) => log::warn!("could not read mounts: {}", err),
+        }
+    }
+    Some(vec![device])
+}
+
+/// Everything under `root` (and `root` itself), leaving out anything for
+/// which `skip` is true along with everything under it
+pub fn walk<'a, F>(
+    root: &Path,
+    options: WalkOptions,
+    skip: F,
+) -> Box<dyn Iterator<Item = Result<Entry, SkippedEntry>> + 'a>
+where
+    F: Fn(&Path) -> bool + 'a,
+{
+    let follow_links = options.follow_links;
+    match options.order {
+        WalkOrder::DepthFirst => {
+            // walkdir's own same_file_system doesn't mix with filter_entry:
+            // skipping a directory it declined to descend into skips the
+            // rest of the parent instead, so mount points are filtered here
+            // (and, as with breadth-first, left out themselves)
+            let root_devices = std::fs::metadata(root)
+                .ok()
+                .and_then(|m| root_devices(m.dev(), options));
+            let other_device = move |entry: &walkdir::DirEntry| {
+                root_devices.as_ref().is_some_and(|devices| {
+                    entry.depth() > 0
+                        && entry.file_type().is_dir()
+                        && !entry.metadata().is_ok_and(|m| devices.contains(&m.dev()))
+                })
+            };
+            Box::new(
+                walkdir::WalkDir::new(root)
+                    .follow_links(follow_links)
+                    .into_iter()
+                    .filter_entry(move |entry| !skip(entry.path()) && !other_device(entry))
+                    .map(move |entry| {
+                        let entry = entry?;
+                        Ok(Entry {
+                            depth: entry.depth(),
+                            is_symlink: entry.path_is_symlink(),
+                            follow_links,
+                            path: entry.into_path(),
+                        })
+                    }),
+            )
+        }
+        WalkOrder::BreadthFirst => Box::new(BreadthFirst::new(root, options, skip)),
+    }
+}
+
+/// A directory being walked, and the ones it's inside of, for noticing when
+/// a followed link leads back up the tree
+#[derive(Debug)]
+struct Ancestor {
+    path: PathBuf,
+    id: (u64, u64),
+    parent: Option<Rc<Ancestor>>,
+}
+
+impl Ancestor {
+    fn find(self: &Rc<Self>, id: (u64, u64)) -> Option<&Path> {
+        let mut ancestor = Some(self);
+        while let Some(current) = ancestor {
+            if current.id == id {
+                return Some(&current.path);
+            }
+            ancestor = current.parent.as_ref();
+        }
+        None
+    }
+}
+
+struct BreadthFirst<F> {
+    options: WalkOptions,
+    skip: F,
+    /// Directories still to be read
+    queue: VecDeque<(Rc<Ancestor>, usize)>,
+    /// What has been read but not handed out yet
+    pending: VecDeque<Result<Entry, SkippedEntry>>,
+    root_devices: Option<Vec<u64>>,
+}
+
+impl<F: Fn(&Path) -> bool> BreadthFirst<F> {
+    fn new(root: &Path, options: WalkOptions, skip: F) -> Self {
+        let mut walk = Self {
+            options,
+            skip,
+            queue: VecDeque::new(),
+            pending: VecDeque::new(),
+            root_devices: None,
+        };
+        // like walkdir, the root is always followed if it's a link
+        match std::fs::metadata(root) {
+            Ok(metadata) => {
+                walk.root_devices = root_devices(metadata.dev(), options);
+                if metadata.is_dir() {
+                    let root = Ancestor {
+                        path: root.to_owned(),
+                        id: (metadata.dev(), metadata.ino()),
+                        parent: None,
+                    };
+                    walk.queue.push_back((Rc::new(root), 0));
+                }
+                walk.pending.push_back(Ok(Entry {
+                    path: root.to_owned(),
+                    depth: 0,
+                    is_symlink: std::fs::symlink_metadata(root)
+                        .is_ok_and(|m| m.file_type().is_symlink()),
+                    follow_links: options.follow_links,
+                }));
+            }
+            Err(reason) => walk.pending.push_back(Err(SkippedEntry {
+                path: root.to_owned(),
+                reason,
+            })),
+        }
+        walk
+    }
+
+    /// Queue the contents of `dir`, and any directories among them
+    fn read(&mut self, dir: &Rc<Ancestor>, depth: usize) {
+        let children = match std::fs::read_dir(&dir.path) {
+            Ok(children) => children,
+            Err(reason) => {
+                self.pending.push_back(Err(SkippedEntry {
+                    path: dir.path.clone(),
+                    reason,
+                }));
+                return;
+            }
+        };
+        for child in children {
+            let child = match child {
+                Ok(child) => child,
+                Err(reason) => {
+                    self.pending.push_back(Err(SkippedEntry {
+                        path: dir.path.clone(),
+                        reason,
+                    }));
+                    continue;
+                }
+            };
+            let path = child.path();
+            if (self.skip)(&path) {
+                continue;
+            }
+            let file_type = child.file_type().ok();
+            let is_symlink = file_type.is_some_and(|t| t.is_symlink());
+            let is_dir = if is_symlink {
+                self.options.follow_links && path.is_dir()
+            } else {
+                file_type.is_some_and(|t| t.is_dir())
+            };
+            if is_dir {
+                // the directory's own metadata, which walking an ordinary
+                // tree in ordinary order can do without
+                let metadata = match std::fs::metadata(&path) {
+                    Ok(metadata) => metadata,
+                    Err(reason) => {
+                        self.pending.push_back(Err(SkippedEntry { path, reason }));
+                        continue;
+                    }
+                };
+                let id = (metadata.dev(), metadata.ino());
+                if let Some(ancestor) = dir.find(id).filter(|_| self.options.follow_links) {
+                    self.pending.push_back(Err(SkippedEntry {
+                        reason: std::io::Error::other(format!(
+                            "File system loop found: {} points to an ancestor {}",
+                            path.display(),
+                            ancestor.display()
+                        )),
+                        path,
+                    }));
+                    continue;
+                }
+                if self
+                    .root_devices
+                    .as_ref()
+                    .is_some_and(|devices| !devices.contains(&metadata.dev()))
+                {
+                    continue;
+                }
+                let child = Ancestor {
+                    path: path.clone(),
+                    id,
+                    parent: Some(Rc::clone(dir)),
+                };
+                self.queue.push_back((Rc::new(child), depth + 1));
+            }
+            self.pending.push_back(Ok(Entry {
+                path,
+                depth: depth + 1,
+                is_symlink,
+                follow_links: self.options.follow_links,
+            }));
+        }
+    }
+}
+
+impl<F: Fn(&Path) -> bool> Iterator for BreadthFirst<F> {
+    type Item = Result<Entry, SkippedEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.pending.pop_front() {
+                return Some(entry);
+            }
+            let (dir, depth) = self.queue.pop_front()?;
+            self.read(&dir, depth);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cli, scan, ScanOptions};
+
+    #[test]
+    fn test_orders_agree() {
+        let root = std::env::temp_dir().join(format!("du-by-user-walk-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("sub/deeper")).unwrap();
+        std::fs::write(root.join("a"), [0; 10]).unwrap();
+        std::fs::write(root.join("sub/b"), [0; 20]).unwrap();
+        std::fs::write(root.join("sub/deeper/c"), [0; 30]).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("sub/loop")).unwrap();
+        for follow in [false, true] {
+            let totals = |order: &str| {
+                let mut args = vec!["du-by-user", "--walk-order", order];
+                if follow {
+                    args.push("--follow-links");
+                }
+                args.push(root.to_str().unwrap());
+                let scan = scan(
+                    &root,
+                    &ScanOptions::from_matches(&cli().get_matches_from(args)),
+                );
+                let mut by_user = scan
+                    .by_user
+                    .into_iter()
+                    .map(|(user_id, stats)| (user_id, stats.bytes, stats.files))
+                    .collect::<Vec<_>>();
+                by_user.sort();
+                (by_user, scan.dirs, scan.skipped.len())
+            };
+            let (dfs, bfs) = (totals("dfs"), totals("bfs"));
+            assert_eq!(dfs, bfs, "following links: {}", follow);
+            assert_eq!(dfs.0.iter().map(|&(_, bytes, _)| bytes).sum::<u64>(), 60);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}